@@ -0,0 +1,78 @@
+//! Times each link phase over a synthetic workload: `cargo bench --bench link_phases`.
+//!
+//! The linker logs every section, symbol and relocation to stdout, so that output is
+//! part of what is measured; redirect it to keep the report readable.
+
+mod synthetic;
+
+use criterion::{BatchSize, Criterion, criterion_group, criterion_main};
+use elkr::linker::LinkerContext;
+use synthetic::Workload;
+
+const WORKLOAD: Workload = Workload {
+    files: 64,
+    sections: 16,
+    relocations: 8,
+};
+
+fn add_files(objects: &[Vec<u8>]) -> LinkerContext<'_> {
+    let mut linker = LinkerContext::default();
+    for (i, object) in objects.iter().enumerate() {
+        linker.add_file(format!("{i}.o"), object).unwrap();
+    }
+    linker
+}
+
+fn laid_out(objects: &[Vec<u8>]) -> LinkerContext<'_> {
+    let mut linker = add_files(objects);
+    linker.layout_and_merge_sections().unwrap();
+    linker
+}
+
+fn resolved(objects: &[Vec<u8>]) -> LinkerContext<'_> {
+    let mut linker = laid_out(objects);
+    linker.resolve_symbols().unwrap();
+    linker
+}
+
+fn link_phases(c: &mut Criterion) {
+    let objects = WORKLOAD.objects();
+    let mut group = c.benchmark_group("link_phases");
+    group.bench_function("add_file", |b| b.iter(|| add_files(&objects)));
+    // Each phase runs on a fresh link that went through the ones before it, and the
+    // linker is dropped outside the measurement
+    group.bench_function("layout", |b| {
+        b.iter_batched(
+            || add_files(&objects),
+            |mut linker| {
+                linker.layout_and_merge_sections().unwrap();
+                linker
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    group.bench_function("resolve", |b| {
+        b.iter_batched(
+            || laid_out(&objects),
+            |mut linker| {
+                linker.resolve_symbols().unwrap();
+                linker
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    group.bench_function("relocate", |b| {
+        b.iter_batched(
+            || resolved(&objects),
+            |mut linker| {
+                linker.apply_relocations().unwrap();
+                linker
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+}
+
+criterion_group!(benches, link_phases);
+criterion_main!(benches);
@@ -0,0 +1,225 @@
+//! Synthetic relocatable objects for the benchmarks: functions in many sections of many
+//! files, calling each other across files.
+
+use std::collections::HashMap;
+
+use elkr::elf::{
+    header::{EI_CLASS_64, EI_DATA_2LSB, EM_AARCH64, ET_REL},
+    relocation::R_AARCH64_CALL26,
+    section::{
+        SHF_ALLOC, SHF_EXECINSTR, SHF_INFO_LINK, SHT_PROGBITS, SHT_RELA, SHT_STRTAB, SHT_SYMTAB,
+        SectionHeader,
+    },
+    symbol::{STB_GLOBAL, STT_FUNC, Symbol},
+};
+
+const BL: u32 = 0x9400_0000;
+const RET: u32 = 0xd65f_03c0;
+
+/// How much there is to link.
+pub struct Workload {
+    pub files: usize,
+    /// Code sections per file, each holding one function
+    pub sections: usize,
+    /// Calls per function, each with a `R_AARCH64_CALL26` to a function in another file
+    pub relocations: usize,
+}
+
+impl Workload {
+    /// The objects `0.o`, `1.o`, ...; the first one defines `_start`.
+    pub fn objects(&self) -> Vec<Vec<u8>> {
+        (0..self.files).map(|file| self.object(file)).collect()
+    }
+
+    fn object(&self, file: usize) -> Vec<u8> {
+        let mut object = ObjectWriter::default();
+        let mut symbols = SymbolTable::default();
+        let text: Vec<u16> = (0..self.sections)
+            .map(|section| {
+                let mut code = Vec::new();
+                for _ in 0..self.relocations {
+                    code.extend_from_slice(&BL.to_le_bytes());
+                }
+                code.extend_from_slice(&RET.to_le_bytes());
+                let flags = SHF_ALLOC | SHF_EXECINSTR;
+                let index = object.section(&format!(".text.f{section}"), SHT_PROGBITS, flags, 4);
+                object.data[index as usize] = code;
+                symbols.define(&format!("f{file}_{section}"), index, 4);
+                index
+            })
+            .collect();
+        if file == 0 {
+            symbols.define("_start", text[0], 4);
+        }
+
+        for (section, &index) in text.iter().enumerate() {
+            let mut rela = Vec::new();
+            for call in 0..self.relocations {
+                let target = format!(
+                    "f{}_{}",
+                    (file + 1 + call) % self.files,
+                    (section + call) % self.sections
+                );
+                let symbol = symbols.reference(&target);
+                rela.extend_from_slice(&(call as u64 * 4).to_le_bytes());
+                rela.extend_from_slice(
+                    &((u64::from(symbol) << 32) | u64::from(R_AARCH64_CALL26)).to_le_bytes(),
+                );
+                rela.extend_from_slice(&0i64.to_le_bytes());
+            }
+            let name = format!(".rela.text.f{section}");
+            let rela_index = object.section(&name, SHT_RELA, SHF_INFO_LINK, 8);
+            object.headers[rela_index as usize].info = u32::from(index);
+            object.headers[rela_index as usize].entsize = 24;
+            object.data[rela_index as usize] = rela;
+        }
+
+        let symtab = object.section(".symtab", SHT_SYMTAB, 0, 8);
+        let strtab = object.section(".strtab", SHT_STRTAB, 0, 1);
+        for header in &mut object.headers {
+            if header.sh_type == SHT_RELA {
+                header.link = u32::from(symtab);
+            }
+        }
+        // Only the null symbol is local
+        object.headers[symtab as usize].link = u32::from(strtab);
+        object.headers[symtab as usize].info = 1;
+        object.headers[symtab as usize].entsize = 24;
+        object.data[symtab as usize] = symbols.entries;
+        object.data[strtab as usize] = symbols.names;
+        object.finish()
+    }
+}
+
+/// Global symbols, defined or referenced, after the null symbol.
+struct SymbolTable {
+    entries: Vec<u8>,
+    names: Vec<u8>,
+    indices: HashMap<String, u32>,
+}
+
+impl Default for SymbolTable {
+    fn default() -> Self {
+        SymbolTable {
+            entries: vec![0; 24],
+            names: vec![0],
+            indices: HashMap::new(),
+        }
+    }
+}
+
+impl SymbolTable {
+    fn define(&mut self, name: &str, shndx: u16, size: u64) {
+        self.add(name, shndx, size);
+    }
+
+    /// The index of `name`, added as an undefined symbol if it isn't in the table yet.
+    fn reference(&mut self, name: &str) -> u32 {
+        match self.indices.get(name) {
+            Some(&index) => index,
+            None => self.add(name, 0, 0),
+        }
+    }
+
+    fn add(&mut self, name: &str, shndx: u16, size: u64) -> u32 {
+        let index = (self.entries.len() / 24) as u32;
+        let symbol = Symbol {
+            name_offset: self.names.len() as u32,
+            info: (STB_GLOBAL << 4) | if shndx == 0 { 0 } else { STT_FUNC },
+            other: 0,
+            shndx,
+            value: 0,
+            size,
+        };
+        symbol.encode(&mut self.entries);
+        self.names.extend_from_slice(name.as_bytes());
+        self.names.push(0);
+        self.indices.insert(name.to_string(), index);
+        index
+    }
+}
+
+/// Section headers and contents, written out as an ELF64 relocatable object.
+struct ObjectWriter {
+    headers: Vec<SectionHeader>,
+    names: Vec<String>,
+    data: Vec<Vec<u8>>,
+}
+
+impl Default for ObjectWriter {
+    fn default() -> Self {
+        ObjectWriter {
+            headers: vec![header(0, 0, 0)],
+            names: vec![String::new()],
+            data: vec![Vec::new()],
+        }
+    }
+}
+
+impl ObjectWriter {
+    fn section(&mut self, name: &str, sh_type: u32, flags: u64, addralign: u64) -> u16 {
+        self.headers.push(header(sh_type, flags, addralign));
+        self.names.push(name.to_string());
+        self.data.push(Vec::new());
+        (self.headers.len() - 1) as u16
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        let shstrtab = self.section(".shstrtab", SHT_STRTAB, 0, 1);
+        let mut names = vec![0];
+        for (header, name) in self.headers.iter_mut().zip(&self.names).skip(1) {
+            header.name_offset = names.len() as u32;
+            names.extend_from_slice(name.as_bytes());
+            names.push(0);
+        }
+        self.data[shstrtab as usize] = names;
+
+        // The contents follow the ELF header, then the section header table
+        let mut out = vec![0; 64];
+        for (header, data) in self.headers.iter_mut().zip(&self.data).skip(1) {
+            out.resize(out.len().next_multiple_of(header.addralign as usize), 0);
+            header.offset = out.len() as u64;
+            header.size = data.len() as u64;
+            out.extend_from_slice(data);
+        }
+        out.resize(out.len().next_multiple_of(8), 0);
+        let shoff = out.len() as u64;
+        for header in &self.headers {
+            header.encode(&mut out);
+        }
+
+        let shnum = self.headers.len() as u16;
+        let mut elf_header = vec![0x7f, b'E', b'L', b'F', EI_CLASS_64, EI_DATA_2LSB, 1];
+        elf_header.resize(16, 0);
+        elf_header.extend_from_slice(&ET_REL.to_le_bytes());
+        elf_header.extend_from_slice(&EM_AARCH64.to_le_bytes());
+        elf_header.extend_from_slice(&1u32.to_le_bytes()); // e_version
+        elf_header.extend_from_slice(&0u64.to_le_bytes()); // e_entry
+        elf_header.extend_from_slice(&0u64.to_le_bytes()); // e_phoff
+        elf_header.extend_from_slice(&shoff.to_le_bytes());
+        elf_header.extend_from_slice(&0u32.to_le_bytes()); // e_flags
+        elf_header.extend_from_slice(&64u16.to_le_bytes()); // e_ehsize
+        elf_header.extend_from_slice(&0u16.to_le_bytes()); // e_phentsize
+        elf_header.extend_from_slice(&0u16.to_le_bytes()); // e_phnum
+        elf_header.extend_from_slice(&64u16.to_le_bytes()); // e_shentsize
+        elf_header.extend_from_slice(&shnum.to_le_bytes());
+        elf_header.extend_from_slice(&shstrtab.to_le_bytes());
+        out[..64].copy_from_slice(&elf_header);
+        out
+    }
+}
+
+fn header(sh_type: u32, flags: u64, addralign: u64) -> SectionHeader {
+    SectionHeader {
+        name_offset: 0,
+        sh_type,
+        flags,
+        addr: 0,
+        offset: 0,
+        size: 0,
+        link: 0,
+        info: 0,
+        addralign,
+        entsize: 0,
+    }
+}
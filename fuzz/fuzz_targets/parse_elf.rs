@@ -0,0 +1,37 @@
+//! Feeds arbitrary bytes through the ELF parsers, which must reject bad input with an
+//! error rather than panic. Seeded with the objects in `materials/`:
+//!
+//!     cargo +nightly fuzz run parse_elf
+
+#![no_main]
+
+use elkr::elf::{
+    header::parse_elf_header,
+    relocation::{parse_rela_table, parse_relocation_table},
+    section::{SHT_REL, SHT_RELA, SHT_SYMTAB, parse_section_header_table},
+    symbol::parse_symbol_table,
+};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok((_, header)) = parse_elf_header(data) else {
+        return;
+    };
+    let Ok((_, sections)) = parse_section_header_table(data, &header) else {
+        return;
+    };
+    for section in &sections {
+        match section.sh_type {
+            SHT_SYMTAB => {
+                let _ = parse_symbol_table(data, section);
+            }
+            SHT_RELA => {
+                let _ = parse_rela_table(data, section);
+            }
+            SHT_REL => {
+                let _ = parse_relocation_table(data, section);
+            }
+            _ => {}
+        }
+    }
+});
@@ -0,0 +1,216 @@
+//! Reading `ar` archives, both regular ones (`!<arch>`) and GNU thin archives
+//! (`!<thin>`), whose members are references to object files kept on disk.
+//!
+//! Only the members the link needs are linked in, see `LinkerContext::add_archive`;
+//! they are found by their own symbol tables rather than the archive's.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::error::LinkerError;
+
+pub const AR_MAGIC: &[u8; 8] = b"!<arch>\n";
+pub const THIN_AR_MAGIC: &[u8; 8] = b"!<thin>\n";
+const MEMBER_HEADER_SIZE: usize = 60;
+
+/// A member as listed in the archive.
+#[derive(Debug, PartialEq, Eq)]
+pub enum ArchiveMember<'a> {
+    /// A member stored in a regular archive.
+    Embedded { name: String, data: &'a [u8] },
+    /// A member of a thin archive: a path, relative to the archive's directory.
+    External { path: String },
+}
+
+pub fn is_archive(content: &[u8]) -> bool {
+    content.starts_with(AR_MAGIC) || content.starts_with(THIN_AR_MAGIC)
+}
+
+/// Lists the object members of an archive, skipping the symbol and long name tables.
+pub fn parse_archive(content: &[u8]) -> Result<Vec<ArchiveMember<'_>>, String> {
+    let thin = if content.starts_with(AR_MAGIC) {
+        false
+    } else if content.starts_with(THIN_AR_MAGIC) {
+        true
+    } else {
+        return Err("not an ar archive".to_string());
+    };
+
+    let mut members = Vec::new();
+    let mut long_names: &[u8] = &[];
+    let mut pos = AR_MAGIC.len();
+    while pos < content.len() {
+        let header = content
+            .get(pos..pos + MEMBER_HEADER_SIZE)
+            .ok_or_else(|| format!("truncated member header at offset {pos:#x}"))?;
+        if &header[58..60] != b"`\n" {
+            return Err(format!("bad member header at offset {pos:#x}"));
+        }
+        let field = |range: std::ops::Range<usize>| {
+            String::from_utf8_lossy(&header[range])
+                .trim_end()
+                .to_string()
+        };
+        let raw_name = field(0..16);
+        let size: usize = field(48..58)
+            .parse()
+            .map_err(|_| format!("bad member size at offset {pos:#x}"))?;
+        let data_start = pos + MEMBER_HEADER_SIZE;
+        // The tables are always stored, even in thin archives; the objects aren't
+        let is_table = raw_name == "/" || raw_name == "/SYM64/" || raw_name == "//";
+        let stored_size = if thin && !is_table { 0 } else { size };
+        let data = content
+            .get(data_start..data_start + stored_size)
+            .ok_or_else(|| format!("member at offset {pos:#x} runs past the end"))?;
+        // Member data is padded to an even offset
+        pos = data_start + stored_size + stored_size % 2;
+
+        if raw_name == "//" {
+            long_names = data;
+            continue;
+        }
+        if is_table {
+            continue;
+        }
+        let name = match raw_name.strip_prefix('/') {
+            // GNU long name: an offset into the `//` table, terminated by "/\n"
+            Some(offset) => {
+                let offset: usize = offset
+                    .parse()
+                    .map_err(|_| format!("bad long name reference {raw_name}"))?;
+                let rest = long_names
+                    .get(offset..)
+                    .ok_or_else(|| format!("long name offset {offset} out of range"))?;
+                let end = rest
+                    .windows(2)
+                    .position(|w| w == b"/\n")
+                    .unwrap_or(rest.len());
+                String::from_utf8_lossy(&rest[..end]).into_owned()
+            }
+            None => raw_name.trim_end_matches('/').to_string(),
+        };
+        members.push(if thin {
+            ArchiveMember::External { path: name }
+        } else {
+            ArchiveMember::Embedded { name, data }
+        });
+    }
+    Ok(members)
+}
+
+/// Reads the members of the archive at `path`, whose contents are `content`, as
+/// `(name, data)` pairs ready for `LinkerContext::add_archive`. Thin archive members are
+/// read from disk relative to the archive's directory.
+pub fn read_archive_members(
+    path: &Path,
+    content: &[u8],
+) -> Result<Vec<(String, Vec<u8>)>, LinkerError> {
+    let members = parse_archive(content).map_err(|reason| LinkerError::MalformedArchive {
+        file: path.display().to_string(),
+        reason,
+    })?;
+    let dir = path.parent().unwrap_or(Path::new(""));
+    members
+        .into_iter()
+        .map(|member| match member {
+            ArchiveMember::Embedded { name, data } => {
+                Ok((format!("{}({name})", path.display()), data.to_vec()))
+            }
+            ArchiveMember::External { path: member } => {
+                let member_path: PathBuf = dir.join(&member);
+                let data = fs::read(&member_path).map_err(|source| LinkerError::Read {
+                    path: member_path.display().to_string(),
+                    source,
+                })?;
+                Ok((format!("{}({member})", path.display()), data))
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn member_header(name: &str, size: usize) -> Vec<u8> {
+        let header = format!("{name:<16}{:<12}{:<6}{:<6}{:<8}{size:<10}`\n", 0, 0, 0, 644);
+        assert_eq!(header.len(), MEMBER_HEADER_SIZE);
+        header.into_bytes()
+    }
+
+    #[test]
+    fn test_parse_regular_archive() {
+        let long_names = b"a_rather_long_member_name.o/\n";
+        let mut archive = AR_MAGIC.to_vec();
+        archive.extend(member_header("/", 4));
+        archive.extend([0; 4]);
+        archive.extend(member_header("//", long_names.len()));
+        archive.extend(long_names);
+        archive.push(b'\n');
+        archive.extend(member_header("short.o/", 3));
+        archive.extend(b"abc\n");
+        archive.extend(member_header("/0", 2));
+        archive.extend(b"de");
+
+        assert_eq!(
+            parse_archive(&archive).unwrap(),
+            [
+                ArchiveMember::Embedded {
+                    name: "short.o".to_string(),
+                    data: b"abc",
+                },
+                ArchiveMember::Embedded {
+                    name: "a_rather_long_member_name.o".to_string(),
+                    data: b"de",
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_thin_archive_members_are_read_from_disk() {
+        let dir = std::env::temp_dir().join(format!("elkr-thin-{}", std::process::id()));
+        fs::create_dir_all(dir.join("obj")).unwrap();
+        fs::write(dir.join("obj/member.o"), b"object bytes").unwrap();
+
+        let long_names = b"obj/member.o/\n";
+        let mut archive = THIN_AR_MAGIC.to_vec();
+        archive.extend(member_header("//", long_names.len()));
+        archive.extend(long_names);
+        // Only the size of the external file is recorded, not its contents
+        archive.extend(member_header("/0", 12));
+        let archive_path = dir.join("lib.a");
+
+        let members = read_archive_members(&archive_path, &archive).unwrap();
+        // A member missing from disk can't be read
+        fs::remove_file(dir.join("obj/member.o")).unwrap();
+        let err = read_archive_members(&archive_path, &archive).unwrap_err();
+        fs::remove_dir_all(&dir).unwrap();
+        let member_path = dir.join("obj/member.o").display().to_string();
+        assert!(matches!(&err, LinkerError::Read { path, .. } if *path == member_path));
+        assert_eq!(err.to_string(), format!("can't read {member_path}"));
+        assert_eq!(
+            members,
+            [(
+                format!("{}(obj/member.o)", archive_path.display()),
+                b"object bytes".to_vec()
+            )]
+        );
+    }
+
+    #[test]
+    fn test_reject_truncated_archive() {
+        let mut archive = AR_MAGIC.to_vec();
+        archive.extend(member_header("short.o/", 100));
+        archive.extend(b"abc");
+        assert!(parse_archive(&archive).is_err());
+        assert!(parse_archive(b"\x7fELF").is_err());
+        let err = read_archive_members(Path::new("lib.a"), &archive).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "lib.a: malformed archive: member at offset 0x8 runs past the end"
+        );
+    }
+}
@@ -0,0 +1,93 @@
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    time::SystemTime,
+};
+
+use sha1::{Digest, Sha1};
+
+pub const BUILD_ID_SECTION: &str = ".note.gnu.build-id";
+/// Note type of a GNU build-id note (`NT_GNU_BUILD_ID`)
+pub const NT_GNU_BUILD_ID: u32 = 3;
+const NOTE_NAME: &[u8; 4] = b"GNU\0";
+
+/// How the build-id stored in `.note.gnu.build-id` is generated.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BuildIdKind {
+    /// Don't emit a build-id note.
+    #[default]
+    None,
+    /// A random 16-byte UUID, different on every link.
+    Uuid,
+    /// SHA-1 (20 bytes) over the contents of the output sections.
+    Sha1,
+    /// 64-bit FNV-1a (8 bytes) over the contents of the output sections.
+    Fast,
+}
+
+impl BuildIdKind {
+    /// Length of the build-id (the note's `desc`) in bytes.
+    pub fn len(&self) -> usize {
+        match self {
+            BuildIdKind::None => 0,
+            BuildIdKind::Uuid => 16,
+            BuildIdKind::Sha1 => 20,
+            BuildIdKind::Fast => 8,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Computes the build-id. `contents` is ignored for [`BuildIdKind::Uuid`].
+    pub fn compute(&self, contents: &[u8]) -> Vec<u8> {
+        match self {
+            BuildIdKind::None => Vec::new(),
+            BuildIdKind::Uuid => random_uuid().to_vec(),
+            BuildIdKind::Sha1 => Sha1::digest(contents).to_vec(),
+            BuildIdKind::Fast => fnv1a64(contents).to_le_bytes().to_vec(),
+        }
+    }
+}
+
+/// Encodes a `NT_GNU_BUILD_ID` note: namesz, descsz, type, "GNU\0", then the id.
+pub fn build_id_note(id: &[u8]) -> Vec<u8> {
+    let mut note = Vec::with_capacity(16 + id.len());
+    note.extend_from_slice(&(NOTE_NAME.len() as u32).to_le_bytes());
+    note.extend_from_slice(&(id.len() as u32).to_le_bytes());
+    note.extend_from_slice(&NT_GNU_BUILD_ID.to_le_bytes());
+    note.extend_from_slice(NOTE_NAME);
+    note.extend_from_slice(id);
+    // Note entries are padded to 4 bytes
+    note.resize(note.len().div_ceil(4) * 4, 0);
+    note
+}
+
+fn fnv1a64(data: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in data {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    hash
+}
+
+/// A version 4 (random) UUID, seeded from std's randomly keyed hasher.
+fn random_uuid() -> [u8; 16] {
+    let state = RandomState::new();
+    let now = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or_default();
+    let mut uuid = [0u8; 16];
+    for (i, chunk) in uuid.chunks_mut(8).enumerate() {
+        let mut hasher = state.build_hasher();
+        hasher.write_usize(i);
+        hasher.write_u128(now);
+        chunk.copy_from_slice(&hasher.finish().to_le_bytes());
+    }
+    uuid[6] = (uuid[6] & 0x0f) | 0x40;
+    uuid[8] = (uuid[8] & 0x3f) | 0x80;
+    uuid
+}
@@ -0,0 +1,84 @@
+//! Collects the warnings reported while linking, and formats what they mention.
+
+/// What happens to warnings, see `LinkerContext::warning_policy`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum WarningPolicy {
+    /// Print them and carry on
+    #[default]
+    Warn,
+    /// Drop them
+    Silent,
+    /// Print them, and fail the link phase that reported one
+    Fatal,
+}
+
+#[derive(Debug, Default)]
+pub struct Diagnostics {
+    warnings: Vec<String>,
+    policy: WarningPolicy,
+}
+
+impl Diagnostics {
+    pub fn set_policy(&mut self, policy: WarningPolicy) {
+        self.policy = policy;
+    }
+
+    /// Reports a problem that doesn't stop the link, unless warnings are fatal.
+    pub fn warn(&mut self, message: String) {
+        if self.policy == WarningPolicy::Silent {
+            return;
+        }
+        eprintln!("warning: {message}");
+        self.warnings.push(message);
+    }
+
+    pub fn warnings(&self) -> &[String] {
+        &self.warnings
+    }
+
+    /// The first warning, if there is one and warnings are fatal.
+    pub fn fatal_warning(&self) -> Option<&str> {
+        if self.policy != WarningPolicy::Fatal {
+            return None;
+        }
+        self.warnings.first().map(String::as_str)
+    }
+}
+
+/// A symbol name as diagnostics quote it: `` `name` ``, followed by the demangled name in
+/// parentheses when `name` is a mangled C++ name and the `demangle` feature is enabled.
+pub fn quote_symbol(name: &str) -> String {
+    match demangle(name) {
+        Some(demangled) => format!("`{name}` ({demangled})"),
+        None => format!("`{name}`"),
+    }
+}
+
+#[cfg(feature = "demangle")]
+fn demangle(name: &str) -> Option<String> {
+    // Plain C names would parse as mangled names too
+    if !name.starts_with("_Z") {
+        return None;
+    }
+    cpp_demangle::Symbol::new(name).ok()?.demangle().ok()
+}
+
+#[cfg(not(feature = "demangle"))]
+fn demangle(_name: &str) -> Option<String> {
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_quote_symbol() {
+        assert_eq!(quote_symbol("main"), "`main`");
+        #[cfg(feature = "demangle")]
+        assert_eq!(
+            quote_symbol("_ZN3foo3barEv"),
+            "`_ZN3foo3barEv` (foo::bar())"
+        );
+    }
+}
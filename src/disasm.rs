@@ -0,0 +1,105 @@
+//! A tiny AArch64 decoder covering only the instruction classes the linker patches,
+//! used to make relocation traces human-readable.
+
+/// Formats `insn` located at `pc`, e.g. `bl 0x401020 <foo>`.
+///
+/// `symbolize` maps a target address to a symbol name for the `<name>` suffix.
+/// Instructions outside the supported classes are printed as `.inst 0x...`.
+pub fn format_instruction<'a>(
+    insn: u32,
+    pc: u64,
+    symbolize: impl Fn(u64) -> Option<&'a str>,
+) -> String {
+    let with_target = |mnemonic: String, target: u64| match symbolize(target) {
+        Some(name) => format!("{mnemonic} {target:#x} <{name}>"),
+        None => format!("{mnemonic} {target:#x}"),
+    };
+
+    if insn & 0x7C00_0000 == 0x1400_0000 {
+        // B / BL: imm26 is a word offset from pc
+        let mnemonic = if insn & 0x8000_0000 != 0 { "bl" } else { "b" };
+        let offset = sign_extend((insn & 0x03FF_FFFF) as u64, 26) << 2;
+        return with_target(mnemonic.to_string(), pc.wrapping_add(offset as u64));
+    }
+
+    if insn & 0x1F00_0000 == 0x1000_0000 {
+        // ADR / ADRP: imm = immhi:immlo
+        let rd = insn & 0x1F;
+        let immlo = ((insn >> 29) & 0x3) as u64;
+        let immhi = ((insn >> 5) & 0x7_FFFF) as u64;
+        let imm = sign_extend((immhi << 2) | immlo, 21);
+        return if insn & 0x8000_0000 != 0 {
+            let target = (pc & !0xFFF).wrapping_add((imm << 12) as u64);
+            with_target(format!("adrp x{rd},"), target)
+        } else {
+            with_target(format!("adr x{rd},"), pc.wrapping_add(imm as u64))
+        };
+    }
+
+    if insn & 0x7F80_0000 == 0x1100_0000 {
+        // ADD (immediate)
+        let prefix = if insn & 0x8000_0000 != 0 { 'x' } else { 'w' };
+        let reg = |r: u32| {
+            if r == 31 {
+                "sp".to_string()
+            } else {
+                format!("{prefix}{r}")
+            }
+        };
+        let imm12 = (insn >> 10) & 0xFFF;
+        let shift = if insn & (1 << 22) != 0 {
+            ", lsl #12"
+        } else {
+            ""
+        };
+        return format!(
+            "add {}, {}, #{imm12:#x}{shift}",
+            reg(insn & 0x1F),
+            reg((insn >> 5) & 0x1F)
+        );
+    }
+
+    format!(".inst {insn:#010x}")
+}
+
+/// Sign-extends the low `bits` bits of `value`.
+fn sign_extend(value: u64, bits: u32) -> i64 {
+    let shift = 64 - bits;
+    ((value << shift) as i64) >> shift
+}
+
+#[cfg(test)]
+mod test {
+    use super::format_instruction;
+
+    #[test]
+    fn test_format_branches() {
+        let foo = |addr| (addr == 0x401020).then_some("foo");
+        assert_eq!(
+            format_instruction(0x9400_0000 | 0x8, 0x401000, foo),
+            "bl 0x401020 <foo>"
+        );
+        // Backward branch: imm26 = -4 words
+        assert_eq!(
+            format_instruction(0x1400_0000 | 0x03FF_FFFC, 0x401010, |_| None),
+            "b 0x401000"
+        );
+    }
+
+    #[test]
+    fn test_format_adrp_and_add() {
+        // adrp x0, +1 page
+        assert_eq!(
+            format_instruction(0x9000_0000 | (1 << 29), 0x400123, |_| None),
+            "adrp x0, 0x401000"
+        );
+        assert_eq!(
+            format_instruction(0x9100_0000 | (0x20 << 10) | (1 << 5) | 2, 0, |_| None),
+            "add x2, x1, #0x20"
+        );
+        assert_eq!(
+            format_instruction(0xd503201f, 0, |_| None),
+            ".inst 0xd503201f"
+        );
+    }
+}
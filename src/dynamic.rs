@@ -0,0 +1,99 @@
+//! The dynamic symbol table of an image exporting its symbols, see
+//! [`crate::linker::LinkerContext::export_dynamic`].
+
+pub const DYNSYM_SECTION: &str = ".dynsym";
+pub const DYNSTR_SECTION: &str = ".dynstr";
+pub const HASH_SECTION: &str = ".hash";
+pub const DYNAMIC_SECTION: &str = ".dynamic";
+
+// Dynamic Array Tags, `d_tag`
+pub const DT_NULL: u64 = 0;
+pub const DT_HASH: u64 = 4;
+pub const DT_STRTAB: u64 = 5;
+pub const DT_SYMTAB: u64 = 6;
+pub const DT_STRSZ: u64 = 10;
+pub const DT_SYMENT: u64 = 11;
+
+/// Size of one `Elf64_Dyn` entry
+pub const DYNAMIC_ENTRY_SIZE: u64 = 16;
+
+/// The SysV ELF hash of a symbol name, as used by `.hash` lookups.
+pub fn elf_hash(name: &[u8]) -> u32 {
+    let mut hash: u32 = 0;
+    for &byte in name {
+        hash = (hash << 4).wrapping_add(byte as u32);
+        let high = hash & 0xf000_0000;
+        if high != 0 {
+            hash ^= high >> 24;
+        }
+        hash &= !high;
+    }
+    hash
+}
+
+/// `.dynstr` for `names`: a leading empty string, then each name in order.
+pub fn dynamic_string_table(names: &[String]) -> Vec<u8> {
+    let mut table = vec![0];
+    for name in names {
+        table.extend_from_slice(name.as_bytes());
+        table.push(0);
+    }
+    table
+}
+
+/// `.hash` for a `.dynsym` holding the null symbol followed by `names`: nbucket, nchain,
+/// the buckets, then one chain entry per symbol, all 32-bit words.
+pub fn hash_table(names: &[String]) -> Vec<u8> {
+    let nchain = names.len() + 1;
+    let nbucket = names.len().max(1);
+    let mut buckets = vec![0u32; nbucket];
+    let mut chains = vec![0u32; nchain];
+    for (i, name) in names.iter().enumerate() {
+        let index = i + 1;
+        let bucket = elf_hash(name.as_bytes()) as usize % nbucket;
+        chains[index] = buckets[bucket];
+        buckets[bucket] = index as u32;
+    }
+    [nbucket as u32, nchain as u32]
+        .into_iter()
+        .chain(buckets)
+        .chain(chains)
+        .flat_map(u32::to_le_bytes)
+        .collect()
+}
+
+/// Encodes `entries` as `Elf64_Dyn`s, terminated by `DT_NULL`.
+pub fn dynamic_section(entries: &[(u64, u64)]) -> Vec<u8> {
+    entries
+        .iter()
+        .chain([&(DT_NULL, 0)])
+        .flat_map(|(tag, value)| [tag.to_le_bytes(), value.to_le_bytes()])
+        .flatten()
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_hash_table_lookup() {
+        assert_eq!(elf_hash(b"printf"), 0x077905a6);
+        assert_eq!(elf_hash(b""), 0);
+
+        let names: Vec<String> = ["alpha", "beta", "gamma"].map(String::from).to_vec();
+        let table = hash_table(&names);
+        let word = |i: usize| u32::from_le_bytes(table[i * 4..i * 4 + 4].try_into().unwrap());
+        let (nbucket, nchain) = (word(0) as usize, word(1) as usize);
+        assert_eq!(nchain, names.len() + 1);
+        assert_eq!(table.len(), (2 + nbucket + nchain) * 4);
+        // Every name is found by walking its bucket's chain
+        for (i, name) in names.iter().enumerate() {
+            let mut index = word(2 + elf_hash(name.as_bytes()) as usize % nbucket);
+            while index != 0 && index as usize != i + 1 {
+                index = word(2 + nbucket + index as usize);
+            }
+            assert_eq!(index as usize, i + 1, "{name}");
+        }
+    }
+}
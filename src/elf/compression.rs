@@ -0,0 +1,157 @@
+use nom::{
+    IResult, Parser,
+    number::complete::{le_u32, le_u64},
+};
+
+// Compression algorithms, `ch_type`
+pub const ELFCOMPRESS_ZLIB: u32 = 1;
+pub const ELFCOMPRESS_ZSTD: u32 = 2;
+
+/// Size of `Elf64_Chdr`
+pub const CHDR_SIZE: usize = 24;
+/// Bound on how much more than its compressed size is reserved for decompressed data
+#[cfg(feature = "zlib")]
+const MAX_PREALLOCATED_RATIO: u64 = 8;
+
+/// How kept debug sections are stored in the output.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CompressKind {
+    /// Uncompressed; compressed inputs are decompressed.
+    #[default]
+    None,
+    /// Compressed with zlib (`ELFCOMPRESS_ZLIB`).
+    Zlib,
+    /// As in the input: sections with a compressed input are compressed with zlib again,
+    /// the others are left alone. Relocations and merging rule out copying the input's
+    /// compressed bytes verbatim.
+    Keep,
+}
+
+/// The `Elf64_Chdr` at the start of a `SHF_COMPRESSED` section.
+pub struct CompressionHeader {
+    pub ch_type: u32,
+    /// Size of the uncompressed data
+    pub ch_size: u64,
+    /// Alignment of the uncompressed data
+    pub ch_addralign: u64,
+}
+
+pub fn parse_compression_header(input: &[u8]) -> IResult<&[u8], CompressionHeader> {
+    let (input, (ch_type, _reserved, ch_size, ch_addralign)) =
+        (le_u32, le_u32, le_u64, le_u64).parse(input)?;
+    Ok((
+        input,
+        CompressionHeader {
+            ch_type,
+            ch_size,
+            ch_addralign,
+        },
+    ))
+}
+
+/// Compresses `data` with zlib into the contents of a `SHF_COMPRESSED` section: an
+/// `Elf64_Chdr` recording the size and `addralign` of the uncompressed data, then the
+/// compressed stream.
+///
+/// Returns `None` if this build can't compress.
+#[cfg_attr(not(feature = "zlib"), allow(unused_variables))]
+pub fn compress_section(data: &[u8], addralign: u64) -> Option<Vec<u8>> {
+    #[cfg(feature = "zlib")]
+    {
+        use std::io::Write;
+
+        let mut out = Vec::with_capacity(CHDR_SIZE + data.len() / 2);
+        out.extend_from_slice(&ELFCOMPRESS_ZLIB.to_le_bytes());
+        out.extend_from_slice(&0u32.to_le_bytes()); // ch_reserved
+        out.extend_from_slice(&(data.len() as u64).to_le_bytes());
+        out.extend_from_slice(&addralign.to_le_bytes());
+        let mut encoder = flate2::write::ZlibEncoder::new(out, flate2::Compression::default());
+        // Writing into a Vec can't fail
+        encoder.write_all(data).ok()?;
+        encoder.finish().ok()
+    }
+    #[cfg(not(feature = "zlib"))]
+    None
+}
+
+/// Decompresses the contents of a `SHF_COMPRESSED` section (header included). `ch_size`
+/// comes from the file, so it isn't trusted: no more than that is ever decompressed, and
+/// data of any other length is an `InvalidData` error.
+///
+/// Returns `None` for algorithms this build can't decompress.
+#[cfg_attr(not(feature = "zlib"), allow(unused_variables))]
+pub fn decompress_section(
+    header: &CompressionHeader,
+    compressed: &[u8],
+) -> Option<std::io::Result<Vec<u8>>> {
+    match header.ch_type {
+        #[cfg(feature = "zlib")]
+        ELFCOMPRESS_ZLIB => {
+            use std::io::{Error, ErrorKind, Read};
+
+            // Preallocate for a plausible compression ratio only; the Vec grows past it
+            let capacity = header
+                .ch_size
+                .min(compressed.len() as u64 * MAX_PREALLOCATED_RATIO);
+            let mut data = Vec::with_capacity(capacity as usize);
+            // One byte past ch_size is enough to tell that the stream is too long
+            let result = flate2::read::ZlibDecoder::new(&compressed[CHDR_SIZE..])
+                .take(header.ch_size.saturating_add(1))
+                .read_to_end(&mut data)
+                .and_then(|_| {
+                    if data.len() as u64 == header.ch_size {
+                        Ok(data)
+                    } else if data.len() as u64 > header.ch_size {
+                        Err(Error::new(
+                            ErrorKind::InvalidData,
+                            format!("decompresses to more than {} bytes", header.ch_size),
+                        ))
+                    } else {
+                        Err(Error::new(
+                            ErrorKind::InvalidData,
+                            format!(
+                                "decompresses to {} bytes instead of {}",
+                                data.len(),
+                                header.ch_size
+                            ),
+                        ))
+                    }
+                });
+            Some(result)
+        }
+        _ => None,
+    }
+}
+
+#[cfg(all(test, feature = "zlib"))]
+mod test {
+    use super::*;
+
+    fn with_size(mut section: Vec<u8>, ch_size: u64) -> (CompressionHeader, Vec<u8>) {
+        section[8..16].copy_from_slice(&ch_size.to_le_bytes());
+        let (_, header) = parse_compression_header(&section).unwrap();
+        (header, section)
+    }
+
+    #[test]
+    fn test_decompress_checks_ch_size() {
+        let data = [7u8; 100];
+        let section = compress_section(&data, 1).unwrap();
+        let (header, section) = with_size(section, 100);
+        assert_eq!(
+            decompress_section(&header, &section).unwrap().unwrap(),
+            data
+        );
+
+        // A huge ch_size neither preallocates it nor reads past the stream
+        let (header, section) = with_size(section, u64::MAX);
+        let err = decompress_section(&header, &section).unwrap().unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("100 bytes instead of"), "{err}");
+
+        // Decompression stops once the stream is longer than ch_size
+        let (header, section) = with_size(section, 10);
+        let err = decompress_section(&header, &section).unwrap().unwrap_err();
+        assert_eq!(err.to_string(), "decompresses to more than 10 bytes");
+    }
+}
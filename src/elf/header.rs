@@ -7,12 +7,15 @@ use nom::{
 const ELF_MAGIC: &[u8] = &[0x7f, b'E', b'L', b'F'];
 
 // Enums for `e_class` field
+pub const EI_CLASS_32: u8 = 1; // 32-bit
 pub const EI_CLASS_64: u8 = 2; // 64-bit
 // Enums for `data` field
 pub const EI_DATA_2LSB: u8 = 1; // Little Endian
+pub const EI_DATA_2MSB: u8 = 2; // Big Endian
 // Enums for `e_type` field
 pub const ET_REL: u16 = 1; // Relocatable file
 pub const ET_EXEC: u16 = 2; // Executable file
+pub const ET_DYN: u16 = 3; // Shared object file
 // Enums for `e_machine` field
 pub const EM_AARCH64: u16 = 183; // AArch64 architecture
 
@@ -43,6 +46,33 @@ pub struct ElfHeader {
     pub e_shstrndx: u16, // index of the section storing all section names
 }
 
+impl ElfHeader {
+    /// The header of a little-endian ELF64 relocatable object for `e_machine`, for objects
+    /// built in memory rather than read from a file.
+    pub fn relocatable(e_machine: u16) -> Self {
+        ElfHeader {
+            class: EI_CLASS_64,
+            data: EI_DATA_2LSB,
+            version: 1,
+            _os_abi: 0,
+            _abi_version: 0,
+            e_type: ET_REL,
+            e_machine,
+            e_version: 1,
+            e_entry: 0,
+            e_phoff: 0,
+            e_shoff: 0,
+            e_flags: 0,
+            e_ehsize: 64,
+            e_phentsize: 0,
+            e_phnum: 0,
+            e_shentsize: 64,
+            e_shnum: 0,
+            e_shstrndx: 0,
+        }
+    }
+}
+
 pub fn parse_elf_header(input: &[u8]) -> IResult<&[u8], ElfHeader> {
     let (
         input,
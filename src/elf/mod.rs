@@ -1,3 +1,4 @@
+pub mod compression;
 pub mod header;
 pub mod relocation;
 pub mod section;
@@ -127,4 +128,67 @@ mod test {
             }
         }
     }
+
+    #[test]
+    fn test_parse_extended_section_count() {
+        use crate::{
+            elf::{
+                header::parse_elf_header,
+                section::{SHN_XINDEX, parse_section_header_table, section_name_table_index},
+            },
+            test_utils::start_object,
+        };
+
+        let mut elf_data = start_object();
+        let (_, elf_header) = parse_elf_header(&elf_data).unwrap();
+        let real_count = elf_header.e_shnum;
+        let real_shstrndx = elf_header.e_shstrndx;
+
+        // Move the section count into sh[0].sh_size and the string table index into sh[0].sh_link
+        let shoff = elf_header.e_shoff as usize;
+        elf_data[0x3c..0x3e].copy_from_slice(&0u16.to_le_bytes());
+        elf_data[0x3e..0x40].copy_from_slice(&SHN_XINDEX.to_le_bytes());
+        elf_data[shoff + 32..shoff + 40].copy_from_slice(&(real_count as u64).to_le_bytes());
+        elf_data[shoff + 40..shoff + 44].copy_from_slice(&(real_shstrndx as u32).to_le_bytes());
+
+        let (_, elf_header) = parse_elf_header(&elf_data).unwrap();
+        assert_eq!(elf_header.e_shnum, 0);
+        let (_, section_headers) = parse_section_header_table(&elf_data, &elf_header)
+            .expect("Failed to parse section header table");
+        assert_eq!(section_headers.len(), real_count as usize);
+        assert_eq!(
+            section_name_table_index(&elf_header, &section_headers),
+            real_shstrndx as usize
+        );
+    }
+
+    #[test]
+    fn test_tables_out_of_range_are_errors() {
+        use crate::{
+            elf::{
+                header::parse_elf_header,
+                relocation::{parse_rel_table, parse_rela_table},
+                section::parse_section_header_table,
+                symbol::parse_symbol_table,
+            },
+            test_utils::start_object,
+        };
+
+        let mut elf_data = start_object();
+        let (_, elf_header) = parse_elf_header(&elf_data).unwrap();
+        let (_, sections) = parse_section_header_table(&elf_data, &elf_header).unwrap();
+        let mut table = sections[1].clone();
+        table.offset = u64::MAX;
+        table.size = 24;
+        table.entsize = 24;
+        assert!(parse_symbol_table(&elf_data, &table).is_err());
+        assert!(parse_rela_table(&elf_data, &table).is_err());
+        table.entsize = 16;
+        assert!(parse_rel_table(&elf_data, &table).is_err());
+
+        // e_shoff past the end of the file
+        elf_data[0x28..0x30].copy_from_slice(&u64::MAX.to_le_bytes());
+        let (_, elf_header) = parse_elf_header(&elf_data).unwrap();
+        assert!(parse_section_header_table(&elf_data, &elf_header).is_err());
+    }
 }
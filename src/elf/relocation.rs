@@ -3,17 +3,32 @@ use nom::{
     number::complete::{le_i64, le_u64},
 };
 
-use crate::elf::section::SectionHeader;
+use crate::elf::section::{SHT_REL, SectionHeader};
 
 pub const R_AARCH64_ABS64: u32 = 257;
 pub const R_AARCH64_ABS32: u32 = 258;
 pub const R_AARCH64_ABS16: u32 = 259;
-
+pub const R_AARCH64_PREL64: u32 = 260;
 pub const R_AARCH64_PREL32: u32 = 261;
 
+pub const R_AARCH64_MOVW_UABS_G0: u32 = 263;
+pub const R_AARCH64_MOVW_UABS_G0_NC: u32 = 264;
+pub const R_AARCH64_MOVW_UABS_G1: u32 = 265;
+pub const R_AARCH64_MOVW_UABS_G1_NC: u32 = 266;
+pub const R_AARCH64_MOVW_UABS_G2: u32 = 267;
+pub const R_AARCH64_MOVW_UABS_G2_NC: u32 = 268;
+pub const R_AARCH64_MOVW_UABS_G3: u32 = 269;
+
+pub const R_AARCH64_ADR_PREL_LO21: u32 = 274;
+
+pub const R_AARCH64_TSTBR14: u32 = 279;
+pub const R_AARCH64_CONDBR19: u32 = 280;
+
 pub const R_AARCH64_JUMP26: u32 = 282;
 pub const R_AARCH64_CALL26: u32 = 283;
 
+pub const R_AARCH64_GOTREL64: u32 = 307;
+
 /// Since we adopt the `ELF64` specification
 /// We use `Rela` instead of `Rel`
 pub struct Rela {
@@ -45,11 +60,90 @@ pub fn parse_rela_entry(input: &[u8]) -> IResult<&[u8], Rela> {
     ))
 }
 
+/// Parses an `Elf64_Rel` entry, which has no addend field: it is stored in the place being
+/// relocated instead (see [`implicit_addend`]), so `addend` is left 0.
+pub fn parse_rel_entry(input: &[u8]) -> IResult<&[u8], Rela> {
+    let (input, (offset, info)) = (le_u64, le_u64).parse(input)?;
+
+    Ok((
+        input,
+        Rela {
+            offset,
+            info,
+            addend: 0,
+        },
+    ))
+}
+
+pub fn parse_rel_table<'a>(
+    file: &'a [u8],
+    rel_header: &SectionHeader,
+) -> IResult<&'a [u8], Vec<Rela>> {
+    if rel_header.entsize == 0 || !rel_header.size.is_multiple_of(rel_header.entsize) {
+        return Err(nom::Err::Error(nom::error::Error::new(
+            file,
+            nom::error::ErrorKind::Verify,
+        )));
+    }
+
+    let num_entries = (rel_header.size / rel_header.entsize) as usize;
+    let Some(table_data) = file.get(rel_header.offset as usize..) else {
+        return Err(nom::Err::Error(nom::error::Error::new(
+            file,
+            nom::error::ErrorKind::Eof,
+        )));
+    };
+
+    nom::multi::count(parse_rel_entry, num_entries).parse(table_data)
+}
+
+/// Parses a `SHT_RELA` or `SHT_REL` section, see [`parse_rel_entry`] for the latter.
+pub fn parse_relocation_table<'a>(
+    file: &'a [u8],
+    header: &SectionHeader,
+) -> IResult<&'a [u8], Vec<Rela>> {
+    if header.sh_type == SHT_REL {
+        parse_rel_table(file, header)
+    } else {
+        parse_rela_table(file, header)
+    }
+}
+
+/// Reads the addend of a `SHT_REL` relocation of type `r_type` from `place`, the bytes
+/// being relocated. Returns `None` for types whose addend can't be stored in place, and
+/// if `place` is too short.
+pub fn implicit_addend(r_type: u32, place: &[u8]) -> Option<i64> {
+    let bytes = |n: usize| place.get(..n);
+    match r_type {
+        R_AARCH64_ABS64 | R_AARCH64_PREL64 => Some(i64::from_le_bytes(bytes(8)?.try_into().ok()?)),
+        R_AARCH64_ABS32 | R_AARCH64_PREL32 => {
+            Some(i32::from_le_bytes(bytes(4)?.try_into().ok()?) as i64)
+        }
+        R_AARCH64_ABS16 => Some(i16::from_le_bytes(bytes(2)?.try_into().ok()?) as i64),
+        R_AARCH64_CALL26 | R_AARCH64_JUMP26 => {
+            let insn = u32::from_le_bytes(bytes(4)?.try_into().ok()?);
+            // imm26 is a signed word offset
+            Some((((insn << 6) as i32) >> 4) as i64)
+        }
+        R_AARCH64_CONDBR19 => {
+            let insn = u32::from_le_bytes(bytes(4)?.try_into().ok()?);
+            // imm19 in bits [23:5], a signed word offset
+            Some((((insn << 8) as i32 >> 13) << 2) as i64)
+        }
+        R_AARCH64_TSTBR14 => {
+            let insn = u32::from_le_bytes(bytes(4)?.try_into().ok()?);
+            // imm14 in bits [18:5], a signed word offset
+            Some((((insn << 13) as i32 >> 18) << 2) as i64)
+        }
+        _ => None,
+    }
+}
+
 pub fn parse_rela_table<'a>(
     file: &'a [u8],
     rela_header: &SectionHeader,
 ) -> IResult<&'a [u8], Vec<Rela>> {
-    if rela_header.entsize == 0 || rela_header.size % rela_header.entsize != 0 {
+    if rela_header.entsize == 0 || !rela_header.size.is_multiple_of(rela_header.entsize) {
         return Err(nom::Err::Error(nom::error::Error::new(
             file,
             nom::error::ErrorKind::Verify,
@@ -57,7 +151,12 @@ pub fn parse_rela_table<'a>(
     }
 
     let num_entries = (rela_header.size / rela_header.entsize) as usize;
-    let table_data = &file[rela_header.offset as usize..];
+    let Some(table_data) = file.get(rela_header.offset as usize..) else {
+        return Err(nom::Err::Error(nom::error::Error::new(
+            file,
+            nom::error::ErrorKind::Eof,
+        )));
+    };
 
     nom::multi::count(parse_rela_entry, num_entries).parse(table_data)
 }
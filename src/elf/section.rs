@@ -1,3 +1,5 @@
+use std::fmt;
+
 use nom::{
     IResult, Parser,
     number::complete::{le_u32, le_u64},
@@ -6,12 +8,37 @@ use nom::{
 use crate::elf::header::ElfHeader;
 
 // Section Types, `sh_type`
+pub const SHT_NULL: u32 = 0;
 pub const SHT_PROGBITS: u32 = 1;
 pub const SHT_SYMTAB: u32 = 2;
+pub const SHT_STRTAB: u32 = 3;
 pub const SHT_RELA: u32 = 4;
+pub const SHT_HASH: u32 = 5;
+pub const SHT_DYNAMIC: u32 = 6;
+pub const SHT_NOTE: u32 = 7;
 pub const SHT_NOBITS: u32 = 8;
+pub const SHT_REL: u32 = 9;
+pub const SHT_DYNSYM: u32 = 11;
+pub const SHT_INIT_ARRAY: u32 = 14;
+pub const SHT_FINI_ARRAY: u32 = 15;
+pub const SHT_PREINIT_ARRAY: u32 = 16;
+pub const SHT_SYMTAB_SHNDX: u32 = 18;
+
+// Special section indices
+pub const SHN_UNDEF: u16 = 0;
+pub const SHN_LORESERVE: u16 = 0xff00;
+pub const SHN_ABS: u16 = 0xfff1;
+pub const SHN_XINDEX: u16 = 0xffff;
 
-#[derive(Clone)]
+// Section Flags, `sh_flags`
+pub const SHF_WRITE: u64 = 0x1;
+pub const SHF_ALLOC: u64 = 0x2;
+pub const SHF_EXECINSTR: u64 = 0x4;
+pub const SHF_INFO_LINK: u64 = 0x40;
+pub const SHF_TLS: u64 = 0x400;
+pub const SHF_COMPRESSED: u64 = 0x800;
+
+#[derive(Clone, Debug)]
 pub struct SectionHeader {
     pub name_offset: u32, // an index into the section header string table section
     pub sh_type: u32,
@@ -30,6 +57,22 @@ pub struct SectionHeader {
     pub entsize: u64,
 }
 
+impl SectionHeader {
+    /// Appends the 64-byte `Elf64_Shdr` encoding of the header to `out`.
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.name_offset.to_le_bytes());
+        out.extend_from_slice(&self.sh_type.to_le_bytes());
+        out.extend_from_slice(&self.flags.to_le_bytes());
+        out.extend_from_slice(&self.addr.to_le_bytes());
+        out.extend_from_slice(&self.offset.to_le_bytes());
+        out.extend_from_slice(&self.size.to_le_bytes());
+        out.extend_from_slice(&self.link.to_le_bytes());
+        out.extend_from_slice(&self.info.to_le_bytes());
+        out.extend_from_slice(&self.addralign.to_le_bytes());
+        out.extend_from_slice(&self.entsize.to_le_bytes());
+    }
+}
+
 fn parse_section_header(input: &[u8]) -> IResult<&[u8], SectionHeader> {
     let (input, (name_offset, sh_type, flags, addr, offset, size, link, info, addralign, entsize)) =
         (
@@ -67,13 +110,72 @@ pub fn parse_section_header_table<'a>(
     elf_header: &ElfHeader,
 ) -> IResult<&'a [u8], Vec<SectionHeader>> {
     let offset = elf_header.e_shoff as usize;
-    let num_headers = elf_header.e_shnum as usize;
+    let mut num_headers = elf_header.e_shnum as usize;
+
+    // A table past the end of the file is malformed input, not a reason to panic
+    let Some(table_input) = file.get(offset..) else {
+        return Err(nom::Err::Error(nom::error::Error::new(
+            file,
+            nom::error::ErrorKind::Eof,
+        )));
+    };
 
-    let table_input = &file[offset..];
+    // With SHN_LORESERVE or more sections, e_shnum is 0 and the real count
+    // is stored in the `sh_size` field of section header 0.
+    if num_headers == 0 && offset != 0 {
+        let (_, first) = parse_section_header(table_input)?;
+        num_headers = first.size as usize;
+    }
 
     nom::multi::count(parse_section_header, num_headers).parse(table_input)
 }
 
+/// A section whose `sh_offset` and `sh_size` reach past the end of the file.
+#[derive(Debug, PartialEq, Eq)]
+pub struct SectionOutOfBounds {
+    pub offset: u64,
+    pub size: u64,
+}
+
+impl fmt::Display for SectionOutOfBounds {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "contents at offset {:#x} with size {:#x} are out of bounds",
+            self.offset, self.size
+        )
+    }
+}
+
+/// The bytes of a section in `file`. `SHT_NOBITS` sections occupy no file space, so
+/// their contents are empty whatever `sh_offset` and `sh_size` say.
+pub fn section_data<'a>(
+    file: &'a [u8],
+    section_header: &SectionHeader,
+) -> Result<&'a [u8], SectionOutOfBounds> {
+    if section_header.sh_type == SHT_NOBITS {
+        return Ok(&[]);
+    }
+    section_header
+        .offset
+        .checked_add(section_header.size)
+        .and_then(|end| file.get(section_header.offset as usize..end as usize))
+        .ok_or(SectionOutOfBounds {
+            offset: section_header.offset,
+            size: section_header.size,
+        })
+}
+
+/// Index of the section name string table, following the `SHN_XINDEX` escape
+/// into section header 0's `sh_link` when the index doesn't fit in `e_shstrndx`.
+pub fn section_name_table_index(elf_header: &ElfHeader, sections: &[SectionHeader]) -> usize {
+    if elf_header.e_shstrndx == SHN_XINDEX {
+        sections.first().map_or(0, |s| s.link as usize)
+    } else {
+        elf_header.e_shstrndx as usize
+    }
+}
+
 pub fn get_section_name<'a>(
     sshstrtab_data /*section cthat store the names of all sections */: &'a [u8],
     section_header: &SectionHeader,
@@ -87,3 +189,46 @@ pub fn get_section_name<'a>(
         .ok()
         .and_then(|cstr| cstr.to_str().ok())
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn header(sh_type: u32, offset: u64, size: u64) -> SectionHeader {
+        SectionHeader {
+            name_offset: 0,
+            sh_type,
+            flags: 0,
+            addr: 0,
+            offset,
+            size,
+            link: 0,
+            info: 0,
+            addralign: 1,
+            entsize: 0,
+        }
+    }
+
+    #[test]
+    fn test_section_data() {
+        let file = [1, 2, 3, 4, 5, 6, 7, 8];
+        assert_eq!(
+            section_data(&file, &header(SHT_PROGBITS, 2, 4)),
+            Ok(&file[2..6])
+        );
+        assert_eq!(
+            section_data(&file, &header(SHT_PROGBITS, 8, 0)),
+            Ok(&[][..])
+        );
+        assert_eq!(
+            section_data(&file, &header(SHT_PROGBITS, 6, 4)),
+            Err(SectionOutOfBounds { offset: 6, size: 4 })
+        );
+        assert!(section_data(&file, &header(SHT_PROGBITS, u64::MAX, 2)).is_err());
+        // .bss takes no file space, wherever it claims to be
+        assert_eq!(
+            section_data(&file, &header(SHT_NOBITS, 0x1000, 0x1000)),
+            Ok(&[][..])
+        );
+    }
+}
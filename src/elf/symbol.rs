@@ -5,6 +5,18 @@ use nom::{
 
 use crate::elf::section::SectionHeader;
 
+// Symbol Binding, `ELF64_ST_BIND(st_info)`
+pub const STB_LOCAL: u8 = 0;
+pub const STB_GLOBAL: u8 = 1;
+pub const STB_WEAK: u8 = 2;
+// Symbol Types, `ELF64_ST_TYPE(st_info)`
+pub const STT_NOTYPE: u8 = 0;
+pub const STT_OBJECT: u8 = 1;
+pub const STT_FUNC: u8 = 2;
+pub const STT_SECTION: u8 = 3;
+pub const STT_FILE: u8 = 4;
+pub const STT_TLS: u8 = 6;
+
 ///An object file's symbol table holds information needed to locate and relocate a program's symbolic definitions and references.
 pub struct Symbol {
     ///holds an index into the object file's symbol string table
@@ -26,6 +38,26 @@ impl Symbol {
     pub fn get_type(&self) -> u8 {
         self.info & 0x0F
     }
+    /// Whether this is the all-zero entry every symbol table starts with. Symbol index 0
+    /// in a relocation means "no symbol".
+    pub fn is_null(&self) -> bool {
+        self.name_offset == 0
+            && self.info == 0
+            && self.other == 0
+            && self.shndx == 0
+            && self.value == 0
+            && self.size == 0
+    }
+
+    /// Appends the 24-byte `Elf64_Sym` encoding of the symbol to `out`.
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.name_offset.to_le_bytes());
+        out.push(self.info);
+        out.push(self.other);
+        out.extend_from_slice(&self.shndx.to_le_bytes());
+        out.extend_from_slice(&self.value.to_le_bytes());
+        out.extend_from_slice(&self.size.to_le_bytes());
+    }
 }
 
 pub fn parse_symbol(input: &[u8]) -> IResult<&[u8], Symbol> {
@@ -49,7 +81,7 @@ pub fn parse_symbol_table<'a>(
     file: &'a [u8],
     symtab_header: &SectionHeader,
 ) -> IResult<&'a [u8], Vec<Symbol>> {
-    if symtab_header.entsize == 0 || symtab_header.size % symtab_header.entsize != 0 {
+    if symtab_header.entsize == 0 || !symtab_header.size.is_multiple_of(symtab_header.entsize) {
         return Err(nom::Err::Error(nom::error::Error::new(
             file,
             nom::error::ErrorKind::Verify,
@@ -57,7 +89,12 @@ pub fn parse_symbol_table<'a>(
     }
 
     let num_symbols = (symtab_header.size / symtab_header.entsize) as usize;
-    let table_data = &file[symtab_header.offset as usize..];
+    let Some(table_data) = file.get(symtab_header.offset as usize..) else {
+        return Err(nom::Err::Error(nom::error::Error::new(
+            file,
+            nom::error::ErrorKind::Eof,
+        )));
+    };
 
     nom::multi::count(parse_symbol, num_symbols).parse(table_data)
 }
@@ -0,0 +1,293 @@
+use std::{error::Error, fmt, io};
+
+use crate::{
+    diagnostics::quote_symbol,
+    elf::header::{EI_CLASS_64, EI_DATA_2LSB, EM_AARCH64, ET_DYN, ET_EXEC},
+};
+
+/// Errors reported while loading and linking input files.
+#[derive(Debug)]
+pub enum LinkerError {
+    /// The input could not be parsed as an ELF object.
+    Malformed { file: String, reason: String },
+    /// The input is not an ELF64 file.
+    UnsupportedClass { file: String, class: u8 },
+    /// The input is not little-endian.
+    UnsupportedEndianness { file: String, data: u8 },
+    /// The input is built for another architecture than AArch64.
+    UnsupportedMachine { file: String, machine: u16 },
+    /// The input is an executable, shared object or core file rather than an `ET_REL` object.
+    NotRelocatable { file: String, e_type: u16 },
+    /// A relocation entry that can't be applied as written.
+    BadRelocation {
+        file: String,
+        offset: u64,
+        reason: String,
+    },
+    /// A relocation's computed value doesn't fit in the field it patches.
+    RelocationOutOfRange {
+        file: String,
+        symbol: String,
+        r_type: u32,
+        offset: u64,
+    },
+    /// A `SHF_COMPRESSED` section uses an algorithm this build can't decompress.
+    UnsupportedCompression {
+        file: String,
+        section: String,
+        ch_type: u32,
+    },
+    /// Laying out a section would take addresses past the end of the address space.
+    AddressOverflow { section: String },
+    /// Placing a section in the output file would take offsets past what a u64 can hold.
+    OffsetOverflow { section: String },
+    /// A section asks for an alignment that isn't a power of two.
+    BadAlignment { section: String, align: u64 },
+    /// Two inputs define the same global symbol under `ResolutionOrder::ErrorOnConflict`.
+    DuplicateSymbol {
+        symbol: String,
+        first_file: String,
+        second_file: String,
+    },
+    /// A symbol passed to `require_defined` isn't defined by any input.
+    RequiredSymbolMissing { symbol: String },
+    /// A symbol to operate on isn't a defined global symbol.
+    UnknownSymbol { symbol: String },
+    /// An address assertion doesn't hold; carries the assertion's own message.
+    AssertionFailed { message: String },
+    /// A loaded segment is both writable and executable while `reject_wx` is on.
+    WxSegment { vaddr: u64 },
+    /// The output is larger than the size it should be padded to.
+    ImageTooLarge { size: u64, limit: u64 },
+    /// A warning was reported while warnings are fatal.
+    FatalWarning { message: String },
+    /// The output fails a check of `verify_output`.
+    InvalidOutput { reason: String },
+    /// A link phase was run out of order; it has to directly follow `previous`.
+    InvalidPhaseOrder {
+        phase: &'static str,
+        previous: &'static str,
+    },
+    /// An input was added once the link moved past adding inputs, to `next`.
+    PhasePassed {
+        phase: &'static str,
+        next: &'static str,
+    },
+    /// There is nothing to link.
+    NoInputFiles,
+    /// Neither `_start` nor `main` is defined, so the executable has nowhere to start.
+    NoEntryPoint,
+    /// The symbol chosen with `EntryPoint::Symbol` isn't defined.
+    UndefinedEntry { symbol: String },
+    /// Writing the output to `path` failed.
+    Io { path: String, source: io::Error },
+    /// Reading an input from `path` failed.
+    Read { path: String, source: io::Error },
+    /// An input archive can't be parsed.
+    MalformedArchive { file: String, reason: String },
+    /// The loaded contents end at `end`, past the addresses hex output can hold.
+    TooHighForHex { end: u64 },
+    /// A relocation in a kept section refers to a symbol whose section was garbage-collected.
+    DiscardedSectionReference {
+        file: String,
+        section: String,
+        symbol: String,
+        discarded_file: String,
+        discarded_section: String,
+    },
+}
+
+/// Errors from applying relocations to a buffer with
+/// [`apply_relocations_to`](crate::linker::apply_relocations_to).
+#[derive(Debug, PartialEq, Eq)]
+pub enum RelocationError {
+    /// The relocation's symbol index has no address.
+    UnresolvedSymbol { symbol: u32, offset: u64 },
+    /// The computed value doesn't fit in the field being patched.
+    OutOfRange { r_type: u32, offset: u64 },
+    /// An instruction relocation whose place isn't 4-byte aligned.
+    Misaligned { r_type: u32, offset: u64 },
+    /// The place runs past the end of the buffer.
+    OutOfBounds { r_type: u32, offset: u64 },
+    /// A relocation type elkr doesn't implement.
+    Unsupported { r_type: u32, offset: u64 },
+    /// A GOT-relative relocation, but there is no GOT.
+    NoGot { r_type: u32, offset: u64 },
+}
+
+impl RelocationError {
+    /// What went wrong, without the offset.
+    pub fn reason(&self) -> String {
+        match self {
+            RelocationError::UnresolvedSymbol { symbol, .. } => {
+                format!("symbol {symbol} is not resolved")
+            }
+            RelocationError::OutOfRange { r_type, .. } => {
+                format!("relocation type {r_type} is out of range")
+            }
+            RelocationError::Misaligned { r_type, .. } => {
+                format!("instruction relocation type {r_type} is not 4-byte aligned")
+            }
+            RelocationError::OutOfBounds { r_type, .. } => {
+                format!("relocation type {r_type} runs past the end of the section")
+            }
+            RelocationError::Unsupported { r_type, .. } => {
+                format!("relocation type {r_type} is not supported")
+            }
+            RelocationError::NoGot { r_type, .. } => {
+                format!("relocation type {r_type} is relative to the GOT, but there is no .got")
+            }
+        }
+    }
+}
+
+impl fmt::Display for RelocationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (RelocationError::UnresolvedSymbol { offset, .. }
+        | RelocationError::OutOfRange { offset, .. }
+        | RelocationError::Misaligned { offset, .. }
+        | RelocationError::OutOfBounds { offset, .. }
+        | RelocationError::Unsupported { offset, .. }
+        | RelocationError::NoGot { offset, .. }) = self;
+        write!(f, "relocation at offset {offset:#x}: {}", self.reason())
+    }
+}
+
+impl Error for RelocationError {}
+
+impl fmt::Display for LinkerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LinkerError::Malformed { file, reason } => {
+                write!(f, "{file}: malformed ELF file: {reason}")
+            }
+            LinkerError::UnsupportedClass { file, class } => write!(
+                f,
+                "{file}: unsupported ELF class {class} (only ELFCLASS64 = {EI_CLASS_64} is supported)"
+            ),
+            LinkerError::UnsupportedEndianness { file, data } => write!(
+                f,
+                "{file}: unsupported ELF data encoding {data} (only little-endian ELFDATA2LSB = {EI_DATA_2LSB} is supported)"
+            ),
+            LinkerError::UnsupportedMachine { file, machine } => write!(
+                f,
+                "{file}: unsupported machine {machine} (only AArch64 = {EM_AARCH64} is supported)"
+            ),
+            LinkerError::NotRelocatable { file, e_type } => {
+                let kind = match *e_type {
+                    ET_EXEC => "an executable",
+                    ET_DYN => "a shared object",
+                    _ => "not a relocatable object",
+                };
+                write!(
+                    f,
+                    "{file}: is {kind} (e_type {e_type}), only relocatable objects can be linked"
+                )
+            }
+            LinkerError::BadRelocation {
+                file,
+                offset,
+                reason,
+            } => write!(f, "{file}: bad relocation at offset {offset:#x}: {reason}"),
+            LinkerError::RelocationOutOfRange {
+                file,
+                symbol,
+                r_type,
+                offset,
+            } => write!(
+                f,
+                "{file}: relocation type {r_type} against {} at offset {offset:#x} is out of range",
+                quote_symbol(symbol)
+            ),
+            LinkerError::UnsupportedCompression {
+                file,
+                section,
+                ch_type,
+            } => write!(
+                f,
+                "{file}: section {section} is compressed with unsupported type {ch_type}"
+            ),
+            LinkerError::AddressOverflow { section } => {
+                write!(f, "section {section} doesn't fit in the address space")
+            }
+            LinkerError::OffsetOverflow { section } => {
+                write!(f, "section {section} doesn't fit in the output file")
+            }
+            LinkerError::BadAlignment { section, align } => write!(
+                f,
+                "section {section} has alignment {align:#x}, which is not a power of two"
+            ),
+            LinkerError::DuplicateSymbol {
+                symbol,
+                first_file,
+                second_file,
+            } => write!(
+                f,
+                "{second_file}: duplicate definition of {}, first defined in {first_file}",
+                quote_symbol(symbol)
+            ),
+            LinkerError::RequiredSymbolMissing { symbol } => {
+                write!(f, "required symbol {} is not defined", quote_symbol(symbol))
+            }
+            LinkerError::UnknownSymbol { symbol } => {
+                write!(f, "{} is not a defined global symbol", quote_symbol(symbol))
+            }
+            LinkerError::AssertionFailed { message } => write!(f, "assertion failed: {message}"),
+            LinkerError::WxSegment { vaddr } => write!(
+                f,
+                "W^X violation: the segment at {vaddr:#x} is both writable and executable"
+            ),
+            LinkerError::ImageTooLarge { size, limit } => write!(
+                f,
+                "output is {size:#x} bytes, more than the {limit:#x} bytes it should be padded to"
+            ),
+            LinkerError::FatalWarning { message } => {
+                write!(f, "warning treated as an error: {message}")
+            }
+            LinkerError::InvalidOutput { reason } => write!(f, "invalid output: {reason}"),
+            LinkerError::InvalidPhaseOrder { phase, previous } => {
+                write!(f, "{phase} can only run right after {previous}")
+            }
+            LinkerError::PhasePassed { phase, next } => {
+                write!(f, "{phase} can only run before {next}")
+            }
+            LinkerError::NoInputFiles => write!(f, "no input files"),
+            LinkerError::NoEntryPoint => {
+                write!(f, "no entry point: neither `_start` nor `main` is defined")
+            }
+            LinkerError::UndefinedEntry { symbol } => {
+                write!(f, "no entry point: {} is not defined", quote_symbol(symbol))
+            }
+            LinkerError::DiscardedSectionReference {
+                file,
+                section,
+                symbol,
+                discarded_file,
+                discarded_section,
+            } => write!(
+                f,
+                "{file}: relocation in {section} refers to {}, defined in discarded section {discarded_section} of {discarded_file}",
+                quote_symbol(symbol)
+            ),
+            // The io::Error is the source, for callers to report as they see fit
+            LinkerError::Io { path, .. } => write!(f, "can't write {path}"),
+            LinkerError::Read { path, .. } => write!(f, "can't read {path}"),
+            LinkerError::MalformedArchive { file, reason } => {
+                write!(f, "{file}: malformed archive: {reason}")
+            }
+            LinkerError::TooHighForHex { end } => write!(
+                f,
+                "the image ends at {end:#x}, past the 32-bit address space of hex output"
+            ),
+        }
+    }
+}
+
+impl Error for LinkerError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            LinkerError::Io { source, .. } | LinkerError::Read { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
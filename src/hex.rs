@@ -0,0 +1,158 @@
+//! ASCII image formats consumed by flash programmers: Intel HEX and Motorola S-records.
+//!
+//! Both encoders take a flat image (see `LinkerContext::flat_image`) and return `None`
+//! when it doesn't fit in the formats' 32-bit address space.
+
+use std::fmt::Write;
+
+/// Data bytes per record; 16 is what objcopy and most tools emit.
+const BYTES_PER_RECORD: usize = 16;
+
+const IHEX_DATA: u8 = 0x00;
+const IHEX_END_OF_FILE: u8 = 0x01;
+const IHEX_EXTENDED_LINEAR_ADDRESS: u8 = 0x04;
+const IHEX_START_LINEAR_ADDRESS: u8 = 0x05;
+
+/// Encodes `data`, loaded at `start`, as Intel HEX. An extended linear address record
+/// precedes the data whenever the upper 16 address bits change, and `entry` becomes a
+/// start linear address record.
+pub fn encode_ihex(start: u64, data: &[u8], entry: Option<u64>) -> Option<String> {
+    if !fits_u32(start, data) || entry.is_some_and(|e| e > u32::MAX as u64) {
+        return None;
+    }
+    let mut out = String::new();
+    let mut upper = None;
+    let mut offset = 0;
+    while offset < data.len() {
+        let addr = (start + offset as u64) as u32;
+        if upper != Some(addr >> 16) {
+            upper = Some(addr >> 16);
+            let upper = (addr >> 16) as u16;
+            ihex_record(
+                &mut out,
+                0,
+                IHEX_EXTENDED_LINEAR_ADDRESS,
+                &upper.to_be_bytes(),
+            );
+        }
+        // A record's 16-bit offset can't run past the end of its 64K window
+        let to_boundary = 0x1_0000 - (addr & 0xFFFF) as usize;
+        let len = BYTES_PER_RECORD.min(to_boundary).min(data.len() - offset);
+        ihex_record(
+            &mut out,
+            addr as u16,
+            IHEX_DATA,
+            &data[offset..offset + len],
+        );
+        offset += len;
+    }
+    if let Some(entry) = entry {
+        let entry = entry as u32;
+        ihex_record(&mut out, 0, IHEX_START_LINEAR_ADDRESS, &entry.to_be_bytes());
+    }
+    ihex_record(&mut out, 0, IHEX_END_OF_FILE, &[]);
+    Some(out)
+}
+
+/// Encodes `data`, loaded at `start`, as Motorola S-records: an S0 header, S3 data
+/// records with 32-bit addresses, an S5/S6 record count and an S7 record holding
+/// `entry` (or 0 without one).
+pub fn encode_srec(start: u64, data: &[u8], entry: Option<u64>) -> Option<String> {
+    if !fits_u32(start, data) || entry.is_some_and(|e| e > u32::MAX as u64) {
+        return None;
+    }
+    let mut out = String::new();
+    srec_record(&mut out, '0', &[0, 0], b"elkr");
+    let mut count = 0u32;
+    for (i, chunk) in data.chunks(BYTES_PER_RECORD).enumerate() {
+        let addr = (start + (i * BYTES_PER_RECORD) as u64) as u32;
+        srec_record(&mut out, '3', &addr.to_be_bytes(), chunk);
+        count += 1;
+    }
+    if count <= 0xFFFF {
+        srec_record(&mut out, '5', &(count as u16).to_be_bytes(), &[]);
+    } else {
+        srec_record(&mut out, '6', &count.to_be_bytes()[1..], &[]);
+    }
+    let entry = entry.unwrap_or(0) as u32;
+    srec_record(&mut out, '7', &entry.to_be_bytes(), &[]);
+    Some(out)
+}
+
+fn fits_u32(start: u64, data: &[u8]) -> bool {
+    start
+        .checked_add(data.len() as u64)
+        .is_some_and(|end| end <= u32::MAX as u64 + 1)
+}
+
+/// `:LLAAAATT<data>CC`, where the checksum is the two's complement of the byte sum.
+fn ihex_record(out: &mut String, addr: u16, record_type: u8, data: &[u8]) {
+    let mut bytes = vec![data.len() as u8];
+    bytes.extend_from_slice(&addr.to_be_bytes());
+    bytes.push(record_type);
+    bytes.extend_from_slice(data);
+    let sum = bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+    bytes.push(sum.wrapping_neg());
+    out.push(':');
+    push_hex(out, &bytes);
+}
+
+/// `S<type><count><address><data><checksum>`, where the count covers the address, data
+/// and checksum, and the checksum is the ones' complement of the byte sum.
+fn srec_record(out: &mut String, record_type: char, addr: &[u8], data: &[u8]) {
+    let mut bytes = vec![(addr.len() + data.len() + 1) as u8];
+    bytes.extend_from_slice(addr);
+    bytes.extend_from_slice(data);
+    let sum = bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+    bytes.push(!sum);
+    out.push('S');
+    out.push(record_type);
+    push_hex(out, &bytes);
+}
+
+fn push_hex(out: &mut String, bytes: &[u8]) {
+    for byte in bytes {
+        write!(out, "{byte:02X}").unwrap();
+    }
+    out.push('\n');
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::decode_ihex;
+
+    #[test]
+    fn test_ihex_records() {
+        let hex = encode_ihex(0x400000, &[0x1f, 0x20, 0x03, 0xd5], Some(0x400000)).unwrap();
+        assert_eq!(
+            hex,
+            ":020000040040BA\n:040000001F2003D5E5\n:0400000500400000B7\n:00000001FF\n"
+        );
+    }
+
+    #[test]
+    fn test_ihex_crosses_64k_boundary() {
+        let data: Vec<u8> = (0..40).collect();
+        let hex = encode_ihex(0x1FFF8, &data, None).unwrap();
+        // One extended address record for each 64K window touched
+        assert_eq!(hex.matches(":02000004").count(), 2);
+        assert_eq!(decode_ihex(&hex), (0x1FFF8, data, None));
+    }
+
+    #[test]
+    fn test_srec_records() {
+        let srec = encode_srec(0x400000, &[0x1f, 0x20, 0x03, 0xd5], Some(0x400000)).unwrap();
+        assert_eq!(
+            srec,
+            "S0070000656C6B724A\nS309004000001F2003D59F\nS5030001FB\nS70500400000BA\n"
+        );
+    }
+
+    #[test]
+    fn test_addresses_beyond_32_bits_are_rejected() {
+        assert!(encode_ihex(0xFFFF_FFFF, &[0, 0], None).is_none());
+        assert!(encode_srec(0x1_0000_0000, &[0], None).is_none());
+        assert!(encode_ihex(0, &[0], Some(0x1_0000_0000)).is_none());
+    }
+}
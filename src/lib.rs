@@ -1,4 +1,13 @@
+pub mod archive;
+pub mod build_id;
+pub mod diagnostics;
+pub mod disasm;
+pub mod dynamic;
 pub mod elf;
+pub mod error;
+pub mod hex;
 pub mod linker;
+pub mod map;
 
-
+#[cfg(test)]
+mod test_utils;
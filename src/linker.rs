@@ -1,32 +1,265 @@
 use std::{
-    collections::HashMap,
-    fs,
-    io::{self, Write},
+    borrow::Cow,
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt, fs,
+    io::{BufWriter, Write},
 };
 
 const PT_LOAD: u32 = 1;
-const PF_R: u32 = 4;
-const PF_W: u32 = 2;
-const PF_X: u32 = 1;
-
-use crate::elf::{
-    header::{ET_EXEC, ElfHeader, parse_elf_header},
-    relocation::{R_AARCH64_CALL26, R_AARCH64_PREL32, parse_rela_table},
-    section::{
-        SHT_NOBITS, SHT_PROGBITS, SHT_RELA, SHT_SYMTAB, SectionHeader, get_section_name,
-        parse_section_header_table,
+const PT_DYNAMIC: u32 = 2;
+const PT_INTERP: u32 = 3;
+const PT_NOTE: u32 = 4;
+const PT_TLS: u32 = 7;
+const PT_GNU_EH_FRAME: u32 = 0x6474e550;
+const PT_GNU_STACK: u32 = 0x6474e551;
+const PT_GNU_RELRO: u32 = 0x6474e552;
+pub const PF_R: u32 = 4;
+pub const PF_W: u32 = 2;
+pub const PF_X: u32 = 1;
+const CODE_SEGMENT_FLAGS: u32 = PF_R | PF_X;
+const DATA_SEGMENT_FLAGS: u32 = PF_R | PF_W;
+const PAGE_SIZE: u64 = 0x1000;
+/// Address the first segment, and with it the ELF header, is loaded at
+const BASE_ADDR: u64 = 0x40_0000;
+const ELF_HEADER_SIZE: u64 = 64;
+const PROGRAM_HEADER_SIZE: u64 = 56;
+const SECTION_HEADER_SIZE: usize = 64;
+const SYMBOL_SIZE: usize = 24;
+/// Section flags that all inputs merged into one output section must agree on
+const MERGE_FLAGS_MASK: u64 = SHF_WRITE | SHF_ALLOC | SHF_EXECINSTR | SHF_TLS;
+/// Size of the thread control block that precedes the TLS block (AArch64 uses TLS variant 1)
+const TCB_SIZE: u64 = 16;
+/// The AArch64 `nop` instruction
+const AARCH64_NOP: u32 = 0xd503201f;
+/// The AArch64 `brk #0` instruction
+const AARCH64_BRK_0: u32 = 0xd4200000;
+/// Holds the path of the dynamic linker, see [`LinkerContext::dynamic_linker`]
+const INTERP_SECTION: &str = ".interp";
+/// Marks an object whose code doesn't need an executable stack, unless it has `SHF_EXECINSTR`
+const GNU_STACK_SECTION: &str = ".note.GNU-stack";
+/// Defined at the start of `.got`, the base of GOT-relative relocations
+const GOT_SYMBOL: &str = "_GLOBAL_OFFSET_TABLE_";
+
+use crate::{
+    build_id::{BUILD_ID_SECTION, BuildIdKind, build_id_note},
+    diagnostics::{Diagnostics, WarningPolicy, quote_symbol},
+    disasm::format_instruction,
+    dynamic::{
+        DT_HASH, DT_STRSZ, DT_STRTAB, DT_SYMENT, DT_SYMTAB, DYNAMIC_ENTRY_SIZE, DYNAMIC_SECTION,
+        DYNSTR_SECTION, DYNSYM_SECTION, HASH_SECTION, dynamic_section, dynamic_string_table,
+        hash_table,
+    },
+    elf::{
+        compression::{
+            CompressKind, compress_section, decompress_section, parse_compression_header,
+        },
+        header::{
+            EI_CLASS_64, EI_DATA_2LSB, EM_AARCH64, ET_EXEC, ET_REL, ElfHeader, parse_elf_header,
+        },
+        relocation::{
+            R_AARCH64_ABS64, R_AARCH64_ADR_PREL_LO21, R_AARCH64_CALL26, R_AARCH64_CONDBR19,
+            R_AARCH64_GOTREL64, R_AARCH64_JUMP26, R_AARCH64_MOVW_UABS_G0,
+            R_AARCH64_MOVW_UABS_G0_NC, R_AARCH64_MOVW_UABS_G1, R_AARCH64_MOVW_UABS_G1_NC,
+            R_AARCH64_MOVW_UABS_G2, R_AARCH64_MOVW_UABS_G2_NC, R_AARCH64_MOVW_UABS_G3,
+            R_AARCH64_PREL32, R_AARCH64_PREL64, R_AARCH64_TSTBR14, Rela, implicit_addend,
+            parse_relocation_table,
+        },
+        section::{
+            SHF_ALLOC, SHF_COMPRESSED, SHF_EXECINSTR, SHF_INFO_LINK, SHF_TLS, SHF_WRITE, SHN_ABS,
+            SHN_LORESERVE, SHN_UNDEF, SHN_XINDEX, SHT_DYNAMIC, SHT_DYNSYM, SHT_FINI_ARRAY,
+            SHT_HASH, SHT_INIT_ARRAY, SHT_NOBITS, SHT_NOTE, SHT_NULL, SHT_PREINIT_ARRAY,
+            SHT_PROGBITS, SHT_REL, SHT_RELA, SHT_STRTAB, SHT_SYMTAB, SHT_SYMTAB_SHNDX,
+            SectionHeader, get_section_name, parse_section_header_table, section_data,
+            section_name_table_index,
+        },
+        symbol::{
+            STB_GLOBAL, STB_LOCAL, STT_FILE, STT_OBJECT, STT_SECTION, STT_TLS, Symbol,
+            get_symbol_name, parse_symbol_table,
+        },
     },
-    symbol::{Symbol, get_symbol_name, parse_symbol_table},
+    error::{LinkerError, RelocationError},
+    hex::{encode_ihex, encode_srec},
+    map::{LinkMap, MapSection, MapSegment, MapSymbol},
 };
 
+/// An object file as ELF structures rather than bytes, see [`LinkerContext::add_parsed`].
+/// Sections and symbols are indexed as in a file: both start with their null entry.
+pub struct ParsedObject {
+    pub filename: String,
+    pub header: ElfHeader,
+    pub sections: Vec<SectionHeader>,
+    /// Parallel to `sections`
+    pub section_names: Vec<String>,
+    /// Contents of each section, parallel to `sections`; empty for NOBITS sections.
+    /// `sh_offset` is ignored.
+    pub section_data: Vec<Vec<u8>>,
+    pub symbols: Vec<Symbol>,
+    /// Parallel to `symbols`
+    pub symbol_names: Vec<String>,
+}
+
 pub struct InputFile<'a> {
     filename: String,
     content: &'a [u8],
     header: ElfHeader,
     sections: Vec<SectionHeader>,
     symbols: Vec<Symbol>,
-    shstrtab_data: &'a [u8],
-    strtab_data: &'a [u8],
+    // Names looked up once up front, parallel to `sections` and `symbols`
+    // (empty when the name offset is invalid)
+    section_names: Vec<Cow<'a, str>>,
+    symbol_names: Vec<Cow<'a, str>>,
+    /// Contents of each section of a file added with [`LinkerContext::add_parsed`], parallel
+    /// to `sections`; empty for files read from `content`
+    section_data: Vec<Vec<u8>>,
+    decompressed_sections: HashMap<usize, (u64, Vec<u8>)>, // section_index -> (ch_addralign, data)
+}
+
+impl InputFile<'_> {
+    /// Contents of a section, decompressed if it was `SHF_COMPRESSED`. Empty for NOBITS
+    /// sections, whose `sh_offset` and `sh_size` don't describe file bytes.
+    fn section_contents(&self, section_idx: usize) -> &[u8] {
+        if let Some((_, data)) = self.decompressed_sections.get(&section_idx) {
+            return data;
+        }
+        if let Some(data) = self.section_data.get(section_idx) {
+            return data;
+        }
+        // add_file rejects sections that are out of bounds
+        section_data(self.content, &self.sections[section_idx]).unwrap_or_default()
+    }
+
+    /// Entries of a `SHT_RELA` or `SHT_REL` section, `None` if they can't be parsed.
+    fn relocations(&self, section_idx: usize) -> Option<Vec<Rela>> {
+        let section = &self.sections[section_idx];
+        let parsed = match self.section_data.get(section_idx) {
+            // A pre-parsed section's contents start at the beginning of its own buffer
+            Some(data) => parse_relocation_table(
+                data,
+                &SectionHeader {
+                    offset: 0,
+                    ..section.clone()
+                },
+            ),
+            None => parse_relocation_table(self.content, section),
+        };
+        parsed.ok().map(|(_, relocations)| relocations)
+    }
+
+    /// Checks the indices the link looks things up by without bounds checks: the section
+    /// of each symbol, and the target section and symbols of each relocation section.
+    /// Returns why the file is malformed otherwise.
+    fn check_indices(&self) -> Result<(), String> {
+        for (symbol_idx, symbol) in self.symbols.iter().enumerate() {
+            if symbol.shndx != SHN_UNDEF
+                && symbol.shndx < SHN_LORESERVE
+                && symbol.shndx as usize >= self.sections.len()
+            {
+                return Err(format!(
+                    "symbol {symbol_idx} is in section {}, which doesn't exist",
+                    symbol.shndx
+                ));
+            }
+        }
+        if let Some(idx) = missing_relocation_target(&self.sections) {
+            return Err(format!(
+                "relocation section {} applies to section {}, which doesn't exist",
+                self.section_names[idx], self.sections[idx].info
+            ));
+        }
+        for (section_idx, section) in self.sections.iter().enumerate() {
+            if !is_relocation_section(section) {
+                continue;
+            }
+            let name = &self.section_names[section_idx];
+            let relocations = self
+                .relocations(section_idx)
+                .ok_or_else(|| format!("relocation section {name} can't be parsed"))?;
+            if let Some(rela) = relocations
+                .iter()
+                .find(|rela| rela.get_symbol_index() as usize >= self.symbols.len())
+            {
+                return Err(format!(
+                    "relocation section {name} refers to symbol {}, which doesn't exist",
+                    rela.get_symbol_index()
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// What has to match for [`LinkerContext::icf`] to fold two sections, `None` for
+    /// sections that are never folded: anything but non-empty, read-only code.
+    fn fold_key(&self, file_idx: usize, section_idx: usize) -> Option<FoldKey> {
+        let section = &self.sections[section_idx];
+        if section.sh_type != SHT_PROGBITS
+            || section.flags & (SHF_ALLOC | SHF_EXECINSTR | SHF_WRITE) != SHF_ALLOC | SHF_EXECINSTR
+            || section.size == 0
+        {
+            return None;
+        }
+        let mut relocations = Vec::new();
+        for (rela_idx, rela_section) in self.sections.iter().enumerate() {
+            if !is_relocation_section(rela_section) || rela_section.info as usize != section_idx {
+                continue;
+            }
+            for rela in self.relocations(rela_idx)? {
+                let symbol_idx = rela.get_symbol_index() as usize;
+                let symbol = self.symbols.get(symbol_idx)?;
+                let target = if symbol_idx == 0 {
+                    FoldTarget::None
+                } else if symbol.get_bind() != STB_LOCAL {
+                    FoldTarget::Symbol(self.symbol_names[symbol_idx].to_string())
+                } else {
+                    match symbol.shndx {
+                        SHN_ABS => FoldTarget::Absolute(symbol.value),
+                        // A self-reference, e.g. a recursive call, matches another copy's
+                        shndx if shndx as usize == section_idx => FoldTarget::Itself(symbol.value),
+                        shndx => FoldTarget::Section(file_idx, shndx, symbol.value),
+                    }
+                };
+                relocations.push((rela.offset, rela.get_type(), rela.addend, target));
+            }
+        }
+        relocations.sort();
+        Some(FoldKey {
+            name: self.section_names[section_idx].to_string(),
+            align: self.section_align(section_idx),
+            data: self.section_contents(section_idx).to_vec(),
+            relocations,
+        })
+    }
+
+    /// Alignment of a section's contents: the compression header's for `SHF_COMPRESSED`
+    /// sections, which declare the alignment of the compressed data in `sh_addralign`.
+    /// Both 0 and 1 mean no constraint, and 0 comes out as 1.
+    fn section_align(&self, section_idx: usize) -> u64 {
+        self.decompressed_sections
+            .get(&section_idx)
+            .map_or(self.sections[section_idx].addralign, |(addralign, _)| {
+                *addralign
+            })
+            .max(1)
+    }
+}
+
+/// Identifies the sections [`LinkerContext::icf`] folds together: they go to the same
+/// output section, with the same alignment, bytes and relocations.
+#[derive(PartialEq, Eq, Hash)]
+struct FoldKey {
+    name: String,
+    align: u64,
+    data: Vec<u8>,
+    relocations: Vec<(u64, u32, i64, FoldTarget)>, // (offset, type, addend, target)
+}
+
+/// What a relocation in a [`FoldKey`] refers to
+#[derive(PartialEq, Eq, Hash, PartialOrd, Ord)]
+enum FoldTarget {
+    None,
+    Symbol(String),
+    Absolute(u64),
+    Itself(u64),              // offset in the section being relocated
+    Section(usize, u16, u64), // (file index, section index, offset)
 }
 
 /// Represents a merged section
@@ -36,9 +269,362 @@ pub struct OutputSection {
     data: Vec<u8>,
 }
 
-pub struct GlobalSymbol<'a> {
-    _name: &'a str,
+impl OutputSection {
+    /// One line describing where the section ended up, e.g.
+    /// `.text @ 0x400078, size 0x18, flags AX`, with readelf's flag letters.
+    pub fn summary(&self) -> String {
+        let flags: String = [
+            (SHF_WRITE, 'W'),
+            (SHF_ALLOC, 'A'),
+            (SHF_EXECINSTR, 'X'),
+            (SHF_TLS, 'T'),
+        ]
+        .iter()
+        .filter(|(flag, _)| self.header.flags & flag != 0)
+        .map(|(_, letter)| letter)
+        .collect();
+        format!(
+            "{} @ {:#x}, size {:#x}, flags {}",
+            self.name, self.header.addr, self.header.size, flags
+        )
+    }
+}
+
+/// A resolved global symbol. It owns its name, so the symbol table doesn't keep the
+/// input files' string tables borrowed.
+pub struct GlobalSymbol {
+    _name: String,
     final_addr: u64,
+    /// Thread-local: `final_addr` is the symbol's address in the TLS template
+    tls: bool,
+    // Carried over from the winning definition, for the emitted symbol table
+    sym_type: u8,
+    bind: u8,
+    size: u64,
+    /// Output section the symbol is defined in; `None` for absolute symbols
+    section: Option<String>,
+}
+
+/// Where a resolved symbol ended up in the output.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SymbolLocation {
+    /// Name of the containing output section
+    pub section: String,
+    /// `p_flags` of the `PT_LOAD` segment holding that section
+    pub segment_flags: u32,
+    pub offset_in_section: u64,
+}
+
+/// An input section that didn't make it into any output section.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SkippedSection {
+    pub file: String,
+    pub name: String,
+    pub reason: SkipReason,
+}
+
+/// Why an input section was left out of the output.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SkipReason {
+    /// Not `SHF_ALLOC`, and [`LinkerContext::keep_sections`] is off
+    NotAllocatable,
+    /// Holds neither code nor data, e.g. `SHT_NOTE` or `SHT_GROUP`
+    NotProgbitsOrNobits,
+    EmptyName,
+    /// Named `.rel*` like a relocation section, but holds code or data
+    RelocationName,
+    /// Left out by [`LinkerContext::exclude_section`] or [`LinkerContext::include_section`]
+    Excluded,
+    /// Unreachable from the roots with [`LinkerContext::gc_sections`] on
+    GarbageCollected,
+}
+
+/// A relocation applied against a global symbol, kept so it can be redone when the
+/// symbol moves, see [`LinkerContext::move_symbol`].
+struct AppliedRelocation {
+    file_idx: usize,
+    /// Output section holding the place, and the place's offset in it
+    section: String,
+    place: usize,
+    /// Offset of the place in its input section, for error messages
+    offset: u64,
+    p: u64,
+    r_type: u32,
+    addend: i64,
+}
+
+/// How the relocations of a link fared, counted by relocation type. See
+/// [`LinkerContext::relocation_stats`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RelocStats {
+    /// Patched into the output
+    pub applied: BTreeMap<u32, usize>,
+    /// Left as the assembler wrote them: the symbol has no address
+    pub unresolved: BTreeMap<u32, usize>,
+    /// Left as the assembler wrote them: the linker doesn't know the type
+    pub unsupported: BTreeMap<u32, usize>,
+}
+
+impl RelocStats {
+    pub fn total_applied(&self) -> usize {
+        self.applied.values().sum()
+    }
+
+    pub fn total_unresolved(&self) -> usize {
+        self.unresolved.values().sum()
+    }
+
+    pub fn total_unsupported(&self) -> usize {
+        self.unsupported.values().sum()
+    }
+}
+
+impl fmt::Display for RelocStats {
+    /// One line of totals, then one line per relocation type, e.g.
+    /// `  type 283: 2 applied, 0 unresolved, 1 unsupported`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "relocations: {} applied, {} unresolved, {} unsupported",
+            self.total_applied(),
+            self.total_unresolved(),
+            self.total_unsupported()
+        )?;
+        let types: BTreeMap<u32, ()> = [&self.applied, &self.unresolved, &self.unsupported]
+            .into_iter()
+            .flat_map(|counts| counts.keys().map(|&r_type| (r_type, ())))
+            .collect();
+        for r_type in types.keys() {
+            let count = |counts: &BTreeMap<u32, usize>| counts.get(r_type).copied().unwrap_or(0);
+            write!(
+                f,
+                "\n  type {r_type}: {} applied, {} unresolved, {} unsupported",
+                count(&self.applied),
+                count(&self.unresolved),
+                count(&self.unsupported)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// What the gaps between executable sections are filled with, see
+/// [`LinkerContext::code_fill`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FillKind {
+    /// The fill byte, like data padding (zero unless set with [`LinkerContext::fill_byte`])
+    #[default]
+    Zero,
+    /// No-ops, so execution falls through the padding
+    Nop,
+    /// Breakpoints, so stray execution faults deterministically
+    Trap,
+}
+
+impl FillKind {
+    /// The instruction repeated through the padding on `arch`; `None` for the fill byte.
+    pub fn instruction(self, arch: TargetArch) -> Option<u32> {
+        let TargetArch::AArch64 = arch;
+        match self {
+            FillKind::Zero => None,
+            FillKind::Nop => Some(AARCH64_NOP),
+            FillKind::Trap => Some(AARCH64_BRK_0),
+        }
+    }
+}
+
+/// Order of the symbols within the locals and within the globals of the emitted
+/// `.symtab`, see [`LinkerContext::sort_symbols`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SymbolSort {
+    #[default]
+    Name,
+    /// By address, then by name
+    Address,
+}
+
+/// Which of several global definitions of a symbol is used, see
+/// [`LinkerContext::resolution_order`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ResolutionOrder {
+    /// The one in the earliest input file
+    #[default]
+    First,
+    /// The one in the latest input file, with a warning for each one it overrides
+    Last,
+    /// None: a second definition fails the link
+    ErrorOnConflict,
+}
+
+/// What `e_entry` of the output points at, see [`LinkerContext::entry`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum EntryPoint {
+    /// `_start`, or `main` if there is no `_start`
+    #[default]
+    Default,
+    /// A global symbol's address
+    Symbol(String),
+    /// A fixed address
+    Address(u64),
+    /// An offset from the base address the image is loaded at, for entry points
+    /// known only by their position in the image
+    OffsetFromBase(u64),
+}
+
+/// A `PT_LOAD` segment in a [`LinkerContext::segment_map`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SegmentSpec {
+    /// Names of the output sections loaded by this segment; `*` matches any run of characters
+    pub patterns: Vec<String>,
+    /// `p_flags` of the segment, e.g. `PF_R | PF_X`
+    pub flags: u32,
+}
+
+impl SegmentSpec {
+    pub fn new(patterns: &[&str], flags: u32) -> Self {
+        Self {
+            patterns: patterns.iter().map(|p| p.to_string()).collect(),
+            flags,
+        }
+    }
+
+    fn matches(&self, section_name: &str) -> bool {
+        self.patterns
+            .iter()
+            .any(|pattern| glob_match(pattern, section_name))
+    }
+}
+
+/// A condition on a resolved symbol's address, checked by
+/// [`LinkerContext::assert_address`] like `ASSERT` in a linker script.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AddrAssertion {
+    /// `symbol <= limit`, e.g. the end of the image fits in flash
+    AtMost { symbol: String, limit: u64 },
+    /// `symbol >= limit`
+    AtLeast { symbol: String, limit: u64 },
+}
+
+impl AddrAssertion {
+    pub fn at_most(symbol: &str, limit: u64) -> Self {
+        AddrAssertion::AtMost {
+            symbol: symbol.to_string(),
+            limit,
+        }
+    }
+
+    pub fn at_least(symbol: &str, limit: u64) -> Self {
+        AddrAssertion::AtLeast {
+            symbol: symbol.to_string(),
+            limit,
+        }
+    }
+
+    /// Whether the assertion holds for the symbol at `addr`.
+    fn holds(&self, addr: u64) -> bool {
+        match self {
+            AddrAssertion::AtMost { limit, .. } => addr <= *limit,
+            AddrAssertion::AtLeast { limit, .. } => addr >= *limit,
+        }
+    }
+
+    fn symbol(&self) -> &str {
+        match self {
+            AddrAssertion::AtMost { symbol, .. } | AddrAssertion::AtLeast { symbol, .. } => symbol,
+        }
+    }
+}
+
+/// Something that happened to a symbol passed to [`LinkerContext::trace_symbol`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TraceEvent {
+    /// `file` defines the symbol with the given `STB_*` binding and `STT_*` type
+    Definition {
+        symbol: String,
+        file: String,
+        bind: u8,
+        sym_type: u8,
+    },
+    /// A relocation at `offset` in `file`'s `section` refers to the symbol
+    Reference {
+        symbol: String,
+        file: String,
+        section: String,
+        offset: u64,
+        r_type: u32,
+    },
+}
+
+impl fmt::Display for TraceEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TraceEvent::Definition {
+                symbol,
+                file,
+                bind,
+                sym_type,
+            } => write!(
+                f,
+                "{file}: definition of {} (bind {bind}, type {sym_type})",
+                quote_symbol(symbol)
+            ),
+            TraceEvent::Reference {
+                symbol,
+                file,
+                section,
+                offset,
+                r_type,
+            } => write!(
+                f,
+                "{file}: reference to {} from {section}+{offset:#x} (relocation type {r_type})",
+                quote_symbol(symbol)
+            ),
+        }
+    }
+}
+
+/// The last link phase that ran; each phase must follow the one before it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Phase {
+    AddFiles,
+    Layout,
+    ResolveSymbols,
+    ApplyRelocations,
+}
+
+impl Phase {
+    /// The method that runs the phase, for error messages.
+    fn method(self) -> &'static str {
+        match self {
+            Phase::AddFiles => "add_file",
+            Phase::Layout => "layout_and_merge_sections",
+            Phase::ResolveSymbols => "resolve_symbols",
+            Phase::ApplyRelocations => "apply_relocations",
+        }
+    }
+}
+
+/// The shape of the output file, fixed by [`LinkerContext::finalize`].
+#[derive(Clone, Debug)]
+struct FinalLayout {
+    entry_point: u64,
+    program_headers: Vec<ProgramHeader>,
+    /// File offsets of the kept non-allocatable sections, by output section name
+    unloaded_offsets: Vec<(String, u64)>,
+    /// Contents of the unloaded sections written compressed, by output section name
+    compressed_sections: HashMap<String, Vec<u8>>,
+    section_headers: Option<SectionHeaderTable>,
+    image_size: u64,
+}
+
+/// The section header table, along with the tables only it refers to.
+#[derive(Clone, Debug)]
+struct SectionHeaderTable {
+    /// Contents of `.symtab`, `.strtab` and `.shstrtab`, with their file offsets
+    tables: Vec<(u64, Vec<u8>)>,
+    offset: u64,
+    headers: Vec<SectionHeader>,
+    shstrndx: u16,
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -52,474 +638,7552 @@ pub struct ProgramHeader {
     pub memsz: u64,
     pub align: u64,
 }
+
+impl ProgramHeader {
+    /// `p_type` as readelf names it, e.g. `LOAD`, or in hex when it isn't known.
+    pub fn type_name(&self) -> String {
+        match self.p_type {
+            PT_LOAD => "LOAD".to_string(),
+            PT_DYNAMIC => "DYNAMIC".to_string(),
+            PT_INTERP => "INTERP".to_string(),
+            PT_NOTE => "NOTE".to_string(),
+            PT_TLS => "TLS".to_string(),
+            PT_GNU_EH_FRAME => "GNU_EH_FRAME".to_string(),
+            PT_GNU_STACK => "GNU_STACK".to_string(),
+            PT_GNU_RELRO => "GNU_RELRO".to_string(),
+            other => format!("{other:#x}"),
+        }
+    }
+
+    /// Reads a 56-byte `Elf64_Phdr`.
+    fn decode(bytes: &[u8; PROGRAM_HEADER_SIZE as usize]) -> Self {
+        let u32_at = |at: usize| u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap());
+        let u64_at = |at: usize| u64::from_le_bytes(bytes[at..at + 8].try_into().unwrap());
+        ProgramHeader {
+            p_type: u32_at(0),
+            flags: u32_at(4),
+            offset: u64_at(8),
+            vaddr: u64_at(16),
+            paddr: u64_at(24),
+            filesz: u64_at(32),
+            memsz: u64_at(40),
+            align: u64_at(48),
+        }
+    }
+}
+
+/// A row of `readelf --program-headers`: type, offset, vaddr, filesz, memsz, flags, align.
+impl fmt::Display for ProgramHeader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let p_type = self.type_name();
+        let flag = |bit, letter| if self.flags & bit != 0 { letter } else { ' ' };
+        write!(
+            f,
+            "{p_type:<14} {:#08x} {:#018x} {:#08x} {:#08x} {}{}{} {:#x}",
+            self.offset,
+            self.vaddr,
+            self.filesz,
+            self.memsz,
+            flag(PF_R, 'R'),
+            flag(PF_W, 'W'),
+            flag(PF_X, 'E'),
+            self.align
+        )
+    }
+}
+
 pub struct LinkerContext<'a> {
     input_files: Vec<InputFile<'a>>,
+    archive_members: Vec<InputFile<'a>>, // members of add_archive not extracted yet
     output_sections: HashMap<String, OutputSection>,
-    global_symbols: HashMap<&'a str, GlobalSymbol<'a>>,
+    global_symbols: HashMap<String, GlobalSymbol>,
     current_addr: u64, // an address counter for allocating addresses
     // Track where each input file's section starts within the output section
     input_section_offsets: HashMap<(usize, usize), u64>, // (file_index, section_index) -> offset_in_output_section
+    input_section_outputs: HashMap<(usize, usize), String>, // (file_index, section_index) -> output section name
+    build_id: BuildIdKind,
+    dynamic_linker: Option<String>,
+    entry: EntryPoint,
+    fill_byte: u8, // used for padding between sections and segments
+    code_fill: FillKind,
+    trim_zero_tails: bool,
+    pad_to_size: Option<u64>,
+    no_merge: bool,
+    traced_symbols: HashSet<String>,
+    trace_events: Vec<TraceEvent>,
+    gc_sections: bool,
+    icf: bool,
+    folded_sections: HashMap<(usize, usize), (usize, usize)>, // duplicate -> kept copy
+    discarded_sections: HashMap<(usize, usize), String>, // (file_index, section_index) -> section name
+    segment_map: Vec<SegmentSpec>,
+    segment_flags_overrides: Vec<(String, u32)>, // (output section, p_flags of its segment)
+    reject_wx: bool,
+    exec_stack: bool, // some input needs an executable stack
+    diagnostics: Diagnostics,
+    keep_sections: bool,
+    compress_debug_sections: CompressKind,
+    compressed_inputs: HashSet<String>, // output sections with a SHF_COMPRESSED input
+    section_align: HashMap<String, u64>, // output section name -> minimum alignment
+    entry_align: Option<u64>,
+    final_layout: Option<FinalLayout>,
+    phase: Phase,
+    dropped_sections: HashMap<(usize, usize), String>, // non-allocatable sections left out
+    skipped_sections: Vec<SkippedSection>,
+    local_symbols: Vec<(String, GlobalSymbol)>, // placed input locals, with keep_symbols
+    export_dynamic: bool,
+    dynamic_symbols: Vec<String>, // .dynsym entries after the null symbol, with export_dynamic
+    relocation_stats: RelocStats,
+    // By referenced global, for move_symbol; one entry per relocation against a global,
+    // kept for the lifetime of the context
+    applied_relocations: HashMap<String, Vec<AppliedRelocation>>,
+    keep_symbols: bool,
+    sort_symbols: SymbolSort,
+    resolution_order: ResolutionOrder,
+    required_symbols: Vec<String>,
+    undefined_roots: Vec<String>,
+    assertions: Vec<(AddrAssertion, String)>, // assertion, message when it fails
+    section_order: Vec<String>,
+    excluded_sections: Vec<(String, String)>, // (file pattern, section pattern)
+    included_sections: Vec<(String, String)>, // (file pattern, section pattern)
 }
 impl<'a> Default for LinkerContext<'a> {
     fn default() -> Self {
         Self {
             input_files: Default::default(),
+            archive_members: Vec::new(),
             output_sections: Default::default(),
             global_symbols: Default::default(),
-            current_addr: 0x400_000,
+            current_addr: BASE_ADDR,
             input_section_offsets: Default::default(),
+            input_section_outputs: Default::default(),
+            build_id: BuildIdKind::None,
+            dynamic_linker: None,
+            entry: EntryPoint::Default,
+            fill_byte: 0,
+            code_fill: FillKind::Zero,
+            trim_zero_tails: false,
+            pad_to_size: None,
+            no_merge: false,
+            traced_symbols: Default::default(),
+            trace_events: Default::default(),
+            gc_sections: false,
+            icf: false,
+            folded_sections: Default::default(),
+            discarded_sections: Default::default(),
+            segment_map: Vec::new(),
+            segment_flags_overrides: Vec::new(),
+            reject_wx: false,
+            exec_stack: false,
+            diagnostics: Diagnostics::default(),
+            keep_sections: false,
+            compress_debug_sections: CompressKind::None,
+            compressed_inputs: Default::default(),
+            section_align: Default::default(),
+            entry_align: None,
+            final_layout: None,
+            phase: Phase::AddFiles,
+            dropped_sections: Default::default(),
+            skipped_sections: Vec::new(),
+            local_symbols: Vec::new(),
+            export_dynamic: false,
+            dynamic_symbols: Vec::new(),
+            relocation_stats: RelocStats::default(),
+            applied_relocations: Default::default(),
+            keep_symbols: false,
+            sort_symbols: SymbolSort::Name,
+            resolution_order: ResolutionOrder::First,
+            required_symbols: Vec::new(),
+            undefined_roots: Vec::new(),
+            assertions: Vec::new(),
+            section_order: Vec::new(),
+            excluded_sections: Vec::new(),
+            included_sections: Vec::new(),
         }
     }
 }
 impl<'a> LinkerContext<'a> {
-    pub fn add_file(&mut self, filename: String, content: &'a [u8]) {
-        let (_, header) = parse_elf_header(content).unwrap();
-        let (_, sections) = parse_section_header_table(content, &header).unwrap();
-
-        let shstrtab_h = &sections[header.e_shstrndx as usize];
-        let shstrtab_data =
-            &content[shstrtab_h.offset as usize..(shstrtab_h.offset + shstrtab_h.size) as usize];
+    /// Name `path` as the program interpreter of a dynamically-linked executable, e.g.
+    /// `/lib/ld-linux-aarch64.so.1`: it goes in an `.interp` section in the first segment,
+    /// described by a `PT_INTERP` header.
+    pub fn dynamic_linker(&mut self, path: &str) -> &mut Self {
+        self.dynamic_linker = Some(path.to_string());
+        self
+    }
 
-        let symtab_h = sections.iter().find(|h| h.sh_type == SHT_SYMTAB).unwrap();
-        let strtab_h = &sections[symtab_h.link as usize];
-        let strtab_data =
-            &content[strtab_h.offset as usize..(strtab_h.offset + strtab_h.size) as usize];
+    /// Where the executable starts running; `_start` or `main` by default.
+    pub fn entry(&mut self, entry: EntryPoint) -> &mut Self {
+        self.entry = entry;
+        self
+    }
 
-        let (_, symbols) = parse_symbol_table(content, symtab_h).unwrap();
+    /// Start the executable at `addr`, overriding [`LinkerContext::entry`]. Unlike that, it
+    /// can be called after [`LinkerContext::finalize`], to write out variants of one link
+    /// that only differ in `e_entry`.
+    pub fn set_entry_address(&mut self, addr: u64) -> &mut Self {
+        self.entry = EntryPoint::Address(addr);
+        if let Some(layout) = &mut self.final_layout {
+            layout.entry_point = addr;
+        }
+        self
+    }
 
-        self.input_files.push(InputFile {
-            filename,
-            content,
-            header,
-            sections,
-            symbols,
-            shstrtab_data,
-            strtab_data,
-        });
+    /// Emit a `.note.gnu.build-id` section (and a `PT_NOTE` header for it) of the given kind.
+    pub fn build_id(&mut self, kind: BuildIdKind) -> &mut Self {
+        self.build_id = kind;
+        self
     }
 
-    pub fn layout_and_merge_sections(&mut self) {
-        // 1. Calculate sizes and create output sections
-        for file in &self.input_files {
-            for section in file.sections.iter() {
-                if section.sh_type == SHT_PROGBITS || section.sh_type == SHT_NOBITS {
-                    let name = get_section_name(file.shstrtab_data, section)
-                        .unwrap_or("")
-                        .to_string();
-                    if name.is_empty() {
-                        continue;
-                    }
-                    if name.starts_with(".rel") {
-                        panic!("Shouldn't happen this");
-                    }
+    /// Fill the gaps between sections and segments in the output, and between the inputs
+    /// merged into a section, with `byte` instead of zeros.
+    pub fn fill_byte(&mut self, byte: u8) -> &mut Self {
+        self.fill_byte = byte;
+        self
+    }
 
-                    // Only include allocatable sections (with SHF_ALLOC flag)
-                    const SHF_ALLOC: u64 = 0x2;
-                    if (section.flags & SHF_ALLOC) == 0 {
-                        continue; // Skip non-allocatable sections like .comment, .note.GNU-stack
-                    }
+    /// Pad the gaps between two executable sections with NOP or trap instructions instead
+    /// of the fill byte, so falling through the padding either carries on or faults rather
+    /// than running whatever the fill byte decodes to. Data is still padded with the fill
+    /// byte.
+    pub fn code_fill(&mut self, kind: FillKind) -> &mut Self {
+        self.code_fill = kind;
+        self
+    }
 
-                    let entry = self.output_sections.entry(name.clone()).or_insert_with(|| {
-                        let mut new_header = section.clone();
-                        new_header.size = 0;
-                        OutputSection {
-                            name,
-                            header: new_header,
-                            data: Vec::new(),
-                        }
-                    });
-                    entry.header.size += section.size;
-                }
-            }
-        }
+    /// Shrink the file by turning the run of zeros at the end of a segment's last writable
+    /// PROGBITS section (typically `.data`) into zero-initialized `.bss` memory. The loaded
+    /// image is the same; only `p_filesz` gets smaller.
+    pub fn trim_zero_tails(&mut self, trim_zero_tails: bool) -> &mut Self {
+        self.trim_zero_tails = trim_zero_tails;
+        self
+    }
 
-        // 2. Assign address and allocate data buffers
-        // Calculate header sizes to know where sections should start in virtual memory
-        let elf_header_size = 64u64;
-        let program_header_size = 56u64;
-        let num_program_headers = 2u64;
-        let headers_total_size = elf_header_size + (num_program_headers * program_header_size);
+    /// Pad every output file (ELF, flat binary and hex) with the fill byte to exactly
+    /// `size` bytes, e.g. to fill a ROM partition. Segments are left as they are; writing
+    /// fails when the contents are already larger.
+    pub fn pad_to_size(&mut self, size: u64) -> &mut Self {
+        self.pad_to_size = Some(size);
+        self
+    }
 
-        // Sections should start after the headers in virtual memory
-        self.current_addr += headers_total_size;
+    /// Keep every input section in its own output section, named `<name>(<file>:<index>)`,
+    /// instead of merging sections by name. Useful to see where each object's bytes landed.
+    pub fn no_merge(&mut self, no_merge: bool) -> &mut Self {
+        self.no_merge = no_merge;
+        self
+    }
 
-        // Sort sections in a logical order: .text, .rodata, .data, .bss
-        let mut sorted_sections: Vec<_> = self.output_sections.values_mut().collect();
-        sorted_sections.sort_by_key(|s| {
-            match s.name.as_str() {
-                ".text" => 0,
-                ".rodata" => 1,
-                ".data" => 2,
-                ".bss" => 3,
-                _ => 4, // Everything else after
-            }
-        });
+    /// Keep non-allocatable sections such as `.comment` and `.debug_*` in the output instead
+    /// of dropping them. They aren't loaded; their contents follow the loaded segments.
+    pub fn keep_sections(&mut self, keep_sections: bool) -> &mut Self {
+        self.keep_sections = keep_sections;
+        self
+    }
 
-        for section in sorted_sections {
-            let align = section.header.addralign as usize;
-            if align > 0 {
-                self.current_addr = (self.current_addr + align as u64 - 1) & !(align as u64 - 1); // Check
-            }
-            section.header.addr = self.current_addr;
-            section.data.resize(section.header.size as usize, 0);
-            self.current_addr += section.header.size;
+    /// How to store the kept `.debug_*` sections, see [`LinkerContext::keep_sections`].
+    /// Compressed sections get an `Elf64_Chdr` and `SHF_COMPRESSED`.
+    pub fn compress_debug_sections(&mut self, kind: CompressKind) -> &mut Self {
+        if cfg!(not(feature = "zlib")) && kind != CompressKind::None {
+            self.diagnostics.warn(
+                "elkr is built without the `zlib` feature, debug sections are left uncompressed"
+                    .to_string(),
+            );
         }
+        self.compress_debug_sections = kind;
+        self
+    }
 
-        // 3. Copy data from input files to output sections
-        let mut current_offsets: HashMap<String, u64> = HashMap::new(); // Global across all files
-        for (file_idx, file) in self.input_files.iter().enumerate() {
-            println!("Processing file {} for data copying", file.filename);
-            for (section_idx, section) in file.sections.iter().enumerate() {
-                if section.sh_type == SHT_PROGBITS {
-                    let name = get_section_name(file.shstrtab_data, section)
-                        .unwrap_or("")
-                        .to_string();
-                    if let Some(output_section) = self.output_sections.get_mut(&name) {
-                        let current_offset = current_offsets.entry(name.clone()).or_insert(0);
+    /// Export the defined global symbols through a `.dynsym`, with its `.dynstr` and
+    /// `.hash`, and a `.dynamic` pointing at them, described by a `PT_DYNAMIC` header. There
+    /// are no dynamic relocations or needed libraries: this only lets other images look up
+    /// symbols in a static or preloaded one.
+    pub fn export_dynamic(&mut self, export: bool) -> &mut Self {
+        self.export_dynamic = export;
+        self
+    }
 
-                        println!(
-                            "  Section {} (idx {}) -> output section {} at offset 0x{:x}",
-                            name, section_idx, name, *current_offset
-                        );
+    /// Emit a `.symtab` with the resolved global symbols and the inputs' local symbols, and
+    /// the section header table it needs, so tools like `nm` and debuggers can name
+    /// addresses in the output.
+    pub fn keep_symbols(&mut self, keep_symbols: bool) -> &mut Self {
+        self.keep_symbols = keep_symbols;
+        self
+    }
 
-                        // Record where this input section starts in the output section
-                        self.input_section_offsets
-                            .insert((file_idx, section_idx), *current_offset);
+    /// How symbols are ordered in the `.symtab` emitted with
+    /// [`LinkerContext::keep_symbols`]. Local symbols always come before the others.
+    pub fn sort_symbols(&mut self, sort: SymbolSort) -> &mut Self {
+        self.sort_symbols = sort;
+        self
+    }
 
-                        let start = *current_offset as usize;
-                        let end = start + section.size as usize;
-                        let data = &file.content
-                            [section.offset as usize..(section.offset + section.size) as usize];
-                        output_section.data[start..end].copy_from_slice(data);
-                        *current_offset += section.size;
-                    }
-                }
-            }
-        }
+    /// What to do when more than one input defines the same global symbol.
+    pub fn resolution_order(&mut self, order: ResolutionOrder) -> &mut Self {
+        self.resolution_order = order;
+        self
     }
 
-    pub fn resolve_symbols(&mut self) {
-        println!("=== Symbol Resolution ===");
-        for (file_idx, file) in self.input_files.iter().enumerate() {
-            println!("Processing file: {}", file.filename);
-            for symbol in &file.symbols {
-                if symbol.get_bind() == 1 {
-                    // GLOBAL SYMBOL
+    /// Drop allocatable sections that can't be reached through relocations from the entry
+    /// point or from sections that are always kept (notes, unwind tables, init/fini arrays).
+    /// An entry given as an [`EntryPoint::Address`] or [`EntryPoint::OffsetFromBase`] isn't
+    /// known to be in any section, so it roots nothing: keep the code it points at with
+    /// [`LinkerContext::keep_undefined`], or get a warning.
+    pub fn gc_sections(&mut self, gc_sections: bool) -> &mut Self {
+        self.gc_sections = gc_sections;
+        self
+    }
 
-                    let name = get_symbol_name(file.strtab_data, symbol).unwrap_or("");
-                    println!(
-                        "  Symbol: {} (value: 0x{:x}, shndx: {})",
-                        name, symbol.value, symbol.shndx
-                    );
-                    if name.is_empty() || self.global_symbols.contains_key(name) {
-                        continue;
-                    }
-                    if symbol.shndx > 0 && (symbol.shndx as usize) < file.sections.len() {
-                        let section_of_symbol = &file.sections[symbol.shndx as usize];
-                        let section_name =
-                            get_section_name(file.shstrtab_data, section_of_symbol).unwrap();
+    /// Identical code folding: keep one copy of read-only code sections with the same
+    /// contents and relocation targets, e.g. template instantiations that compile to the
+    /// same instructions. Symbols in the other copies point at the one that is kept.
+    pub fn icf(&mut self, icf: bool) -> &mut Self {
+        self.icf = icf;
+        self
+    }
 
-                        println!("    Section: {}", section_name);
+    /// Load sections in the given segments, in order, instead of splitting them into an
+    /// executable code segment and a writable data segment. A section goes to the first
+    /// segment with a matching pattern; unmatched allocatable sections still go to the
+    /// default code or data segment, placed after the configured ones.
+    pub fn segment_map(&mut self, segments: Vec<SegmentSpec>) -> &mut Self {
+        self.segment_map = segments;
+        self
+    }
 
-                        if let Some(output_sec) = self.output_sections.get(section_name) {
-                            // Get the offset of this input section within the output section
-                            let input_section_offset = self
-                                .input_section_offsets
-                                .get(&(file_idx, symbol.shndx as usize))
-                                .unwrap_or(&0);
+    /// Set the `p_flags` of the segment that loads output section `section`, replacing the
+    /// automatic R+X or R+W (or the flags from the segment map), e.g. R+W+X for a
+    /// self-modifying bootloader.
+    pub fn segment_flags_override(&mut self, section: &str, flags: u32) -> &mut Self {
+        self.segment_flags_overrides
+            .push((section.to_string(), flags));
+        self
+    }
 
-                            let final_addr =
-                                output_sec.header.addr + input_section_offset + symbol.value;
-                            println!(
-                                "    Final address: 0x{:x} (section base: 0x{:x} + input offset: 0x{:x} + symbol offset: 0x{:x})",
-                                final_addr,
-                                output_sec.header.addr,
-                                input_section_offset,
-                                symbol.value
-                            );
-                            self.global_symbols.insert(
-                                name,
-                                GlobalSymbol {
-                                    _name: name,
-                                    final_addr,
-                                },
-                            );
-                        }
-                    }
-                }
-            }
-        }
+    /// Enforce W^X: fail the link if any loaded segment would be both writable and
+    /// executable.
+    pub fn reject_wx(&mut self, reject_wx: bool) -> &mut Self {
+        self.reject_wx = reject_wx;
+        self
     }
 
-    pub fn apply_relocations(&mut self) {
-        for (file_idx, file) in self.input_files.iter().enumerate() {
-            for section in file.sections.iter().filter(|s| s.sh_type == SHT_RELA) {
-                let target_sec_idx = section.info as usize;
-                println!("the target section index is {target_sec_idx}");
-                let target_sec = &file.sections[target_sec_idx];
-                let target_sec_name = get_section_name(file.shstrtab_data, target_sec)
-                    .unwrap()
-                    .to_string();
+    /// Place the named output sections in this order, ahead of the default
+    /// .text/.rodata/.data/.bss priority; unlisted sections follow in the default order.
+    /// A segment still holds its sections contiguously, so segments are ordered by their
+    /// first listed section.
+    pub fn section_order(&mut self, order: Vec<String>) -> &mut Self {
+        self.section_order = order;
+        self
+    }
 
-                if let Some(output_section) = self.output_sections.get_mut(&target_sec_name) {
-                    // 传递重定位表section本身，而不是目标section
-                    let (_, relocations) = parse_rela_table(file.content, section).unwrap();
+    /// Align the output section `name` to at least `align` bytes, even if its inputs ask
+    /// for less. `align` must be a power of two.
+    pub fn set_section_align(&mut self, name: &str, align: u64) -> &mut Self {
+        assert!(
+            align.is_power_of_two(),
+            "section alignment must be a power of two"
+        );
+        self.section_align.insert(name.to_string(), align);
+        self
+    }
 
-                    for rela in relocations {
-                        let sym_index = rela.get_symbol_index() as usize;
-                        let symbol = &file.symbols[sym_index];
-                        let sym_name = get_symbol_name(file.strtab_data, symbol).unwrap();
+    /// Place the entry point at an `align`-byte boundary, padding the output section
+    /// before the input section that defines it. Without an entry symbol the start of the
+    /// first `.text` input is aligned instead. A fixed [`EntryPoint::Address`] or
+    /// [`EntryPoint::OffsetFromBase`] is left alone. If `align` isn't a power of two, the
+    /// layout fails with [`LinkerError::BadAlignment`] for the section holding the entry.
+    pub fn align_entry(&mut self, align: u64) -> &mut Self {
+        self.entry_align = Some(align);
+        self
+    }
 
-                        println!(
-                            "  Relocation: {} type {} offset 0x{:x} addend {}",
-                            sym_name,
-                            rela.get_type(),
-                            rela.offset,
-                            rela.addend
-                        );
+    /// Leave sections matching `section` out of the input files matching `file`, as if
+    /// garbage-collected. Both are patterns where `*` matches any run of characters.
+    pub fn exclude_section(&mut self, file: &str, section: &str) -> &mut Self {
+        self.excluded_sections
+            .push((file.to_string(), section.to_string()));
+        self
+    }
 
-                        if let Some(global_sym) = self.global_symbols.get(sym_name) {
-                            let s = global_sym.final_addr;
+    /// Only take sections matching `section` from the input files matching `file`; their
+    /// other sections are left out as with [`LinkerContext::exclude_section`]. Includes
+    /// for the same files add up. Files no include applies to are unaffected.
+    pub fn include_section(&mut self, file: &str, section: &str) -> &mut Self {
+        self.included_sections
+            .push((file.to_string(), section.to_string()));
+        self
+    }
 
-                            // P is the address of the place being relocated
-                            // Need to account for where this input section is within the output section
-                            let input_section_offset = self
-                                .input_section_offsets
-                                .get(&(file_idx, target_sec_idx))
-                                .unwrap_or(&0);
-                            let p = output_section.header.addr + input_section_offset + rela.offset;
-                            let a = rela.addend as u64;
+    /// Treat `name` as referenced, like ld's `-u`: an archive member defining it is linked
+    /// in, see [`LinkerContext::add_archive`], and with [`LinkerContext::gc_sections`] the
+    /// section defining it is kept along with everything it refers to.
+    pub fn keep_undefined(&mut self, name: &str) -> &mut Self {
+        self.undefined_roots.push(name.to_string());
+        self
+    }
 
-                            println!(
-                                "    S (symbol addr) = 0x{:x}, P (patch location) = 0x{:x} (section: 0x{:x} + input_offset: 0x{:x} + rela_offset: 0x{:x}), A (addend) = 0x{:x}",
-                                s,
-                                p,
-                                output_section.header.addr,
-                                input_section_offset,
-                                rela.offset,
-                                a
-                            );
+    /// Fail symbol resolution with `message` unless `assertion` holds for the resolved
+    /// addresses. A symbol no input defines fails the assertion.
+    pub fn assert_address(&mut self, assertion: AddrAssertion, message: &str) -> &mut Self {
+        self.assertions.push((assertion, message.to_string()));
+        self
+    }
 
-                            if rela.get_type() == R_AARCH64_CALL26 {
-                                let offset = (s + a).wrapping_sub(p);
-                                // The immediate is 26 bits, right-shifted by 2
-                                let imm26 = (offset as i64 >> 2) & 0x03FFFFFF;
+    /// Fail symbol resolution unless some input defines the global symbol `name`, whether
+    /// or not anything refers to it.
+    pub fn require_defined(&mut self, name: &str) -> &mut Self {
+        self.required_symbols.push(name.to_string());
+        self
+    }
 
-                                println!(
-                                    "    CALL26: offset = 0x{:x}, imm26 = 0x{:x}",
-                                    offset, imm26
-                                );
+    /// Record (and print) every definition of `name` seen during symbol resolution and
+    /// every relocation against it, see [`LinkerContext::trace_events`].
+    pub fn trace_symbol(&mut self, name: &str) -> &mut Self {
+        self.traced_symbols.insert(name.to_string());
+        self
+    }
 
-                                // Read the original instruction - need to account for input section offset
-                                let reloc_offset_in_buffer =
-                                    (input_section_offset + rela.offset) as usize;
-                                let mut instruction = u32::from_le_bytes(
-                                    output_section.data
-                                        [reloc_offset_in_buffer..reloc_offset_in_buffer + 4]
-                                        .try_into()
-                                        .unwrap(),
-                                );
-                                println!("    Original instruction: 0x{:x}", instruction);
-                                // Clear the immediate field and patch in the new value
-                                instruction &= 0xFC000000;
-                                instruction |= imm26 as u32;
-                                println!("    Patched instruction: 0x{:x}", instruction);
-
-                                // Write the patched instruction back
-                                output_section.data
-                                    [reloc_offset_in_buffer..reloc_offset_in_buffer + 4]
-                                    .copy_from_slice(&instruction.to_le_bytes());
-                            } else if rela.get_type() == R_AARCH64_PREL32 {
-                                // PC-relative 32-bit: S + A - P
-                                let value = (s + a).wrapping_sub(p) as u32;
-
-                                println!("    PREL32: value = 0x{:x}", value);
-
-                                // Write the 32-bit value directly - need to account for input section offset
-                                let reloc_offset_in_buffer =
-                                    (input_section_offset + rela.offset) as usize;
-                                output_section.data
-                                    [reloc_offset_in_buffer..reloc_offset_in_buffer + 4]
-                                    .copy_from_slice(&value.to_le_bytes());
-                            }
-                        }
+    /// Whether warnings are printed, dropped, or fail the link. Warnings reported before
+    /// the policy is set are kept.
+    pub fn warning_policy(&mut self, policy: WarningPolicy) -> &mut Self {
+        self.diagnostics.set_policy(policy);
+        self
+    }
+
+    /// Warnings reported so far.
+    pub fn warnings(&self) -> &[String] {
+        self.diagnostics.warnings()
+    }
+
+    /// Events recorded so far for the symbols passed to [`LinkerContext::trace_symbol`].
+    pub fn trace_events(&self) -> &[TraceEvent] {
+        &self.trace_events
+    }
+
+    /// The input sections layout left out, and why. Relocation, symbol and string tables
+    /// are consumed by the link itself and aren't listed.
+    pub fn skipped_sections(&self) -> &[SkippedSection] {
+        &self.skipped_sections
+    }
+
+    /// How many relocations [`LinkerContext::apply_relocations`] applied, and how many it
+    /// left unpatched, by type. Relocations against dropped or folded sections aren't counted.
+    pub fn relocation_stats(&self) -> &RelocStats {
+        &self.relocation_stats
+    }
+
+    pub fn add_file(&mut self, filename: String, content: &'a [u8]) -> Result<(), LinkerError> {
+        self.check_adding_inputs("add_file")?;
+        let input = parse_input_file(filename, content)?;
+        self.push_input(input)
+    }
+
+    /// Adds the members of an archive, as `(name, contents)` pairs like those of
+    /// [`read_archive_members`](crate::archive::read_archive_members). Like with ld, only
+    /// the members the link needs are linked in: those defining a symbol the other inputs
+    /// refer to but don't define, and then those the extracted members need in turn.
+    /// Members are extracted when layout starts, so an archive may be added before the
+    /// objects that need it.
+    pub fn add_archive(&mut self, members: &'a [(String, Vec<u8>)]) -> Result<(), LinkerError> {
+        self.check_adding_inputs("add_archive")?;
+        // Every member is checked up front, so a malformed one is reported whether or not
+        // it is needed
+        let mut parsed = Vec::with_capacity(members.len());
+        for (name, content) in members {
+            let member = parse_input_file(name.clone(), content)?;
+            check_input_indices(&member)?;
+            parsed.push(member);
+        }
+        self.archive_members.extend(parsed);
+        Ok(())
+    }
+
+    /// Adds an input once its indices have been checked, see [`InputFile::check_indices`].
+    fn push_input(&mut self, input: InputFile<'a>) -> Result<(), LinkerError> {
+        check_input_indices(&input)?;
+        self.input_files.push(input);
+        Ok(())
+    }
+
+    /// Moves the archive members that define an undefined symbol to the inputs, until
+    /// none does, see [`LinkerContext::add_archive`].
+    fn extract_archive_members(&mut self) {
+        loop {
+            let mut defined = HashSet::new();
+            let mut referenced = Vec::new();
+            for file in &self.input_files {
+                for (symbol, name) in file.symbols.iter().zip(&file.symbol_names) {
+                    let name: &str = name;
+                    if is_global_definition(symbol, name) {
+                        defined.insert(name);
+                    } else if symbol.get_bind() == STB_GLOBAL && !name.is_empty() {
+                        // Weak references don't pull members in
+                        referenced.push(name);
                     }
                 }
             }
+            referenced.extend(self.undefined_roots.iter().map(String::as_str));
+            // An entry point nothing defines yet is looked for in the archives too
+            let entry_symbols = self.entry_symbols();
+            if !entry_symbols.iter().any(|name| defined.contains(name)) {
+                referenced.extend(entry_symbols);
+            }
+            let undefined: HashSet<&str> = referenced
+                .into_iter()
+                .filter(|name| !defined.contains(name))
+                .collect();
+            let needed = self.archive_members.iter().position(|member| {
+                member
+                    .symbols
+                    .iter()
+                    .zip(&member.symbol_names)
+                    .any(|(symbol, name)| {
+                        is_global_definition(symbol, name) && undefined.contains(&**name)
+                    })
+            });
+            let Some(idx) = needed else {
+                return;
+            };
+            let member = self.archive_members.remove(idx);
+            println!("Extracting archive member {}", member.filename);
+            self.input_files.push(member);
         }
     }
 
-    pub fn write_executable(&self, path: &str) -> io::Result<()> {
-        let mut file = fs::File::create(path)?;
+    /// Adds an object that is already in memory as ELF structures, e.g. from a compiler,
+    /// without serializing it to bytes first. Its sections are linked from their own
+    /// buffers.
+    pub fn add_parsed(&mut self, object: ParsedObject) -> Result<(), LinkerError> {
+        self.check_adding_inputs("add_parsed")?;
+        let malformed = |reason: &str| LinkerError::Malformed {
+            file: object.filename.clone(),
+            reason: reason.to_string(),
+        };
+        if object.section_names.len() != object.sections.len()
+            || object.section_data.len() != object.sections.len()
+        {
+            return Err(malformed(
+                "section names and contents don't match the section headers",
+            ));
+        }
+        check_input_header(&object.filename, &object.header)?;
+        if object.symbol_names.len() != object.symbols.len() {
+            return Err(malformed("symbol names don't match the symbols"));
+        }
+        if object
+            .symbols
+            .first()
+            .is_some_and(|symbol| !symbol.is_null())
+        {
+            return Err(malformed(
+                "the first symbol table entry is not the null symbol",
+            ));
+        }
+        if let Some(idx) = object
+            .sections
+            .iter()
+            .enumerate()
+            .position(|(idx, section)| {
+                section.sh_type != SHT_NOBITS
+                    && object.section_data[idx].len() as u64 != section.size
+            })
+        {
+            return Err(malformed(&format!(
+                "section {} has {} bytes of contents, but a size of {}",
+                object.section_names[idx],
+                object.section_data[idx].len(),
+                object.sections[idx].size
+            )));
+        }
+        self.push_input(InputFile {
+            filename: object.filename,
+            content: &[],
+            header: object.header,
+            sections: object.sections,
+            symbols: object.symbols,
+            section_names: object.section_names.into_iter().map(Cow::Owned).collect(),
+            symbol_names: object.symbol_names.into_iter().map(Cow::Owned).collect(),
+            section_data: object.section_data,
+            decompressed_sections: HashMap::new(),
+        })
+    }
 
-        // Debug: Print global symbols
-        println!("Global symbols:");
-        for (name, sym) in &self.global_symbols {
-            println!("  {} -> 0x{:x}", name, sym.final_addr);
+    pub fn layout_and_merge_sections(&mut self) -> Result<(), LinkerError> {
+        self.check_phase(Phase::AddFiles, Phase::Layout)?;
+        self.extract_archive_members();
+        if self.gc_sections && self.entry_symbols().is_empty() && self.undefined_roots.is_empty() {
+            self.diagnostics.warn(
+                "the entry point is an address, so gc_sections keeps no code for it".to_string(),
+            );
         }
+        let live_sections = self.gc_sections.then(|| self.live_sections());
+        let mut kept_copies: HashMap<FoldKey, (usize, usize)> = HashMap::new();
+        let entry_input = self.entry_align.and_then(|_| self.entry_input());
+        let mut entry_padding = 0;
 
-        let entry_point = self
-            .global_symbols
-            .get("_start")
-            .or_else(|| self.global_symbols.get("main"))
-            .unwrap()
-            .final_addr;
-        println!("Entry point: 0x{:x}", entry_point);
+        // The stack is only left non-executable if every input has a non-executable
+        // .note.GNU-stack; older objects without one are assumed to need it executable
+        self.exec_stack = false;
+        for file in &self.input_files {
+            let note = file
+                .section_names
+                .iter()
+                .position(|name| name == GNU_STACK_SECTION);
+            let reason = match note {
+                None => "has no .note.GNU-stack section",
+                Some(idx) if file.sections[idx].flags & SHF_EXECINSTR != 0 => {
+                    "marks .note.GNU-stack executable"
+                }
+                Some(_) => continue,
+            };
+            self.diagnostics.warn(format!(
+                "{}: {reason}, which makes the stack executable",
+                file.filename
+            ));
+            self.exec_stack = true;
+        }
 
-        let base_addr = 0x400_000;
-        let page_size = 0x1000;
+        // Inputs are laid out in order, except that prioritized constructors and destructors
+        // go first, in ascending priority. The sort is stable, so it moves nothing else.
+        // Sizing and copying both walk this order, so they agree on the padding.
+        let mut inputs: Vec<(usize, usize)> = self
+            .input_files
+            .iter()
+            .enumerate()
+            .flat_map(|(file_idx, file)| (0..file.sections.len()).map(move |idx| (file_idx, idx)))
+            .collect();
+        inputs.sort_by_key(|&(file_idx, section_idx)| {
+            let priority = init_priority(&self.input_files[file_idx].section_names[section_idx])
+                .map(|(_, priority)| priority);
+            (priority.is_none(), priority)
+        });
+        // 1. Calculate sizes and create output sections
+        for &(file_idx, section_idx) in &inputs {
+            let file = &self.input_files[file_idx];
+            let section = &file.sections[section_idx];
+            let skipped = |reason| SkippedSection {
+                file: file.filename.clone(),
+                name: file.section_names[section_idx].to_string(),
+                reason,
+            };
+            if !is_output_section_type(section.sh_type)
+                && !matches!(
+                    section.sh_type,
+                    SHT_NULL | SHT_SYMTAB | SHT_SYMTAB_SHNDX | SHT_STRTAB | SHT_REL | SHT_RELA
+                )
+            {
+                self.skipped_sections
+                    .push(skipped(SkipReason::NotProgbitsOrNobits));
+            }
+            if is_output_section_type(section.sh_type) {
+                let name = file.section_names[section_idx].to_string();
+                if name.is_empty() {
+                    self.skipped_sections.push(skipped(SkipReason::EmptyName));
+                    continue;
+                }
+                if name.starts_with(".rel") {
+                    self.diagnostics.warn(format!(
+                        "{}: section {name} has type {}, but its name is reserved for relocations, so it is left out",
+                        file.filename, section.sh_type
+                    ));
+                    self.skipped_sections
+                        .push(skipped(SkipReason::RelocationName));
+                    continue;
+                }
+                // Only read for the stack permissions, never output
+                if name == GNU_STACK_SECTION {
+                    continue;
+                }
 
-        // === Step 1. Assign sections to segments ===
-        let mut code_sections = Vec::new();
-        let mut data_sections = Vec::new();
-        let mut sorted_sections: Vec<_> = self.output_sections.values().collect();
-        sorted_sections.sort_by_key(|s| s.header.addr);
+                // Only include allocatable sections (with SHF_ALLOC flag)
+                if (section.flags & SHF_ALLOC) == 0 && !self.keep_sections {
+                    // Skip non-allocatable sections like .comment, .note.GNU-stack
+                    self.dropped_sections.insert((file_idx, section_idx), name);
+                    self.skipped_sections
+                        .push(skipped(SkipReason::NotAllocatable));
+                    continue;
+                }
 
-        println!("Output sections:");
-        for sec in &sorted_sections {
-            println!(
-                "  {} @ 0x{:x}, size: 0x{:x}, flags: 0x{:x}",
-                sec.name, sec.header.addr, sec.header.size, sec.header.flags
+                if self.is_filtered_out(&file.filename, &name) {
+                    println!("Excluding section {} from {}", name, file.filename);
+                    self.discarded_sections
+                        .insert((file_idx, section_idx), name);
+                    self.skipped_sections.push(skipped(SkipReason::Excluded));
+                    continue;
+                }
+
+                // Non-allocatable sections aren't roots or targets of reachability
+                if let Some(live) = &live_sections
+                    && section.flags & SHF_ALLOC != 0
+                    && !live.contains(&(file_idx, section_idx))
+                {
+                    println!(
+                        "Discarding unreferenced section {} from {}",
+                        name, file.filename
+                    );
+                    self.discarded_sections
+                        .insert((file_idx, section_idx), name);
+                    self.skipped_sections
+                        .push(skipped(SkipReason::GarbageCollected));
+                    continue;
+                }
+
+                if self.icf
+                    && let Some(key) = file.fold_key(file_idx, section_idx)
+                {
+                    if let Some(&kept) = kept_copies.get(&key) {
+                        println!(
+                            "Folding section {} from {} into an identical copy",
+                            name, file.filename
+                        );
+                        let output_name = self.input_section_outputs[&kept].clone();
+                        self.input_section_outputs
+                            .insert((file_idx, section_idx), output_name);
+                        self.folded_sections.insert((file_idx, section_idx), kept);
+                        continue;
+                    }
+                    kept_copies.insert(key, (file_idx, section_idx));
+                }
+
+                // Prioritized constructors and destructors are merged into the plain arrays
+                let name = init_priority(&name).map_or(name, |(output, _)| output.to_string());
+                let name = if self.no_merge {
+                    format!("{}({}:{})", name, file.filename, section_idx)
+                } else {
+                    name
+                };
+                self.input_section_outputs
+                    .insert((file_idx, section_idx), name.clone());
+                // Relocatable inputs are placed by the linker; an address in one is
+                // most likely meant as a placement this link won't honor
+                if section.addr != 0 {
+                    self.diagnostics.warn(format!(
+                        "{}: section {} has address {:#x}, which is ignored",
+                        file.filename, file.section_names[section_idx], section.addr
+                    ));
+                }
+
+                let is_new = !self.output_sections.contains_key(&name);
+                let decompressed = file.decompressed_sections.get(&section_idx);
+                if decompressed.is_some() {
+                    self.compressed_inputs.insert(name.clone());
+                }
+                let entry = self.output_sections.entry(name.clone()).or_insert_with(|| {
+                    let mut new_header = section.clone();
+                    new_header.size = 0;
+                    if let Some((addralign, _)) = decompressed {
+                        new_header.flags &= !SHF_COMPRESSED;
+                        new_header.addralign = *addralign;
+                    }
+                    OutputSection {
+                        name,
+                        header: new_header,
+                        data: Vec::new(),
+                    }
+                });
+                // Merged inputs must agree on how the section is loaded. On a mismatch
+                // take the most permissive combination, so e.g. writable data never
+                // ends up in a read-only segment.
+                let flags = section.flags & MERGE_FLAGS_MASK;
+                let merged_flags = entry.header.flags & MERGE_FLAGS_MASK;
+                if !is_new && (flags != merged_flags || section.sh_type != entry.header.sh_type) {
+                    self.diagnostics.warn(format!(
+                        "{}: section {} has type {} and flags {:#x}, but earlier inputs have type {} and flags {:#x}",
+                        file.filename,
+                        entry.name,
+                        section.sh_type,
+                        flags,
+                        entry.header.sh_type,
+                        merged_flags
+                    ));
+                    entry.header.flags |= flags;
+                    if section.sh_type == SHT_PROGBITS {
+                        // Zero-filled NOBITS input merges fine into file-backed data
+                        entry.header.sh_type = SHT_PROGBITS;
+                    }
+                }
+                // The output section is as aligned as the strictest of its inputs,
+                // whichever file comes first, and never declares an alignment of 0
+                let align = file.section_align(section_idx);
+                if !align.is_power_of_two() {
+                    return Err(LinkerError::BadAlignment {
+                        section: entry.name.clone(),
+                        align,
+                    });
+                }
+                entry.header.addralign = entry.header.addralign.max(align);
+                // Each input starts on its own alignment within the output section
+                entry.header.size = align_up(entry.header.size, align).ok_or_else(|| {
+                    LinkerError::AddressOverflow {
+                        section: entry.name.clone(),
+                    }
+                })?;
+                // Pad up to the input defining the entry, so that it lands on the
+                // boundary once the output section is at least as aligned
+                if let Some(entry_align) = self.entry_align
+                    && let Some((_, value)) =
+                        entry_input.filter(|&(key, _)| key == (file_idx, section_idx))
+                {
+                    if !entry_align.is_power_of_two() {
+                        return Err(LinkerError::BadAlignment {
+                            section: entry.name.clone(),
+                            align: entry_align,
+                        });
+                    }
+                    entry_padding = (entry.header.size + value).next_multiple_of(entry_align)
+                        - (entry.header.size + value);
+                    entry.header.addralign = entry.header.addralign.max(entry_align);
+                    entry.header.size += entry_padding;
+                }
+                entry.header.size +=
+                    decompressed.map_or(section.size, |(_, data)| data.len() as u64);
+            }
+        }
+
+        if let Some(path) = &self.dynamic_linker {
+            let mut data = path.as_bytes().to_vec();
+            data.push(0);
+            self.output_sections.insert(
+                INTERP_SECTION.to_string(),
+                OutputSection {
+                    name: INTERP_SECTION.to_string(),
+                    header: SectionHeader {
+                        name_offset: 0,
+                        sh_type: SHT_PROGBITS,
+                        flags: SHF_ALLOC,
+                        addr: 0,
+                        offset: 0,
+                        size: data.len() as u64,
+                        link: 0,
+                        info: 0,
+                        addralign: 1,
+                        entsize: 0,
+                    },
+                    data,
+                },
+            );
+        }
+        if self.build_id != BuildIdKind::None {
+            // The id itself is filled in once the section contents are final
+            let data = build_id_note(&vec![0; self.build_id.len()]);
+            self.output_sections.insert(
+                BUILD_ID_SECTION.to_string(),
+                OutputSection {
+                    name: BUILD_ID_SECTION.to_string(),
+                    header: SectionHeader {
+                        name_offset: 0,
+                        sh_type: SHT_NOTE,
+                        flags: SHF_ALLOC,
+                        addr: 0,
+                        offset: 0,
+                        size: data.len() as u64,
+                        link: 0,
+                        info: 0,
+                        addralign: 4,
+                        entsize: 0,
+                    },
+                    data,
+                },
             );
         }
 
-        for sec in sorted_sections {
-            // SHF_EXECINSTR flag is 0x4
-            if sec.header.flags & 0x4 != 0 {
-                code_sections.push(sec);
-            } else {
-                data_sections.push(sec);
-            }
+        if self.export_dynamic {
+            self.add_dynamic_sections();
         }
 
-        // === Step 2. Calculate layout ===
-        let elf_header_size = 64u64;
-        let program_header_size = 56u64;
-        let num_program_headers = 2u64;
-        let headers_total_size = elf_header_size + (num_program_headers * program_header_size);
+        // 2. Assign address and allocate data buffers
+        // Calculate header sizes to know where sections should start in virtual memory
+        let num_program_headers = self.program_header_count();
+        let headers_total_size = ELF_HEADER_SIZE + (num_program_headers * PROGRAM_HEADER_SIZE);
 
-        println!("Layout calculations:");
-        println!("  Headers total size: 0x{:x}", headers_total_size);
+        // Sections should start after the headers in virtual memory
+        self.current_addr += headers_total_size;
 
-        // Code Segment Layout
-        let code_segment_start_vaddr = base_addr;
-        let code_segment_file_offset = 0u64;
-        let code_segment_filesz =
-            headers_total_size + code_sections.iter().map(|s| s.header.size).sum::<u64>();
-        let code_segment_memsz = code_segment_filesz;
+        // Sort sections in a logical order: by segment, then within each segment
+        // notes, .text.hot, .text, .text.unlikely, .rodata, .tdata, .tbss, .data, .bss,
+        // with orphans next to their kind, see section_rank
+        let segment_of: HashMap<String, usize> = self
+            .output_sections
+            .values()
+            .map(|s| (s.name.clone(), self.segment_index(s)))
+            .collect();
+        // The headers are loaded by the first segment, which sections are laid out after
+        let segment_order = self.loaded_segments();
+        let mut current_segment = segment_order[0];
+        let mut relro_started = false;
+        let mut relro_ended = false;
+        let order_of: HashMap<String, usize> = self
+            .output_sections
+            .keys()
+            .map(|name| (name.clone(), self.section_order_index(name)))
+            .collect();
+        let mut sorted_sections: Vec<_> = self.output_sections.values_mut().collect();
+        sorted_sections.sort_by_cached_key(|section| {
+            let segment = segment_of[&section.name];
+            (
+                segment_order.iter().position(|&s| s == segment),
+                segment,
+                order_of[&section.name],
+                section_rank(section),
+                section.name.clone(),
+            )
+        });
 
-        let code_segment_file_offset_aligned = align_up(code_segment_file_offset, page_size);
-        let code_segment_start_vaddr_aligned = align_up(code_segment_start_vaddr, page_size);
+        for section in sorted_sections {
+            // Kept non-allocatable sections aren't loaded, so they get no address
+            if section.header.flags & SHF_ALLOC == 0 {
+                section.data.resize(section.header.size as usize, 0);
+                continue;
+            }
+            let overflow = || LinkerError::AddressOverflow {
+                section: section.name.clone(),
+            };
+            // Every segment is loaded on its own page, so its addresses start there too
+            if segment_of[&section.name] != current_segment {
+                self.current_addr = align_up(self.current_addr, PAGE_SIZE).ok_or_else(overflow)?;
+                current_segment = segment_of[&section.name];
+            }
+            // The loader write-protects whole pages, so RELRO starts on one, and what
+            // follows it starts on the next
+            if is_relro(section) && !relro_started {
+                self.current_addr = align_up(self.current_addr, PAGE_SIZE).ok_or_else(overflow)?;
+                relro_started = true;
+            } else if !is_relro(section) && relro_started && !relro_ended {
+                self.current_addr = align_up(self.current_addr, PAGE_SIZE).ok_or_else(overflow)?;
+                relro_ended = true;
+            }
+            if let Some(&align) = self.section_align.get(&section.name) {
+                section.header.addralign = section.header.addralign.max(align);
+            }
+            let align = section.header.addralign.max(1);
+            if !align.is_power_of_two() {
+                return Err(LinkerError::BadAlignment {
+                    section: section.name.clone(),
+                    align,
+                });
+            }
+            self.current_addr = align_up(self.current_addr, align).ok_or_else(overflow)?;
+            section.header.addr = self.current_addr;
+            section.data.resize(section.header.size as usize, 0);
+            let end = self
+                .current_addr
+                .checked_add(section.header.size)
+                .ok_or_else(overflow)?;
+            // The end of the image is page-aligned when laying out segments and the file,
+            // so checking it here covers those too
+            align_up(end, PAGE_SIZE).ok_or_else(overflow)?;
+            // .tbss only describes the zeroed tail of each thread's TLS block; it takes
+            // no room in the image, so the sections after it may overlap its addresses
+            if !is_tbss(section) {
+                self.current_addr = end;
+            }
+        }
 
-        println!(
-            "  Code segment file offset: 0x{:x} -> aligned: 0x{:x}",
-            code_segment_file_offset, code_segment_file_offset_aligned
-        );
-        println!(
-            "  Code segment vaddr: 0x{:x} -> aligned: 0x{:x}",
-            code_segment_start_vaddr, code_segment_start_vaddr_aligned
-        );
-        println!("  Code segment file size: 0x{:x}", code_segment_filesz);
+        // 3. Copy data from input files to output sections
+        let mut current_offsets: HashMap<String, u64> = HashMap::new(); // Global across all files
+        for (file_idx, section_idx) in inputs {
+            let file = &self.input_files[file_idx];
+            let section = &file.sections[section_idx];
+            if is_output_section_type(section.sh_type) {
+                let name = &file.section_names[section_idx];
+                let Some(output_name) = self.input_section_outputs.get(&(file_idx, section_idx))
+                else {
+                    continue;
+                };
+                // A folded section shares the place of the copy that was kept, which
+                // comes earlier and has been copied already
+                if let Some(kept) = self.folded_sections.get(&(file_idx, section_idx)) {
+                    let offset = self.input_section_offsets[kept];
+                    self.input_section_offsets
+                        .insert((file_idx, section_idx), offset);
+                    continue;
+                }
+                if let Some(output_section) = self.output_sections.get_mut(output_name) {
+                    let current_offset = current_offsets.entry(output_name.clone()).or_insert(0);
+                    let gap_start = *current_offset;
+                    // Step 1 sized the section with this padding, so it can't overflow
+                    *current_offset =
+                        current_offset.next_multiple_of(file.section_align(section_idx));
+                    if entry_input.is_some_and(|(key, _)| key == (file_idx, section_idx)) {
+                        *current_offset += entry_padding;
+                    }
+                    // Padding between code runs the configured fill, and any other padding
+                    // is the fill byte; NOBITS sections have no bytes to fill
+                    let gap =
+                        &mut output_section.data[gap_start as usize..*current_offset as usize];
+                    if output_section.header.flags & SHF_EXECINSTR != 0
+                        && let Some(instruction) = self.code_fill.instruction(TargetArch::AArch64)
+                    {
+                        fill_with_instruction(gap, gap_start, instruction);
+                    } else if output_section.header.sh_type != SHT_NOBITS {
+                        gap.fill(self.fill_byte);
+                    }
 
-        // Data Segment Layout
-        let data_segment_start_vaddr =
-            align_up(code_segment_start_vaddr + code_segment_memsz, page_size);
-        let data_segment_file_offset = align_up(code_segment_filesz, page_size);
-        let data_segment_filesz = data_sections
-            .iter()
-            .filter(|s| s.header.sh_type != SHT_NOBITS)
-            .map(|s| s.header.size)
-            .sum::<u64>();
-        let data_segment_memsz = data_sections.iter().map(|s| s.header.size).sum::<u64>();
+                    println!(
+                        "  Section {} (idx {}) of {} -> output section {} at offset 0x{:x}",
+                        name, section_idx, file.filename, output_name, *current_offset
+                    );
 
-        // === Step 3. Create Program Headers ===
-        let code_header = ProgramHeader {
-            p_type: PT_LOAD,
-            flags: PF_R | PF_X,
-            offset: code_segment_file_offset_aligned, // Code segment starts from the beginning of the file   TODO:check
-            vaddr: code_segment_start_vaddr_aligned,
-            paddr: code_segment_start_vaddr_aligned,
-            filesz: code_segment_filesz,
-            memsz: code_segment_memsz,
-            align: page_size,
-        };
+                    // Record where this input section starts in the output section
+                    self.input_section_offsets
+                        .insert((file_idx, section_idx), *current_offset);
 
-        let data_header = ProgramHeader {
-            p_type: PT_LOAD,
-            flags: PF_R | PF_W,
-            offset: data_segment_file_offset,
-            vaddr: data_segment_start_vaddr,
-            paddr: data_segment_start_vaddr,
-            filesz: data_segment_filesz,
-            memsz: data_segment_memsz,
-            align: page_size,
-        };
+                    // NOBITS sections (.bss) only reserve space, there is nothing to copy
+                    if section.sh_type != SHT_NOBITS {
+                        let data = file.section_contents(section_idx);
+                        let start = *current_offset as usize;
+                        output_section.data[start..start + data.len()].copy_from_slice(data);
+                        *current_offset += data.len() as u64;
+                    } else {
+                        *current_offset += section.size;
+                    }
+                }
+            }
+        }
+        self.check_warnings()?;
+        self.phase = Phase::Layout;
+        Ok(())
+    }
 
-        // === Step 4. Create ELF Header ===
-        let mut header = self.input_files[0].header.clone();
-        header.e_type = ET_EXEC;
-        header.e_entry = entry_point;
-        header.e_phoff = elf_header_size;
-        header.e_phnum = num_program_headers as u16;
-        header.e_phentsize = program_header_size as u16;
-        header.e_shoff = 0; // No section headers
-        header.e_shnum = 0;
-        header.e_shstrndx = 0;
+    pub fn resolve_symbols(&mut self) -> Result<(), LinkerError> {
+        self.check_phase(Phase::Layout, Phase::ResolveSymbols)?;
+        println!("=== Symbol Resolution ===");
+        // The file each resolved global was taken from
+        let mut defined_in: HashMap<&str, &str> = HashMap::new();
+        for (file_idx, file) in self.input_files.iter().enumerate() {
+            println!("Processing file: {}", file.filename);
+            for (symbol, name) in file.symbols.iter().zip(&file.symbol_names) {
+                let name: &str = name;
+                if symbol.shndx != SHN_UNDEF && self.traced_symbols.contains(name) {
+                    let event = TraceEvent::Definition {
+                        symbol: name.to_string(),
+                        file: file.filename.clone(),
+                        bind: symbol.get_bind(),
+                        sym_type: symbol.get_type(),
+                    };
+                    println!("trace: {event}");
+                    self.trace_events.push(event);
+                }
+                if symbol.get_bind() == 1 {
+                    // GLOBAL SYMBOL
 
-        // === Step 5. Write everything to a buffer ===
-        let mut buffer = Vec::new();
+                    println!(
+                        "  Symbol: {} (value: 0x{:x}, shndx: {})",
+                        name, symbol.value, symbol.shndx
+                    );
+                    if !is_global_definition(symbol, name) {
+                        continue;
+                    }
+                    if let Some(first_file) = defined_in.get(name) {
+                        match self.resolution_order {
+                            ResolutionOrder::First => continue,
+                            ResolutionOrder::Last => self.diagnostics.warn(format!(
+                                "{}: {} overrides the definition in {first_file}",
+                                file.filename,
+                                quote_symbol(name)
+                            )),
+                            ResolutionOrder::ErrorOnConflict => {
+                                return Err(LinkerError::DuplicateSymbol {
+                                    symbol: name.to_string(),
+                                    first_file: first_file.to_string(),
+                                    second_file: file.filename.clone(),
+                                });
+                            }
+                        }
+                    }
+                    if symbol.shndx == SHN_ABS {
+                        // Absolute symbols aren't relocated with any section
+                        self.global_symbols.insert(
+                            name.to_string(),
+                            GlobalSymbol {
+                                _name: name.to_string(),
+                                final_addr: symbol.value,
+                                tls: false,
+                                sym_type: symbol.get_type(),
+                                bind: symbol.get_bind(),
+                                size: symbol.size,
+                                section: None,
+                            },
+                        );
+                        defined_in.insert(name, &file.filename);
+                        continue;
+                    }
+                    if symbol.shndx > 0 && (symbol.shndx as usize) < file.sections.len() {
+                        let section_name = &file.section_names[symbol.shndx as usize];
 
-        // ELF Header
-        buffer.extend_from_slice(&[0x7f, b'E', b'L', b'F', 2, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
-        buffer.extend_from_slice(&header.e_type.to_le_bytes());
-        buffer.extend_from_slice(&header.e_machine.to_le_bytes());
-        buffer.extend_from_slice(&header.e_version.to_le_bytes());
-        buffer.extend_from_slice(&header.e_entry.to_le_bytes());
-        buffer.extend_from_slice(&header.e_phoff.to_le_bytes());
-        buffer.extend_from_slice(&header.e_shoff.to_le_bytes());
-        buffer.extend_from_slice(&header.e_flags.to_le_bytes());
-        buffer.extend_from_slice(&header.e_ehsize.to_le_bytes());
-        buffer.extend_from_slice(&header.e_phentsize.to_le_bytes());
-        buffer.extend_from_slice(&header.e_phnum.to_le_bytes());
-        buffer.extend_from_slice(&header.e_shentsize.to_le_bytes());
-        buffer.extend_from_slice(&header.e_shnum.to_le_bytes());
-        buffer.extend_from_slice(&header.e_shstrndx.to_le_bytes());
+                        println!("    Section: {}", section_name);
 
-        // Program Headers
-        for p_header in &[code_header, data_header] {
-            buffer.extend_from_slice(&p_header.p_type.to_le_bytes());
-            buffer.extend_from_slice(&p_header.flags.to_le_bytes());
-            buffer.extend_from_slice(&p_header.offset.to_le_bytes());
-            buffer.extend_from_slice(&p_header.vaddr.to_le_bytes());
-            buffer.extend_from_slice(&p_header.paddr.to_le_bytes());
-            buffer.extend_from_slice(&p_header.filesz.to_le_bytes());
-            buffer.extend_from_slice(&p_header.memsz.to_le_bytes());
-            buffer.extend_from_slice(&p_header.align.to_le_bytes());
+                        let key = (file_idx, symbol.shndx as usize);
+                        if let Some(dropped) = self.dropped_sections.get(&key) {
+                            // Only allocatable sections are kept without keep_sections
+                            self.diagnostics.warn(format!(
+                                "{}: global symbol {} is defined in non-allocatable section {dropped}, which was dropped",
+                                file.filename,
+                                quote_symbol(name)
+                            ));
+                            continue;
+                        }
+                        // Relocation, symbol and string tables never make it into the
+                        // output, so a symbol can't be placed in one
+                        let section = &file.sections[symbol.shndx as usize];
+                        if !is_output_section_type(section.sh_type) {
+                            self.diagnostics.warn(format!(
+                                "{}: global symbol {} claims to be defined in {section_name}, which holds no code or data",
+                                file.filename,
+                                quote_symbol(name)
+                            ));
+                            continue;
+                        }
+
+                        if let Some((output_name, output_sec)) = self
+                            .input_section_outputs
+                            .get(&(file_idx, symbol.shndx as usize))
+                            .and_then(|output_name| {
+                                Some((output_name, self.output_sections.get(output_name)?))
+                            })
+                        {
+                            // Get the offset of this input section within the output section
+                            let input_section_offset = self
+                                .input_section_offsets
+                                .get(&(file_idx, symbol.shndx as usize))
+                                .unwrap_or(&0);
+
+                            let final_addr =
+                                output_sec.header.addr + input_section_offset + symbol.value;
+                            println!(
+                                "    Final address: 0x{:x} (section base: 0x{:x} + input offset: 0x{:x} + symbol offset: 0x{:x})",
+                                final_addr,
+                                output_sec.header.addr,
+                                input_section_offset,
+                                symbol.value
+                            );
+                            self.global_symbols.insert(
+                                name.to_string(),
+                                GlobalSymbol {
+                                    _name: name.to_string(),
+                                    final_addr,
+                                    tls: symbol.get_type() == STT_TLS,
+                                    sym_type: symbol.get_type(),
+                                    bind: symbol.get_bind(),
+                                    size: symbol.size,
+                                    section: Some(output_name.clone()),
+                                },
+                            );
+                            defined_in.insert(name, &file.filename);
+                        }
+                    }
+                }
+            }
         }
 
-        let code_padding = code_header.offset.saturating_sub(buffer.len() as u64);
-        buffer.extend_from_slice(&vec![0; code_padding as usize]);
-        // Code Section Data
-        for sec in &code_sections {
-            buffer.extend_from_slice(&sec.data);
+        if self.keep_symbols {
+            self.local_symbols = self.placed_local_symbols();
         }
 
-        // Padding to align data segment
-        let padding_size = data_header.offset.saturating_sub(buffer.len() as u64);
-        buffer.extend_from_slice(&vec![0; padding_size as usize]);
+        if let Some(got) = self.output_sections.get(".got")
+            && !self.global_symbols.contains_key(GOT_SYMBOL)
+        {
+            self.global_symbols.insert(
+                GOT_SYMBOL.to_string(),
+                GlobalSymbol {
+                    _name: GOT_SYMBOL.to_string(),
+                    final_addr: got.header.addr,
+                    tls: false,
+                    sym_type: STT_OBJECT,
+                    bind: STB_LOCAL,
+                    size: 0,
+                    section: Some(".got".to_string()),
+                },
+            );
+        }
 
-        // Data Section Data
-        for sec in &data_sections {
-            if sec.header.sh_type != SHT_NOBITS {
-                buffer.extend_from_slice(&sec.data);
+        if let Some(name) = self
+            .required_symbols
+            .iter()
+            .find(|name| !self.global_symbols.contains_key(*name))
+        {
+            return Err(LinkerError::RequiredSymbolMissing {
+                symbol: name.clone(),
+            });
+        }
+        for (assertion, message) in &self.assertions {
+            let addr = self.global_symbols.get(assertion.symbol());
+            if !addr.is_some_and(|symbol| assertion.holds(symbol.final_addr)) {
+                return Err(LinkerError::AssertionFailed {
+                    message: message.clone(),
+                });
             }
         }
-
-        file.write_all(&buffer)?;
+        self.check_warnings()?;
+        self.phase = Phase::ResolveSymbols;
         Ok(())
     }
-}
 
-fn align_up(addr: u64, page_size: u64) -> u64 {
-    (addr + page_size - 1) & !(page_size - 1)
+    pub fn apply_relocations(&mut self) -> Result<(), LinkerError> {
+        self.check_phase(Phase::ResolveSymbols, Phase::ApplyRelocations)?;
+        // Used to annotate patched instructions with the symbol they now point at
+        let names_by_addr: HashMap<u64, &str> = self
+            .global_symbols
+            .iter()
+            .map(|(name, sym)| (sym.final_addr, name.as_str()))
+            .collect();
+        let got = self
+            .global_symbols
+            .get(GOT_SYMBOL)
+            .map(|sym| sym.final_addr);
+
+        // Output section addresses, so symbols can be resolved while a section is being patched
+        let section_addrs: HashMap<String, u64> = self
+            .output_sections
+            .iter()
+            .map(|(name, sec)| (name.clone(), sec.header.addr))
+            .collect();
+        // Globals come from the resolved symbol table, locals (including section
+        // symbols) from where their own file's section was placed
+        let symbol_address = |file_idx: usize, symbol: &Symbol, name: &str| {
+            if symbol.get_bind() != STB_LOCAL {
+                return self.global_symbols.get(name).map(|sym| sym.final_addr);
+            }
+            match symbol.shndx {
+                SHN_UNDEF => None,
+                SHN_ABS => Some(symbol.value),
+                shndx => {
+                    let key = (file_idx, shndx as usize);
+                    let base = section_addrs.get(self.input_section_outputs.get(&key)?)?;
+                    let input_section_offset = self.input_section_offsets.get(&key).unwrap_or(&0);
+                    Some(base + input_section_offset + symbol.value)
+                }
+            }
+        };
+
+        // The (file, section) a symbol is defined in, if gc-sections or a section filter
+        // discarded that section. Reachability keeps every relocation target alive, so
+        // with gc-sections alone finding one is a linker bug.
+        let discarded_definition = |file_idx: usize, symbol: &Symbol, name: &str| {
+            let describe = |file_idx: usize, shndx: u16| {
+                let name = self.discarded_sections.get(&(file_idx, shndx as usize))?;
+                Some((self.input_files[file_idx].filename.clone(), name.clone()))
+            };
+            if symbol.get_bind() == STB_LOCAL {
+                return describe(file_idx, symbol.shndx);
+            }
+            if self.global_symbols.contains_key(name) {
+                return None;
+            }
+            self.input_files
+                .iter()
+                .enumerate()
+                .find_map(|(other_idx, other)| {
+                    other
+                        .symbols
+                        .iter()
+                        .zip(&other.symbol_names)
+                        .find(|(s, other_name)| {
+                            s.get_bind() != STB_LOCAL
+                                && s.shndx != SHN_UNDEF
+                                && **other_name == name
+                        })
+                        .map(|(s, _)| (other_idx, s.shndx))
+                })
+                .and_then(|(other_idx, shndx)| describe(other_idx, shndx))
+        };
+
+        for (file_idx, file) in self.input_files.iter().enumerate() {
+            for (section_idx, section) in file.sections.iter().enumerate() {
+                if !is_relocation_section(section) {
+                    continue;
+                }
+                let target_sec_idx = section.info as usize;
+                println!("the target section index is {target_sec_idx}");
+                // The kept copy's relocations patch the same bytes the same way
+                if self
+                    .folded_sections
+                    .contains_key(&(file_idx, target_sec_idx))
+                {
+                    continue;
+                }
+                let Some(target_output_name) =
+                    self.input_section_outputs.get(&(file_idx, target_sec_idx))
+                else {
+                    if let Some(dropped) = self.dropped_sections.get(&(file_idx, target_sec_idx)) {
+                        let count = section.size / section.entsize.max(1);
+                        self.diagnostics.warn(format!(
+                            "{}: {count} relocation(s) against non-allocatable section {dropped} not applied, the section was dropped",
+                            file.filename
+                        ));
+                    }
+                    continue;
+                };
+
+                if let Some(output_section) = self.output_sections.get_mut(target_output_name) {
+                    // 传递重定位表section本身，而不是目标section
+                    let relocations = file.relocations(section_idx).unwrap();
+
+                    for mut rela in relocations {
+                        if section.sh_type == SHT_REL {
+                            let place = self
+                                .input_section_offsets
+                                .get(&(file_idx, target_sec_idx))
+                                .unwrap_or(&0)
+                                + rela.offset;
+                            rela.addend = output_section
+                                .data
+                                .get(place as usize..)
+                                .and_then(|place| implicit_addend(rela.get_type(), place))
+                                .ok_or_else(|| LinkerError::BadRelocation {
+                                    file: file.filename.clone(),
+                                    offset: rela.offset,
+                                    reason: format!(
+                                        "no implicit addend for REL relocation type {}",
+                                        rela.get_type()
+                                    ),
+                                })?;
+                        }
+
+                        let sym_index = rela.get_symbol_index() as usize;
+                        let symbol = &file.symbols[sym_index];
+                        let sym_name: &str = &file.symbol_names[sym_index];
+
+                        println!(
+                            "  Relocation: {} type {} offset 0x{:x} addend {}",
+                            sym_name,
+                            rela.get_type(),
+                            rela.offset,
+                            rela.addend
+                        );
+
+                        if self.traced_symbols.contains(sym_name) {
+                            let event = TraceEvent::Reference {
+                                symbol: sym_name.to_string(),
+                                file: file.filename.clone(),
+                                section: file.section_names[target_sec_idx].to_string(),
+                                offset: rela.offset,
+                                r_type: rela.get_type(),
+                            };
+                            println!("trace: {event}");
+                            self.trace_events.push(event);
+                        }
+
+                        if let Some((discarded_file, discarded_section)) =
+                            discarded_definition(file_idx, symbol, sym_name)
+                        {
+                            return Err(LinkerError::DiscardedSectionReference {
+                                file: file.filename.clone(),
+                                section: file.section_names[target_sec_idx].to_string(),
+                                symbol: sym_name.to_string(),
+                                discarded_file,
+                                discarded_section,
+                            });
+                        }
+
+                        // Symbol index 0 means no symbol: S is 0 and only the addend counts
+                        let s = if sym_index == 0 {
+                            Some(0)
+                        } else {
+                            symbol_address(file_idx, symbol, sym_name)
+                        };
+                        let count = |counts: &mut BTreeMap<u32, usize>| {
+                            *counts.entry(rela.get_type()).or_default() += 1;
+                        };
+                        let Some(s) = s else {
+                            count(&mut self.relocation_stats.unresolved);
+                            continue;
+                        };
+                        // P is the address of the place being relocated
+                        // Need to account for where this input section is within the output section
+                        let input_section_offset = self
+                            .input_section_offsets
+                            .get(&(file_idx, target_sec_idx))
+                            .unwrap_or(&0);
+                        let p = output_section.header.addr + input_section_offset + rela.offset;
+
+                        println!(
+                            "    S (symbol addr) = 0x{:x}, P (patch location) = 0x{:x} (section: 0x{:x} + input_offset: 0x{:x} + rela_offset: 0x{:x}), A (addend) = {:#x}",
+                            s,
+                            p,
+                            output_section.header.addr,
+                            input_section_offset,
+                            rela.offset,
+                            rela.addend
+                        );
+
+                        let place = (input_section_offset + rela.offset) as usize;
+                        let result = relocate(
+                            &mut output_section.data,
+                            place,
+                            rela.get_type(),
+                            s,
+                            rela.addend,
+                            p,
+                            got,
+                        );
+                        if result.is_ok() && sym_index != 0 && symbol.get_bind() != STB_LOCAL {
+                            self.applied_relocations
+                                .entry(sym_name.to_string())
+                                .or_default()
+                                .push(AppliedRelocation {
+                                    file_idx,
+                                    section: target_output_name.clone(),
+                                    place,
+                                    offset: rela.offset,
+                                    p,
+                                    r_type: rela.get_type(),
+                                    addend: rela.addend,
+                                });
+                        }
+                        match result {
+                            Ok(()) if is_instruction_relocation(rela.get_type()) => {
+                                count(&mut self.relocation_stats.applied);
+                                let instruction = u32::from_le_bytes(
+                                    output_section.data[place..place + 4].try_into().unwrap(),
+                                );
+                                println!(
+                                    "    Patched instruction: 0x{:x} ({})",
+                                    instruction,
+                                    format_instruction(instruction, p, |addr| names_by_addr
+                                        .get(&addr)
+                                        .copied())
+                                );
+                            }
+                            Ok(()) => count(&mut self.relocation_stats.applied),
+                            // Left as the assembler wrote it
+                            Err(RelocationError::Unsupported { r_type, .. }) => {
+                                count(&mut self.relocation_stats.unsupported);
+                                println!("    Relocation type {r_type} not supported, skipped");
+                            }
+                            Err(RelocationError::OutOfRange { r_type, .. }) => {
+                                return Err(LinkerError::RelocationOutOfRange {
+                                    file: file.filename.clone(),
+                                    symbol: sym_name.to_string(),
+                                    r_type,
+                                    offset: rela.offset,
+                                });
+                            }
+                            Err(err) => {
+                                return Err(LinkerError::BadRelocation {
+                                    file: file.filename.clone(),
+                                    offset: rela.offset,
+                                    reason: err.reason(),
+                                });
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        println!("{}", self.relocation_stats);
+        // Only now are the contents final: relocations may write into the zeros
+        if self.trim_zero_tails {
+            self.move_zero_tails_to_bss();
+        }
+        self.check_warnings()?;
+        self.phase = Phase::ApplyRelocations;
+        Ok(())
+    }
+
+    /// Moves the global symbol `name` to `addr` after the link, e.g. to redirect calls to
+    /// a patched copy of a function, and redoes only the relocations that reference it.
+    /// The symbol's code or data stays where it is: placing the copy at `addr` is up to
+    /// the caller. Can be called any number of times once relocations have been applied;
+    /// if a reference can't reach `addr`, nothing is changed. To support this, every link
+    /// keeps a record of each relocation it applied against a global.
+    pub fn move_symbol(&mut self, name: &str, addr: u64) -> Result<(), LinkerError> {
+        if self.phase != Phase::ApplyRelocations {
+            return Err(LinkerError::InvalidPhaseOrder {
+                phase: "move_symbol",
+                previous: Phase::ApplyRelocations.method(),
+            });
+        }
+        // Outside the image, e.g. in a scratch region, the symbol becomes absolute
+        let section = self
+            .output_sections
+            .values()
+            .find(|s| {
+                s.header.flags & SHF_ALLOC != 0
+                    && (s.header.addr..s.header.addr + s.header.size).contains(&addr)
+            })
+            .map(|s| s.name.clone());
+        if !self.global_symbols.contains_key(name) {
+            return Err(LinkerError::UnknownSymbol {
+                symbol: name.to_string(),
+            });
+        }
+        let got = self
+            .global_symbols
+            .get(GOT_SYMBOL)
+            .map(|sym| sym.final_addr);
+        // Patch copies of the places first, so that a reference out of range leaves
+        // everything as it was
+        let mut patches: Vec<(String, usize, Vec<u8>)> = Vec::new();
+        for applied in self.applied_relocations.get(name).into_iter().flatten() {
+            let data = &self.output_sections[&applied.section].data;
+            // No relocation patches more than 8 bytes
+            let end = data.len().min(applied.place + 8);
+            let mut place = data[applied.place..end].to_vec();
+            let file = || self.input_files[applied.file_idx].filename.clone();
+            match relocate(
+                &mut place,
+                0,
+                applied.r_type,
+                addr,
+                applied.addend,
+                applied.p,
+                got,
+            ) {
+                Ok(()) => patches.push((applied.section.clone(), applied.place, place)),
+                Err(RelocationError::OutOfRange { r_type, .. }) => {
+                    return Err(LinkerError::RelocationOutOfRange {
+                        file: file(),
+                        symbol: name.to_string(),
+                        r_type,
+                        offset: applied.offset,
+                    });
+                }
+                Err(err) => {
+                    return Err(LinkerError::BadRelocation {
+                        file: file(),
+                        offset: applied.offset,
+                        reason: err.reason(),
+                    });
+                }
+            }
+        }
+
+        let symbol = self.global_symbols.get_mut(name).unwrap();
+        let previous = (symbol.final_addr, symbol.section.clone());
+        symbol.final_addr = addr;
+        symbol.section = section;
+        // Swapping the patches in leaves the old contents in them, for undoing
+        let mut swap_patches = |output_sections: &mut HashMap<String, OutputSection>| {
+            for (section, place, bytes) in &mut patches {
+                let data = &mut output_sections.get_mut(section).unwrap().data;
+                data[*place..*place + bytes.len()].swap_with_slice(bytes);
+            }
+        };
+        swap_patches(&mut self.output_sections);
+        // The symbol tables and entry point are fixed by finalize
+        if self.final_layout.is_some()
+            && let Err(err) = self.finalize()
+        {
+            swap_patches(&mut self.output_sections);
+            let symbol = self.global_symbols.get_mut(name).unwrap();
+            (symbol.final_addr, symbol.section) = previous;
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    /// Writes the ELF executable from [`LinkerContext::link_to_bytes`] to `path`.
+    pub fn write_executable(&self, path: &str) -> Result<(), LinkerError> {
+        let image = self.link_to_bytes()?;
+        write_file(path, image)
+    }
+
+    /// The program headers of the output, in the order they are written: `PT_INTERP`
+    /// when present, the `PT_LOAD` segments, then `PT_DYNAMIC`, `PT_GNU_EH_FRAME`,
+    /// `PT_TLS` and `PT_NOTE` when present, `PT_GNU_STACK`, and `PT_GNU_RELRO` when
+    /// present. Empty until [`LinkerContext::finalize`] has run.
+    pub fn program_headers(&self) -> &[ProgramHeader] {
+        self.final_layout
+            .as_ref()
+            .map_or(&[], |layout| &layout.program_headers)
+    }
+
+    /// Where the sections, global symbols and segments of the output ended up, finalizing
+    /// a copy of the layout first if [`LinkerContext::finalize`] hasn't been called.
+    pub fn link_map(&self) -> Result<LinkMap, LinkerError> {
+        let layout = match &self.final_layout {
+            Some(layout) => Cow::Borrowed(layout),
+            None => Cow::Owned(self.compute_final_layout()?),
+        };
+        let mut offsets: HashMap<&str, u64> = layout
+            .unloaded_offsets
+            .iter()
+            .map(|(name, offset)| (name.as_str(), *offset))
+            .collect();
+        for (header, sections) in &self.segment_layout() {
+            for sec in sections {
+                offsets.insert(&sec.name, header.offset + (sec.header.addr - header.vaddr));
+            }
+        }
+
+        let mut sections: Vec<MapSection> = self
+            .output_sections
+            .values()
+            .map(|sec| MapSection {
+                name: sec.name.clone(),
+                vaddr: sec.header.addr,
+                offset: offsets.get(sec.name.as_str()).copied().unwrap_or(0),
+                size: sec.header.size,
+                flags: sec.header.flags,
+            })
+            .collect();
+        sections.sort_by(|a, b| (a.vaddr, &a.name).cmp(&(b.vaddr, &b.name)));
+
+        let mut symbols: Vec<MapSymbol> = self
+            .global_symbols
+            .iter()
+            .map(|(name, sym)| MapSymbol {
+                name: name.clone(),
+                addr: sym.final_addr,
+                section: sym.section.clone(),
+            })
+            .collect();
+        symbols.sort_by(|a, b| a.name.cmp(&b.name));
+
+        let segments = layout
+            .program_headers
+            .iter()
+            .map(|h| MapSegment {
+                segment_type: h.type_name(),
+                flags: h.flags,
+                vaddr: h.vaddr,
+                offset: h.offset,
+                filesz: h.filesz,
+                memsz: h.memsz,
+            })
+            .collect();
+        Ok(LinkMap {
+            sections,
+            symbols,
+            segments,
+        })
+    }
+
+    /// Writes [`LinkerContext::link_map`] as JSON to a new file at `path`, see
+    /// [`LinkMap::write_json`].
+    pub fn write_map_json(&self, path: &str) -> Result<(), LinkerError> {
+        let map = self.link_map()?;
+        fs::File::create(path)
+            .and_then(|file| {
+                let mut writer = BufWriter::new(file);
+                map.write_json(&mut writer)?;
+                writer.flush()
+            })
+            .map_err(|source| LinkerError::Io {
+                path: path.to_string(),
+                source,
+            })
+    }
+
+    /// Writes the loaded contents as a flat binary, like `objcopy -O binary`: see
+    /// [`LinkerContext::flat_image`].
+    pub fn write_binary(&self, path: &str) -> Result<(), LinkerError> {
+        let (_, image) = self.padded_flat_image()?;
+        write_file(path, image)
+    }
+
+    /// The loaded contents of the output as one block of bytes, without ELF or program
+    /// headers: every allocated section with file contents placed by address, from the
+    /// lowest such address to the end of the highest, with gaps filled with the fill byte.
+    /// Returns the address of the first byte along with the image.
+    pub fn flat_image(&self) -> (u64, Vec<u8>) {
+        let mut sections: Vec<_> = self
+            .output_sections
+            .values()
+            .filter(|s| s.header.flags & SHF_ALLOC != 0 && s.header.sh_type != SHT_NOBITS)
+            .filter(|s| s.header.size > 0)
+            .collect();
+        sections.sort_by_key(|s| s.header.addr);
+        let Some(start) = sections.first().map(|s| s.header.addr) else {
+            return (0, Vec::new());
+        };
+        let mut image = Vec::new();
+        let mut previous: Option<&OutputSection> = None;
+        for sec in sections {
+            let gap_start = image.len();
+            image.resize((sec.header.addr - start) as usize, self.fill_byte);
+            if let Some(instruction) = self.code_padding(previous, sec) {
+                fill_with_instruction(
+                    &mut image[gap_start..],
+                    start + gap_start as u64,
+                    instruction,
+                );
+            }
+            image.extend_from_slice(&self.output_section_data(sec));
+            previous = Some(sec);
+        }
+        (start, image)
+    }
+
+    /// Writes the loaded contents as Intel HEX, with the entry point as the start address.
+    pub fn write_ihex(&self, path: &str) -> Result<(), LinkerError> {
+        let (start, image) = self.padded_flat_image()?;
+        let hex = encode_ihex(start, &image, self.entry_point())
+            .ok_or_else(|| too_high_for_hex(start, &image))?;
+        write_file(path, hex)
+    }
+
+    /// Writes the loaded contents as Motorola S-records, with the entry point in the
+    /// terminating S7 record.
+    pub fn write_srec(&self, path: &str) -> Result<(), LinkerError> {
+        let (start, image) = self.padded_flat_image()?;
+        let srec = encode_srec(start, &image, self.entry_point())
+            .ok_or_else(|| too_high_for_hex(start, &image))?;
+        write_file(path, srec)
+    }
+
+    /// Finds the output section and segment a resolved global symbol lands in.
+    pub fn symbol_location(&self, name: &str) -> Option<SymbolLocation> {
+        let symbol = self.global_symbols.get(name)?;
+        let addr = symbol.final_addr;
+        // .tbss overlaps the sections after it, so only look where the symbol can live
+        let candidates = || {
+            self.output_sections
+                .values()
+                .filter(|s| (s.header.flags & SHF_TLS != 0) == symbol.tls)
+        };
+        let section = candidates()
+            .find(|s| addr >= s.header.addr && addr < s.header.addr + s.header.size)
+            // A zero-sized section can still hold a symbol at its start
+            .or_else(|| candidates().find(|s| addr == s.header.addr))?;
+        Some(SymbolLocation {
+            section: section.name.clone(),
+            segment_flags: self.segment_flags(self.segment_index(section)),
+            offset_in_section: addr - section.header.addr,
+        })
+    }
+
+    /// Offset of a thread-local symbol from the thread pointer, as used by local-exec
+    /// TLS accesses. Returns `None` for unknown or non-TLS symbols.
+    pub fn tls_offset(&self, name: &str) -> Option<u64> {
+        let symbol = self.global_symbols.get(name).filter(|s| s.tls)?;
+        let tls = self.tls_header()?;
+        // Variant 1: the TLS block follows the TCB, aligned to the template's alignment
+        Some(align_up(TCB_SIZE, tls.align.max(1))? + (symbol.final_addr - tls.vaddr))
+    }
+
+    /// The address `file_offset` in the output is loaded at, if it is part of a loaded
+    /// segment's file image. Only meaningful once the link has been laid out.
+    pub fn offset_to_vaddr(&self, file_offset: u64) -> Option<u64> {
+        self.segment_layout()
+            .iter()
+            .map(|(header, _)| header)
+            .find(|h| file_offset >= h.offset && file_offset < h.offset + h.filesz)
+            .map(|h| h.vaddr + (file_offset - h.offset))
+    }
+
+    /// The output file offset holding the initial contents of `vaddr`. Returns `None` for
+    /// unloaded addresses and for zero-filled memory such as `.bss`, which has no bytes
+    /// in the file.
+    pub fn vaddr_to_offset(&self, vaddr: u64) -> Option<u64> {
+        self.segment_layout()
+            .iter()
+            .map(|(header, _)| header)
+            .find(|h| vaddr >= h.vaddr && vaddr < h.vaddr + h.filesz)
+            .map(|h| h.offset + (vaddr - h.vaddr))
+    }
+
+    /// Fixes the shape of the output file: the entry point, the program headers and where
+    /// each part of the image goes. Run after relocations are applied; it only reads the
+    /// laid out sections, so the inspection APIs reflect what will be written.
+    pub fn finalize(&mut self) -> Result<(), LinkerError> {
+        self.final_layout = Some(self.compute_final_layout()?);
+        Ok(())
+    }
+
+    /// Checks that `image`, e.g. from [`LinkerContext::link_to_bytes`], can be loaded as its
+    /// own program headers describe: every `PT_LOAD` lies within the image, its offset and
+    /// address are congruent modulo its alignment, no two overlap in memory, and `e_entry`
+    /// is in an executable one.
+    pub fn verify_output(&self, image: &[u8]) -> Result<(), LinkerError> {
+        let invalid = |reason: String| LinkerError::InvalidOutput { reason };
+        let (_, header) =
+            parse_elf_header(image).map_err(|_| invalid("invalid ELF header".to_string()))?;
+        if header.e_phentsize as u64 != PROGRAM_HEADER_SIZE {
+            return Err(invalid(format!(
+                "e_phentsize is {}, not {PROGRAM_HEADER_SIZE}",
+                header.e_phentsize
+            )));
+        }
+        let table = header
+            .e_phoff
+            .checked_add(header.e_phnum as u64 * PROGRAM_HEADER_SIZE)
+            .and_then(|end| image.get(header.e_phoff as usize..end as usize))
+            .ok_or_else(|| invalid("the program header table is out of bounds".to_string()))?;
+        let mut loads: Vec<ProgramHeader> = table
+            .chunks_exact(PROGRAM_HEADER_SIZE as usize)
+            .map(|bytes| ProgramHeader::decode(bytes.try_into().unwrap()))
+            .filter(|ph| ph.p_type == PT_LOAD)
+            .collect();
+
+        for ph in &loads {
+            if ph
+                .offset
+                .checked_add(ph.filesz)
+                .is_none_or(|end| end > image.len() as u64)
+            {
+                return Err(invalid(format!(
+                    "the segment at {:#x} extends past the end of the file",
+                    ph.vaddr
+                )));
+            }
+            if ph.filesz > ph.memsz {
+                return Err(invalid(format!(
+                    "the segment at {:#x} has more bytes in the file than in memory",
+                    ph.vaddr
+                )));
+            }
+            if ph.align > 1
+                && (!ph.align.is_power_of_two() || ph.vaddr.wrapping_sub(ph.offset) % ph.align != 0)
+            {
+                return Err(invalid(format!(
+                    "the segment at {:#x} has offset {:#x}, which isn't congruent to its address modulo the alignment {:#x}",
+                    ph.vaddr, ph.offset, ph.align
+                )));
+            }
+        }
+        loads.sort_by_key(|ph| ph.vaddr);
+        for pair in loads.windows(2) {
+            if pair[0].vaddr.saturating_add(pair[0].memsz) > pair[1].vaddr {
+                return Err(invalid(format!(
+                    "the segments at {:#x} and {:#x} overlap",
+                    pair[0].vaddr, pair[1].vaddr
+                )));
+            }
+        }
+        let entry = header.e_entry;
+        if !loads
+            .iter()
+            .any(|ph| ph.flags & PF_X != 0 && entry >= ph.vaddr && entry - ph.vaddr < ph.memsz)
+        {
+            return Err(invalid(format!(
+                "the entry point {entry:#x} isn't in an executable segment"
+            )));
+        }
+        Ok(())
+    }
+
+    /// Whether `vaddr` is mapped executable: inside a `PT_LOAD` segment with `PF_X`. Always
+    /// false until [`LinkerContext::finalize`] has run.
+    pub fn is_executable(&self, vaddr: u64) -> bool {
+        self.program_headers().iter().any(|h| {
+            h.p_type == PT_LOAD
+                && h.flags & PF_X != 0
+                && vaddr >= h.vaddr
+                && vaddr - h.vaddr < h.memsz
+        })
+    }
+
+    /// Size in bytes of the output file, once [`LinkerContext::finalize`] has run.
+    pub fn image_size(&self) -> Option<u64> {
+        self.final_layout.as_ref().map(|layout| layout.image_size)
+    }
+
+    /// Serializes the linked executable into memory, finalizing a copy of the layout first
+    /// if [`LinkerContext::finalize`] hasn't been called.
+    pub fn link_to_bytes(&self) -> Result<Vec<u8>, LinkerError> {
+        // The output ELF header is derived from the first input's
+        let Some(first_file) = self.input_files.first() else {
+            return Err(LinkerError::NoInputFiles);
+        };
+
+        // Debug: Print global symbols
+        println!("Global symbols:");
+        for (name, sym) in &self.global_symbols {
+            println!("  {} -> 0x{:x}", name, sym.final_addr);
+        }
+
+        let layout = match &self.final_layout {
+            Some(layout) => Cow::Borrowed(layout),
+            None => Cow::Owned(self.compute_final_layout()?),
+        };
+        let program_headers = &layout.program_headers;
+
+        // === Step 1. Create ELF Header ===
+        let mut header = first_file.header.clone();
+        header.e_type = ET_EXEC;
+        header.e_entry = layout.entry_point;
+        header.e_phoff = ELF_HEADER_SIZE;
+        header.e_phnum = program_headers.len() as u16;
+        header.e_phentsize = PROGRAM_HEADER_SIZE as u16;
+        match &layout.section_headers {
+            Some(table) => {
+                header.e_shoff = table.offset;
+                header.e_shnum = table.headers.len() as u16;
+                header.e_shentsize = SECTION_HEADER_SIZE as u16;
+                header.e_shstrndx = table.shstrndx;
+            }
+            None => {
+                header.e_shoff = 0; // No section headers
+                header.e_shnum = 0;
+                header.e_shstrndx = 0;
+            }
+        }
+        // The kernel refuses to run a file whose program headers aren't loaded
+        if !phdrs_are_loaded(&header, program_headers) {
+            return Err(LinkerError::InvalidOutput {
+                reason: "the program headers are not covered by any PT_LOAD segment".to_string(),
+            });
+        }
+
+        // === Step 2. Write everything to a buffer ===
+        let mut buffer = Vec::with_capacity(layout.image_size as usize);
+
+        // ELF Header
+        buffer.extend_from_slice(&[0x7f, b'E', b'L', b'F', 2, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        buffer.extend_from_slice(&header.e_type.to_le_bytes());
+        buffer.extend_from_slice(&header.e_machine.to_le_bytes());
+        buffer.extend_from_slice(&header.e_version.to_le_bytes());
+        buffer.extend_from_slice(&header.e_entry.to_le_bytes());
+        buffer.extend_from_slice(&header.e_phoff.to_le_bytes());
+        buffer.extend_from_slice(&header.e_shoff.to_le_bytes());
+        buffer.extend_from_slice(&header.e_flags.to_le_bytes());
+        buffer.extend_from_slice(&header.e_ehsize.to_le_bytes());
+        buffer.extend_from_slice(&header.e_phentsize.to_le_bytes());
+        buffer.extend_from_slice(&header.e_phnum.to_le_bytes());
+        buffer.extend_from_slice(&header.e_shentsize.to_le_bytes());
+        buffer.extend_from_slice(&header.e_shnum.to_le_bytes());
+        buffer.extend_from_slice(&header.e_shstrndx.to_le_bytes());
+
+        // Program Headers
+        for p_header in program_headers {
+            buffer.extend_from_slice(&p_header.p_type.to_le_bytes());
+            buffer.extend_from_slice(&p_header.flags.to_le_bytes());
+            buffer.extend_from_slice(&p_header.offset.to_le_bytes());
+            buffer.extend_from_slice(&p_header.vaddr.to_le_bytes());
+            buffer.extend_from_slice(&p_header.paddr.to_le_bytes());
+            buffer.extend_from_slice(&p_header.filesz.to_le_bytes());
+            buffer.extend_from_slice(&p_header.memsz.to_le_bytes());
+            buffer.extend_from_slice(&p_header.align.to_le_bytes());
+        }
+
+        // Section Data, padded with the fill byte wherever nothing is written
+        buffer.resize(layout.image_size as usize, self.fill_byte);
+        let loads: Vec<_> = program_headers
+            .iter()
+            .filter(|h| h.p_type == PT_LOAD)
+            .collect();
+        let mut loaded: Vec<_> = self
+            .output_sections
+            .values()
+            .filter(|s| {
+                s.header.flags & SHF_ALLOC != 0
+                    && s.header.sh_type != SHT_NOBITS
+                    && s.header.size > 0
+            })
+            .collect();
+        loaded.sort_by_key(|s| s.header.addr);
+        let mut previous: Option<&OutputSection> = None;
+        for sec in loaded {
+            let load = loads
+                .iter()
+                .find(|h| sec.header.addr >= h.vaddr && sec.header.addr < h.vaddr + h.filesz)
+                .ok_or_else(|| LinkerError::InvalidOutput {
+                    reason: format!("section {} is outside of every PT_LOAD segment", sec.name),
+                })?;
+            let offset = (load.offset + (sec.header.addr - load.vaddr)) as usize;
+            if let Some(prev) = previous
+                && let Some(instruction) = self.code_padding(Some(prev), sec)
+            {
+                // Both are executable, so they share the code segment
+                let gap_addr = prev.header.addr + prev.header.size;
+                let gap_offset = offset - (sec.header.addr - gap_addr) as usize;
+                fill_with_instruction(&mut buffer[gap_offset..offset], gap_addr, instruction);
+            }
+            write_at(&mut buffer, offset as u64, &self.output_section_data(sec))?;
+            previous = Some(sec);
+        }
+        // Kept non-allocatable sections follow the loaded segments
+        for (name, offset) in &layout.unloaded_offsets {
+            let data = layout
+                .compressed_sections
+                .get(name)
+                .unwrap_or(&self.output_sections[name].data);
+            write_at(&mut buffer, *offset, data)?;
+        }
+        if let Some(table) = &layout.section_headers {
+            for (offset, data) in &table.tables {
+                write_at(&mut buffer, *offset, data)?;
+            }
+            let mut headers = Vec::with_capacity(table.headers.len() * SECTION_HEADER_SIZE);
+            for section_header in &table.headers {
+                section_header.encode(&mut headers);
+            }
+            write_at(&mut buffer, table.offset, &headers)?;
+        }
+
+        self.pad(&mut buffer)?;
+        Ok(buffer)
+    }
+
+    /// Extends `image` to the size set with [`LinkerContext::pad_to_size`], if any.
+    fn pad(&self, image: &mut Vec<u8>) -> Result<(), LinkerError> {
+        let Some(limit) = self.pad_to_size else {
+            return Ok(());
+        };
+        let size = image.len() as u64;
+        if size > limit {
+            return Err(LinkerError::ImageTooLarge { size, limit });
+        }
+        image.resize(limit as usize, self.fill_byte);
+        Ok(())
+    }
+
+    /// [`LinkerContext::flat_image`], padded for writing out.
+    fn padded_flat_image(&self) -> Result<(u64, Vec<u8>), LinkerError> {
+        let (start, mut image) = self.flat_image();
+        self.pad(&mut image)?;
+        Ok((start, image))
+    }
+
+    /// Computes the [`FinalLayout`] of the current sections.
+    fn compute_final_layout(&self) -> Result<FinalLayout, LinkerError> {
+        if self.phase != Phase::ApplyRelocations {
+            return Err(LinkerError::InvalidPhaseOrder {
+                phase: "finalize",
+                previous: Phase::ApplyRelocations.method(),
+            });
+        }
+        if self.input_files.is_empty() {
+            return Err(LinkerError::NoInputFiles);
+        }
+        let Some(entry_point) = self.entry_point() else {
+            return Err(match &self.entry {
+                EntryPoint::Symbol(symbol) => LinkerError::UndefinedEntry {
+                    symbol: symbol.clone(),
+                },
+                _ => LinkerError::NoEntryPoint,
+            });
+        };
+        println!("Entry point: 0x{:x}", entry_point);
+
+        println!("Segments:");
+        for (header, sections) in &self.segment_layout() {
+            println!("  {header}");
+            for sec in sections {
+                println!("    {}", sec.summary());
+            }
+        }
+
+        let program_headers = self.build_program_headers();
+        // Layout left room for exactly this many headers before the first section
+        let reserved = self.program_header_count();
+        if program_headers.len() as u64 != reserved {
+            return Err(LinkerError::InvalidOutput {
+                reason: format!(
+                    "{} program headers don't fit in the {reserved} slots left by layout",
+                    program_headers.len()
+                ),
+            });
+        }
+        if self.reject_wx
+            && let Some(header) = program_headers
+                .iter()
+                .find(|h| h.p_type == PT_LOAD && h.flags & PF_W != 0 && h.flags & PF_X != 0)
+        {
+            return Err(LinkerError::WxSegment {
+                vaddr: header.vaddr,
+            });
+        }
+        let mut image_size = program_headers
+            .iter()
+            .filter(|h| h.p_type == PT_LOAD)
+            .map(|h| h.offset + h.filesz)
+            .max()
+            .unwrap_or(0);
+
+        let mut unloaded: Vec<_> = self
+            .output_sections
+            .values()
+            .filter(|s| s.header.flags & SHF_ALLOC == 0 && s.header.sh_type != SHT_NOBITS)
+            .collect();
+        unloaded.sort_by(|a, b| a.name.cmp(&b.name));
+        let mut unloaded_offsets = Vec::new();
+        let mut compressed_sections = HashMap::new();
+        for sec in unloaded {
+            let compressed = self
+                .should_compress(sec)
+                .then(|| compress_section(&sec.data, sec.header.addralign))
+                .flatten();
+            // The Chdr's fields are 8 bytes wide
+            let align = match compressed {
+                Some(_) => 8,
+                None => sec.header.addralign.max(1),
+            };
+            let overflow = || LinkerError::OffsetOverflow {
+                section: sec.name.clone(),
+            };
+            let offset = align_up(image_size, align).ok_or_else(overflow)?;
+            unloaded_offsets.push((sec.name.clone(), offset));
+            let len = match compressed {
+                Some(data) => {
+                    let len = data.len() as u64;
+                    compressed_sections.insert(sec.name.clone(), data);
+                    len
+                }
+                None => sec.data.len() as u64,
+            };
+            image_size = offset.checked_add(len).ok_or_else(overflow)?;
+        }
+
+        let section_headers = if self.keep_symbols {
+            let table = self.section_header_table(
+                &program_headers,
+                &unloaded_offsets,
+                &compressed_sections,
+                image_size,
+            )?;
+            image_size = table
+                .offset
+                .checked_add((table.headers.len() * SECTION_HEADER_SIZE) as u64)
+                .ok_or_else(|| LinkerError::OffsetOverflow {
+                    section: "section header table".to_string(),
+                })?;
+            Some(table)
+        } else {
+            None
+        };
+
+        Ok(FinalLayout {
+            entry_point,
+            program_headers,
+            unloaded_offsets,
+            compressed_sections,
+            section_headers,
+            image_size,
+        })
+    }
+
+    /// Whether a kept non-allocatable section is written compressed, see
+    /// [`LinkerContext::compress_debug_sections`].
+    fn should_compress(&self, section: &OutputSection) -> bool {
+        // Unmerged sections are named `<name>(<file>:<index>)`
+        let name = section
+            .name
+            .split_once('(')
+            .map_or(section.name.as_str(), |(base, _)| base);
+        if !name.starts_with(".debug") {
+            return false;
+        }
+        match self.compress_debug_sections {
+            CompressKind::None => false,
+            CompressKind::Zlib => true,
+            CompressKind::Keep => self.compressed_inputs.contains(&section.name),
+        }
+    }
+
+    /// Section headers for every output section, then `.symtab`, `.strtab` and
+    /// `.shstrtab`, which are placed from `end` on, followed by the table itself.
+    fn section_header_table(
+        &self,
+        program_headers: &[ProgramHeader],
+        unloaded_offsets: &[(String, u64)],
+        compressed_sections: &HashMap<String, Vec<u8>>,
+        end: u64,
+    ) -> Result<SectionHeaderTable, LinkerError> {
+        let loaded = self.loaded_sections();
+        let file_offset = |addr: u64| {
+            program_headers
+                .iter()
+                .filter(|h| h.p_type == PT_LOAD)
+                .find(|h| addr >= h.vaddr && addr <= h.vaddr + h.memsz)
+                .map_or(0, |h| h.offset + (addr - h.vaddr))
+        };
+        let sections: Vec<(&OutputSection, u64)> = loaded
+            .into_iter()
+            .map(|s| (s, file_offset(s.header.addr)))
+            .chain(
+                unloaded_offsets
+                    .iter()
+                    .map(|(name, offset)| (&self.output_sections[name], *offset)),
+            )
+            .collect();
+        let section_index: HashMap<&str, u16> = sections
+            .iter()
+            .enumerate()
+            .map(|(i, (s, _))| (s.name.as_str(), i as u16 + 1))
+            .collect();
+
+        // The null symbol, then the locals, then everything else: ELF requires locals to
+        // come first, with sh_info holding the index of the first non-local
+        let tls_vaddr = self.tls_header().map_or(0, |tls| tls.vaddr);
+        let mut symtab = vec![0; SYMBOL_SIZE];
+        let mut strtab = vec![0];
+        let mut symbols: Vec<_> = self
+            .global_symbols
+            .iter()
+            .chain(
+                self.local_symbols
+                    .iter()
+                    .map(|(name, symbol)| (name, symbol)),
+            )
+            .collect();
+        match self.sort_symbols {
+            SymbolSort::Name => symbols.sort_by_key(|(name, _)| *name),
+            SymbolSort::Address => symbols.sort_by_key(|(name, sym)| (sym.final_addr, *name)),
+        }
+        // Stable, so each class keeps the order chosen above
+        symbols.sort_by_key(|(_, sym)| sym.bind != STB_LOCAL);
+        let first_non_local = 1 + symbols
+            .iter()
+            .filter(|(_, sym)| sym.bind == STB_LOCAL)
+            .count() as u32;
+        for (name, global) in symbols {
+            let symbol = Symbol {
+                name_offset: strtab.len() as u32,
+                info: (global.bind << 4) | global.sym_type,
+                other: 0,
+                shndx: global
+                    .section
+                    .as_ref()
+                    .map_or(SHN_ABS, |s| section_index[s.as_str()]),
+                // TLS symbols are offsets into the TLS template
+                value: if global.tls {
+                    global.final_addr - tls_vaddr
+                } else {
+                    global.final_addr
+                },
+                size: global.size,
+            };
+            symbol.encode(&mut symtab);
+            strtab.extend_from_slice(name.as_bytes());
+            strtab.push(0);
+        }
+
+        let mut shstrtab = vec![0];
+        let mut name_offset = |name: &str| {
+            let offset = shstrtab.len() as u32;
+            shstrtab.extend_from_slice(name.as_bytes());
+            shstrtab.push(0);
+            offset
+        };
+        let mut headers = vec![SectionHeader {
+            name_offset: 0,
+            sh_type: 0,
+            flags: 0,
+            addr: 0,
+            offset: 0,
+            size: 0,
+            link: 0,
+            info: 0,
+            addralign: 0,
+            entsize: 0,
+        }];
+        for (section, offset) in &sections {
+            let mut header = SectionHeader {
+                name_offset: name_offset(&section.name),
+                offset: *offset,
+                // Links into the inputs' section tables mean nothing here
+                flags: section.header.flags & !SHF_INFO_LINK,
+                link: 0,
+                info: 0,
+                ..section.header.clone()
+            };
+            if let Some(data) = compressed_sections.get(&section.name) {
+                header.flags |= SHF_COMPRESSED;
+                header.size = data.len() as u64;
+                header.addralign = 8;
+            }
+            headers.push(header);
+        }
+        // The dynamic symbol tables link to each other by their output indices
+        if self.export_dynamic {
+            let index = |name: &str| section_index[name] as usize;
+            headers[index(DYNSYM_SECTION)].link = index(DYNSTR_SECTION) as u32;
+            headers[index(DYNSYM_SECTION)].info = 1; // all but the null symbol are global
+            headers[index(HASH_SECTION)].link = index(DYNSYM_SECTION) as u32;
+            headers[index(DYNAMIC_SECTION)].link = index(DYNSTR_SECTION) as u32;
+        }
+        let symtab_index = headers.len() as u32;
+        let overflow = |section: &str| LinkerError::OffsetOverflow {
+            section: section.to_string(),
+        };
+        let symtab_offset = align_up(end, 8).ok_or_else(|| overflow(".symtab"))?;
+        let strtab_offset = symtab_offset
+            .checked_add(symtab.len() as u64)
+            .ok_or_else(|| overflow(".strtab"))?;
+        headers.push(SectionHeader {
+            name_offset: name_offset(".symtab"),
+            sh_type: SHT_SYMTAB,
+            flags: 0,
+            addr: 0,
+            offset: symtab_offset,
+            size: symtab.len() as u64,
+            link: symtab_index + 1,
+            info: first_non_local,
+            addralign: 8,
+            entsize: SYMBOL_SIZE as u64,
+        });
+        headers.push(SectionHeader {
+            name_offset: name_offset(".strtab"),
+            sh_type: SHT_STRTAB,
+            flags: 0,
+            addr: 0,
+            offset: strtab_offset,
+            size: strtab.len() as u64,
+            link: 0,
+            info: 0,
+            addralign: 1,
+            entsize: 0,
+        });
+        let shstrtab_name = name_offset(".shstrtab");
+        let shstrtab_offset = strtab_offset
+            .checked_add(strtab.len() as u64)
+            .ok_or_else(|| overflow(".shstrtab"))?;
+        let shstrndx = headers.len() as u16;
+        headers.push(SectionHeader {
+            name_offset: shstrtab_name,
+            sh_type: SHT_STRTAB,
+            flags: 0,
+            addr: 0,
+            offset: shstrtab_offset,
+            size: shstrtab.len() as u64,
+            link: 0,
+            info: 0,
+            addralign: 1,
+            entsize: 0,
+        });
+
+        let offset = shstrtab_offset
+            .checked_add(shstrtab.len() as u64)
+            .and_then(|end| align_up(end, 8))
+            .ok_or_else(|| overflow("section header table"))?;
+        Ok(SectionHeaderTable {
+            offset,
+            tables: vec![
+                (symtab_offset, symtab),
+                (strtab_offset, strtab),
+                (shstrtab_offset, shstrtab),
+            ],
+            headers,
+            shstrndx,
+        })
+    }
+
+    /// Whether the `exclude_section`/`include_section` filters leave a section out.
+    fn is_filtered_out(&self, filename: &str, section_name: &str) -> bool {
+        let applies = |(file, _): &&(String, String)| glob_match(file, filename);
+        let matches = |(_, section): &(String, String)| glob_match(section, section_name);
+        let mut includes = self.included_sections.iter().filter(applies).peekable();
+        self.excluded_sections.iter().filter(applies).any(matches)
+            || (includes.peek().is_some() && !includes.any(matches))
+    }
+
+    /// Sections reachable from the GC roots, as (file_index, section_index) pairs.
+    fn live_sections(&self) -> HashSet<(usize, usize)> {
+        let definitions: HashMap<&str, (usize, usize)> = self
+            .global_definitions()
+            .into_iter()
+            .map(|(name, (key, _))| (name, key))
+            .collect();
+
+        let mut live = HashSet::new();
+        let mut worklist: Vec<(usize, usize)> = self
+            .entry_symbols()
+            .iter()
+            .find_map(|entry| definitions.get(entry))
+            .into_iter()
+            .chain(
+                self.undefined_roots
+                    .iter()
+                    .filter_map(|name| definitions.get(name.as_str())),
+            )
+            .copied()
+            .collect();
+        for (file_idx, file) in self.input_files.iter().enumerate() {
+            for (section_idx, section) in file.sections.iter().enumerate() {
+                let name: &str = &file.section_names[section_idx];
+                if section.sh_type == SHT_NOTE
+                    || matches!(name, ".init_array" | ".fini_array" | ".preinit_array")
+                    || init_priority(name).is_some()
+                {
+                    worklist.push((file_idx, section_idx));
+                } else if name == ".eh_frame" {
+                    // Unwind tables are kept, but don't keep the code they describe alive
+                    live.insert((file_idx, section_idx));
+                }
+            }
+        }
+
+        while let Some(key @ (file_idx, section_idx)) = worklist.pop() {
+            if !live.insert(key) {
+                continue;
+            }
+            let file = &self.input_files[file_idx];
+            let rela_sections = file
+                .sections
+                .iter()
+                .enumerate()
+                .filter(|(_, s)| is_relocation_section(s) && s.info as usize == section_idx);
+            for (rela_idx, _) in rela_sections {
+                let Some(relocations) = file.relocations(rela_idx) else {
+                    continue;
+                };
+                for rela in relocations {
+                    let sym_index = rela.get_symbol_index() as usize;
+                    let Some(symbol) = file.symbols.get(sym_index) else {
+                        continue;
+                    };
+                    let target = if symbol.get_bind() == STB_LOCAL {
+                        (symbol.shndx != SHN_UNDEF && (symbol.shndx as usize) < file.sections.len())
+                            .then_some((file_idx, symbol.shndx as usize))
+                    } else {
+                        definitions.get(&*file.symbol_names[sym_index]).copied()
+                    };
+                    if let Some(target) = target.filter(|target| !live.contains(target)) {
+                        worklist.push(target);
+                    }
+                }
+            }
+        }
+        live
+    }
+
+    /// The segment `section` is loaded in: an index into the segment map, or one of the
+    /// two default segments after it.
+    fn segment_index(&self, section: &OutputSection) -> usize {
+        // Unmerged sections are named `<name>(<file>:<index>)`
+        let name = section
+            .name
+            .split_once('(')
+            .map_or(section.name.as_str(), |(base, _)| base);
+        self.segment_map
+            .iter()
+            .position(|spec| spec.matches(name))
+            .unwrap_or_else(|| {
+                if in_code_segment(section) {
+                    self.segment_map.len()
+                } else {
+                    self.segment_map.len() + 1
+                }
+            })
+    }
+
+    fn segment_flags(&self, segment: usize) -> u32 {
+        // The last override for a section in the segment wins
+        let overridden = self.segment_flags_overrides.iter().rev().find(|(name, _)| {
+            self.output_sections
+                .get(name)
+                .is_some_and(|section| self.segment_index(section) == segment)
+        });
+        if let Some((_, flags)) = overridden {
+            return *flags;
+        }
+        match segment.checked_sub(self.segment_map.len()) {
+            None => self.segment_map[segment].flags,
+            Some(0) => CODE_SEGMENT_FLAGS,
+            Some(_) => DATA_SEGMENT_FLAGS,
+        }
+    }
+
+    /// All program headers for the current layout, see [`LinkerContext::program_headers`].
+    fn build_program_headers(&self) -> Vec<ProgramHeader> {
+        let mut program_headers: Vec<ProgramHeader> = self
+            .segment_layout()
+            .into_iter()
+            .map(|(header, _)| header)
+            .collect();
+        // File offset of a loaded address
+        let file_offset = |addr: u64| {
+            program_headers
+                .iter()
+                .find(|h| addr >= h.vaddr && addr <= h.vaddr + h.memsz)
+                .map_or(0, |h| h.offset + (addr - h.vaddr))
+        };
+
+        let mut extra_headers = Vec::new();
+        if let Some(dynamic) = self.output_sections.get(DYNAMIC_SECTION) {
+            extra_headers.push(ProgramHeader {
+                p_type: PT_DYNAMIC,
+                flags: PF_R,
+                offset: file_offset(dynamic.header.addr),
+                vaddr: dynamic.header.addr,
+                paddr: dynamic.header.addr,
+                filesz: dynamic.header.size,
+                memsz: dynamic.header.size,
+                align: dynamic.header.addralign,
+            });
+        }
+        if let Some(eh_frame) = self.output_sections.get(".eh_frame") {
+            extra_headers.push(ProgramHeader {
+                p_type: PT_GNU_EH_FRAME,
+                flags: PF_R,
+                offset: file_offset(eh_frame.header.addr),
+                vaddr: eh_frame.header.addr,
+                paddr: eh_frame.header.addr,
+                filesz: eh_frame.header.size,
+                memsz: eh_frame.header.size,
+                align: eh_frame.header.addralign,
+            });
+        }
+        if let Some(tls) = self.tls_header() {
+            extra_headers.push(ProgramHeader {
+                offset: file_offset(tls.vaddr),
+                ..tls
+            });
+        }
+        if let Some(note) = self.output_sections.get(BUILD_ID_SECTION) {
+            extra_headers.push(ProgramHeader {
+                p_type: PT_NOTE,
+                flags: PF_R,
+                offset: file_offset(note.header.addr),
+                vaddr: note.header.addr,
+                paddr: note.header.addr,
+                filesz: note.header.size,
+                memsz: note.header.size,
+                align: note.header.addralign,
+            });
+        }
+        extra_headers.push(ProgramHeader {
+            p_type: PT_GNU_STACK,
+            flags: if self.exec_stack {
+                PF_R | PF_W | PF_X
+            } else {
+                PF_R | PF_W
+            },
+            offset: 0,
+            vaddr: 0,
+            paddr: 0,
+            filesz: 0,
+            memsz: 0,
+            align: 16,
+        });
+        if let Some(relro) = self.relro_header() {
+            extra_headers.push(ProgramHeader {
+                offset: file_offset(relro.vaddr),
+                ..relro
+            });
+        }
+        let interp = self
+            .output_sections
+            .get(INTERP_SECTION)
+            .map(|interp| ProgramHeader {
+                p_type: PT_INTERP,
+                flags: PF_R,
+                offset: file_offset(interp.header.addr),
+                vaddr: interp.header.addr,
+                paddr: interp.header.addr,
+                filesz: interp.header.size,
+                memsz: interp.header.size,
+                align: 1,
+            });
+        program_headers.extend(extra_headers);
+        // PT_INTERP has to come before every PT_LOAD
+        if let Some(interp) = interp {
+            program_headers.insert(0, interp);
+        }
+        program_headers
+    }
+
+    /// The loaded segments in address order: each `PT_LOAD` header with the sections it
+    /// holds, sorted by address.
+    fn segment_layout(&self) -> Vec<(ProgramHeader, Vec<&OutputSection>)> {
+        let base_addr = BASE_ADDR;
+        let page_size = PAGE_SIZE;
+
+        let mut sorted_sections: Vec<_> = self.output_sections.values().collect();
+        sorted_sections.sort_by_key(|s| s.header.addr);
+
+        let segments: Vec<(usize, Vec<&OutputSection>)> = self
+            .loaded_segments()
+            .into_iter()
+            .map(|segment| {
+                let sections = sorted_sections
+                    .iter()
+                    .copied()
+                    .filter(|s| s.header.flags & SHF_ALLOC != 0)
+                    .filter(|s| self.segment_index(s) == segment)
+                    .collect();
+                (segment, sections)
+            })
+            .collect();
+
+        let num_program_headers = self.program_header_count();
+        let headers_total_size = ELF_HEADER_SIZE + (num_program_headers * PROGRAM_HEADER_SIZE);
+
+        println!("Layout calculations:");
+        println!("  Headers total size: 0x{:x}", headers_total_size);
+
+        // The first segment starts at the beginning of the file so it also loads the
+        // headers; every later one starts on a fresh page, in the file and in memory.
+        // Sections are placed at their vaddr's offset from the segment start.
+        let mut layout: Vec<(ProgramHeader, Vec<&OutputSection>)> = Vec::new();
+        for (segment, sections) in segments {
+            let (vaddr, offset) = match layout.last().map(|(header, _)| header) {
+                None => (base_addr, 0),
+                Some(prev) => (
+                    sections.first().map_or_else(
+                        || {
+                            align_up(prev.vaddr + prev.memsz, page_size)
+                                .expect("the end of the image is checked in layout")
+                        },
+                        |s| s.header.addr & !(page_size - 1),
+                    ),
+                    align_up(prev.offset + prev.filesz, page_size)
+                        .expect("the end of the image is checked in layout"),
+                ),
+            };
+            let headers_size = if layout.is_empty() {
+                headers_total_size
+            } else {
+                0
+            };
+            let filesz = sections
+                .iter()
+                .filter(|s| s.header.sh_type != SHT_NOBITS)
+                .map(|s| s.header.addr + s.header.size - vaddr)
+                .max()
+                .unwrap_or(0)
+                .max(headers_size);
+            let memsz = sections
+                .iter()
+                .filter(|s| !is_tbss(s))
+                .map(|s| s.header.addr + s.header.size - vaddr)
+                .max()
+                .unwrap_or(0)
+                .max(filesz);
+            let header = ProgramHeader {
+                p_type: PT_LOAD,
+                flags: self.segment_flags(segment),
+                offset,
+                vaddr,
+                paddr: vaddr,
+                filesz,
+                memsz,
+                align: page_size,
+            };
+            layout.push((header, sections));
+        }
+        layout
+    }
+
+    /// Segments that get a `PT_LOAD`, in address order. Without a segment map both default
+    /// segments are always emitted; with one, only the segments holding sections are.
+    fn loaded_segments(&self) -> Vec<usize> {
+        if self.segment_map.is_empty() {
+            let mut segments = vec![0, 1];
+            self.sort_segments_by_section_order(&mut segments);
+            return segments;
+        }
+        let mut segments: Vec<usize> = self
+            .output_sections
+            .values()
+            .filter(|s| s.header.flags & SHF_ALLOC != 0)
+            .map(|s| self.segment_index(s))
+            .collect();
+        segments.sort_unstable();
+        segments.dedup();
+        if segments.is_empty() {
+            // The headers still need loading
+            segments.push(0);
+        }
+        self.sort_segments_by_section_order(&mut segments);
+        segments
+    }
+
+    /// Moves segments holding sections named in [`LinkerContext::section_order`] to the
+    /// front, ordered by their first listed section. The sort is stable, so the others
+    /// keep their order.
+    fn sort_segments_by_section_order(&self, segments: &mut [usize]) {
+        if self.section_order.is_empty() {
+            return;
+        }
+        let first_listed = |segment: usize| {
+            self.output_sections
+                .values()
+                .filter(|s| s.header.flags & SHF_ALLOC != 0 && self.segment_index(s) == segment)
+                .map(|s| self.section_order_index(&s.name))
+                .min()
+                .unwrap_or(usize::MAX)
+        };
+        segments.sort_by_cached_key(|&segment| first_listed(segment));
+    }
+
+    /// Position of a section in [`LinkerContext::section_order`], or the length of the
+    /// list for unlisted sections.
+    fn section_order_index(&self, name: &str) -> usize {
+        // Unmerged sections are named `<name>(<file>:<index>)`
+        let name = name.split_once('(').map_or(name, |(base, _)| base);
+        self.section_order
+            .iter()
+            .position(|listed| listed == name)
+            .unwrap_or(self.section_order.len())
+    }
+
+    fn program_header_count(&self) -> u64 {
+        let mut count = self.loaded_segments().len() as u64; // PT_LOAD
+        count += 1; // PT_GNU_STACK
+        if self.dynamic_linker.is_some() {
+            count += 1; // PT_INTERP
+        }
+        if self.build_id != BuildIdKind::None {
+            count += 1; // PT_NOTE
+        }
+        if self.export_dynamic {
+            count += 1; // PT_DYNAMIC
+        }
+        if self.output_sections.contains_key(".eh_frame") {
+            count += 1; // PT_GNU_EH_FRAME
+        }
+        if self
+            .output_sections
+            .values()
+            .any(|s| s.header.flags & SHF_TLS != 0)
+        {
+            count += 1; // PT_TLS
+        }
+        if self.output_sections.values().any(is_relro) {
+            count += 1; // PT_GNU_RELRO
+        }
+        count
+    }
+
+    /// The PT_GNU_RELRO header covering the sections the loader makes read-only once
+    /// startup relocation is done, up to the end of their last page: loaders round the end
+    /// down. `offset` is left for the writer to fill in.
+    fn relro_header(&self) -> Option<ProgramHeader> {
+        let relro_sections: Vec<_> = self
+            .output_sections
+            .values()
+            .filter(|s| is_relro(s))
+            .collect();
+        let start = relro_sections.iter().map(|s| s.header.addr).min()?;
+        let end = relro_sections
+            .iter()
+            .map(|s| s.header.addr + s.header.size)
+            .max()?;
+        // The layout starts whatever follows on the next page
+        let end = align_up(end, PAGE_SIZE)?;
+        Some(ProgramHeader {
+            p_type: PT_GNU_RELRO,
+            flags: PF_R,
+            offset: 0,
+            vaddr: start,
+            paddr: start,
+            filesz: end - start,
+            memsz: end - start,
+            align: 1,
+        })
+    }
+
+    /// The PT_TLS header describing the TLS template: .tdata is its initialized image
+    /// and .tbss the zeroed remainder. `offset` is left for the writer to fill in.
+    fn tls_header(&self) -> Option<ProgramHeader> {
+        let tls_sections: Vec<_> = self
+            .output_sections
+            .values()
+            .filter(|s| s.header.flags & SHF_TLS != 0)
+            .collect();
+        let start = tls_sections.iter().map(|s| s.header.addr).min()?;
+        let filesz = tls_sections
+            .iter()
+            .filter(|s| s.header.sh_type != SHT_NOBITS)
+            .map(|s| s.header.addr + s.header.size - start)
+            .max()
+            .unwrap_or(0);
+        let memsz = tls_sections
+            .iter()
+            .map(|s| s.header.addr + s.header.size - start)
+            .max()?;
+        Some(ProgramHeader {
+            p_type: PT_TLS,
+            flags: PF_R,
+            offset: 0,
+            vaddr: start,
+            paddr: start,
+            filesz,
+            memsz,
+            align: tls_sections
+                .iter()
+                .map(|s| s.header.addralign)
+                .max()
+                .unwrap_or(1),
+        })
+    }
+
+    /// The instruction the gap between `previous` and `section`, neighbours in address
+    /// order, is padded with, if any, see [`LinkerContext::code_fill`].
+    fn code_padding(
+        &self,
+        previous: Option<&OutputSection>,
+        section: &OutputSection,
+    ) -> Option<u32> {
+        let executable = |s: &OutputSection| s.header.flags & SHF_EXECINSTR != 0;
+        if !executable(section) || !previous.is_some_and(executable) {
+            return None;
+        }
+        self.code_fill.instruction(TargetArch::AArch64)
+    }
+
+    /// See [`LinkerContext::trim_zero_tails`]. The zeros keep their addresses: the tail is
+    /// cut from the section and the `.bss` that follows it grows down over it (or a new
+    /// `.bss` is made, under another name if `.bss` is taken), so only which section holds
+    /// those addresses changes.
+    fn move_zero_tails_to_bss(&mut self) {
+        let mut by_addr: Vec<&OutputSection> = self
+            .output_sections
+            .values()
+            .filter(|s| s.header.flags & SHF_ALLOC != 0 && !is_tbss(s))
+            .collect();
+        by_addr.sort_by_key(|s| (s.header.addr, s.header.size));
+        // (section to trim, the NOBITS section after it, if any, and the new size)
+        let mut trims: Vec<(String, Option<String>, u64)> = Vec::new();
+        for segment in self.loaded_segments() {
+            let sections: Vec<_> = by_addr
+                .iter()
+                .filter(|s| self.segment_index(s) == segment)
+                .collect();
+            let Some(last) = sections
+                .iter()
+                .rposition(|s| s.header.sh_type != SHT_NOBITS)
+            else {
+                continue;
+            };
+            let section = sections[last];
+            if section.header.flags & (SHF_WRITE | SHF_TLS) != SHF_WRITE {
+                continue;
+            }
+            let next = sections.get(last + 1).map(|s| &s.name);
+            // The new start of the NOBITS part has to suit the alignment of what follows
+            let align = next.map_or(1, |name| self.output_sections[name].header.addralign.max(1));
+            let content_len = section
+                .data
+                .iter()
+                .rposition(|&b| b != 0)
+                .map_or(0, |i| i + 1);
+            let Some(end) = align_up(section.header.addr + content_len as u64, align) else {
+                continue;
+            };
+            let keep = end - section.header.addr;
+            if keep < section.header.size {
+                trims.push((section.name.clone(), next.cloned(), keep));
+            }
+        }
+
+        for (name, next, keep) in trims {
+            let section = self.output_sections.get_mut(&name).expect("found above");
+            let tail_start = section.header.addr + keep;
+            let tail_end = section.header.addr + section.header.size;
+            section.header.size = keep;
+            section.data.truncate(keep as usize);
+            let trimmed_header = section.header.clone();
+            let bss_name = match next {
+                Some(next) => {
+                    let bss = self.output_sections.get_mut(&next).expect("found above");
+                    bss.header.size += bss.header.addr - tail_start;
+                    bss.header.addr = tail_start;
+                    next
+                }
+                None => {
+                    // Another segment may already have made or kept a `.bss`
+                    let bss_name = (0..)
+                        .map(|n| match n {
+                            0 => ".bss".to_string(),
+                            n => format!(".bss.{n}"),
+                        })
+                        .find(|name| !self.output_sections.contains_key(name))
+                        .expect("section names run out eventually");
+                    let header = SectionHeader {
+                        sh_type: SHT_NOBITS,
+                        addr: tail_start,
+                        size: tail_end - tail_start,
+                        addralign: 1,
+                        ..trimmed_header
+                    };
+                    let bss = OutputSection {
+                        name: bss_name.clone(),
+                        header,
+                        data: Vec::new(),
+                    };
+                    self.output_sections.insert(bss_name.clone(), bss);
+                    bss_name
+                }
+            };
+            println!(
+                "Moved {:#x} trailing zero bytes of {name} to {bss_name}",
+                tail_end - tail_start
+            );
+            let locals = self.local_symbols.iter_mut().map(|(_, symbol)| symbol);
+            for symbol in self.global_symbols.values_mut().chain(locals) {
+                if symbol.section.as_ref() == Some(&name) && symbol.final_addr >= tail_start {
+                    symbol.section = Some(bss_name.clone());
+                }
+            }
+        }
+    }
+
+    /// The named local symbols of the inputs at their final addresses, for the emitted
+    /// `.symtab`. Section and file symbols, and those in sections that were left out,
+    /// aren't included.
+    fn placed_local_symbols(&self) -> Vec<(String, GlobalSymbol)> {
+        let mut locals = Vec::new();
+        for (file_idx, file) in self.input_files.iter().enumerate() {
+            for (symbol, name) in file.symbols.iter().zip(&file.symbol_names).skip(1) {
+                if symbol.get_bind() != STB_LOCAL
+                    || matches!(symbol.get_type(), STT_SECTION | STT_FILE)
+                    || name.is_empty()
+                {
+                    continue;
+                }
+                let (final_addr, section) = match symbol.shndx {
+                    SHN_UNDEF => continue,
+                    SHN_ABS => (symbol.value, None),
+                    shndx => {
+                        let key = (file_idx, shndx as usize);
+                        let Some(output_name) = self.input_section_outputs.get(&key) else {
+                            continue;
+                        };
+                        let base = self.output_sections[output_name].header.addr;
+                        let input_section_offset = self.input_section_offsets[&key];
+                        (
+                            base + input_section_offset + symbol.value,
+                            Some(output_name.clone()),
+                        )
+                    }
+                };
+                locals.push((
+                    name.to_string(),
+                    GlobalSymbol {
+                        _name: name.to_string(),
+                        final_addr,
+                        tls: symbol.get_type() == STT_TLS,
+                        sym_type: symbol.get_type(),
+                        bind: STB_LOCAL,
+                        size: symbol.size,
+                        section,
+                    },
+                ));
+            }
+        }
+        locals
+    }
+
+    /// Fails the phase that just ran if it, or anything before it, reported a warning
+    /// while warnings are fatal.
+    fn check_warnings(&self) -> Result<(), LinkerError> {
+        match self.diagnostics.fatal_warning() {
+            Some(message) => Err(LinkerError::FatalWarning {
+                message: message.to_string(),
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Checks that `phase` can run, which it can only directly after `previous`: running
+    /// a phase early would work on an incomplete link, and running one twice would redo
+    /// its work. The link moves on to `phase` once the phase has succeeded.
+    fn check_phase(&self, previous: Phase, phase: Phase) -> Result<(), LinkerError> {
+        if self.phase != previous {
+            return Err(LinkerError::InvalidPhaseOrder {
+                phase: phase.method(),
+                previous: previous.method(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Checks that inputs can still be added by `method`: the layout only sees the inputs
+    /// added before it.
+    fn check_adding_inputs(&self, method: &'static str) -> Result<(), LinkerError> {
+        if self.phase != Phase::AddFiles {
+            return Err(LinkerError::PhasePassed {
+                phase: method,
+                next: Phase::Layout.method(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Address the configured [`EntryPoint`] refers to, if it is defined.
+    fn entry_point(&self) -> Option<u64> {
+        let symbol = match &self.entry {
+            EntryPoint::Default => self
+                .global_symbols
+                .get("_start")
+                .or_else(|| self.global_symbols.get("main")),
+            EntryPoint::Symbol(name) => self.global_symbols.get(name),
+            EntryPoint::Address(addr) => return Some(*addr),
+            EntryPoint::OffsetFromBase(offset) => return BASE_ADDR.checked_add(*offset),
+        };
+        symbol.map(|symbol| symbol.final_addr)
+    }
+
+    /// The symbols the entry point is looked up by, in order of preference; none when
+    /// it is given as an address.
+    fn entry_symbols(&self) -> Vec<&str> {
+        match &self.entry {
+            EntryPoint::Default => vec!["_start", "main"],
+            EntryPoint::Symbol(name) => vec![name.as_str()],
+            EntryPoint::Address(_) | EntryPoint::OffsetFromBase(_) => Vec::new(),
+        }
+    }
+
+    /// The input section and value of the definition each global resolves to, as picked
+    /// by `resolve_symbols`, for use before it has run.
+    fn global_definitions(&self) -> HashMap<&str, ((usize, usize), u64)> {
+        let mut definitions = HashMap::new();
+        for (file_idx, file) in self.input_files.iter().enumerate() {
+            for (symbol, name) in file.symbols.iter().zip(&file.symbol_names) {
+                let name: &str = name;
+                if is_global_definition(symbol, name)
+                    && (symbol.shndx as usize) < file.sections.len()
+                {
+                    let definition = ((file_idx, symbol.shndx as usize), symbol.value);
+                    if self.resolution_order == ResolutionOrder::Last {
+                        definitions.insert(name, definition);
+                    } else {
+                        definitions.entry(name).or_insert(definition);
+                    }
+                }
+            }
+        }
+        definitions
+    }
+
+    /// The input section defining the entry point and the entry's offset into it, see
+    /// [`LinkerContext::align_entry`].
+    fn entry_input(&self) -> Option<((usize, usize), u64)> {
+        let names = self.entry_symbols();
+        if names.is_empty() {
+            return None;
+        }
+        let definitions = self.global_definitions();
+        names
+            .iter()
+            .find_map(|name| definitions.get(name).copied())
+            .or_else(|| {
+                self.input_files
+                    .iter()
+                    .enumerate()
+                    .find_map(|(file_idx, file)| {
+                        let section_idx =
+                            file.section_names.iter().position(|name| name == ".text")?;
+                        Some(((file_idx, section_idx), 0))
+                    })
+            })
+    }
+
+    /// Final contents of an output section.
+    fn output_section_data<'s>(&'s self, section: &'s OutputSection) -> Cow<'s, [u8]> {
+        match section.name.as_str() {
+            BUILD_ID_SECTION => Cow::Owned(self.build_id_note_data()),
+            DYNSYM_SECTION if self.export_dynamic => Cow::Owned(self.dynamic_symbol_table()),
+            DYNAMIC_SECTION if self.export_dynamic => Cow::Owned(self.dynamic_section_data()),
+            _ => Cow::Borrowed(&section.data),
+        }
+    }
+
+    /// Creates the sections of [`LinkerContext::export_dynamic`]. The exported names are
+    /// known once the inputs are placed, so `.dynstr` and `.hash` are filled in here;
+    /// `.dynsym` and `.dynamic` hold addresses and are only generated on output.
+    fn add_dynamic_sections(&mut self) {
+        // Defined globals whose definition, as it will be resolved, is in a placed
+        // allocatable section, and absolute ones
+        let definitions = self.global_definitions();
+        let placed = |name: &str| {
+            definitions.get(name).is_some_and(|&(key, _)| {
+                self.input_section_outputs.contains_key(&key)
+                    && self.input_files[key.0].sections[key.1].flags & SHF_ALLOC != 0
+            })
+        };
+        let mut names: Vec<String> = self
+            .input_files
+            .iter()
+            .flat_map(|file| file.symbols.iter().zip(&file.symbol_names))
+            .filter(|(symbol, name)| {
+                is_global_definition(symbol, name) && (symbol.shndx == SHN_ABS || placed(name))
+            })
+            .map(|(_, name)| name.to_string())
+            .collect();
+        names.sort();
+        names.dedup();
+
+        let dynsym_size = (names.len() + 1) * SYMBOL_SIZE;
+        // DT_HASH, DT_STRTAB, DT_SYMTAB, DT_STRSZ, DT_SYMENT and DT_NULL
+        let dynamic_size = 6 * DYNAMIC_ENTRY_SIZE as usize;
+        let sections = [
+            (
+                DYNSYM_SECTION,
+                SHT_DYNSYM,
+                8,
+                SYMBOL_SIZE as u64,
+                vec![0; dynsym_size],
+            ),
+            (
+                DYNSTR_SECTION,
+                SHT_STRTAB,
+                1,
+                0,
+                dynamic_string_table(&names),
+            ),
+            (HASH_SECTION, SHT_HASH, 8, 4, hash_table(&names)),
+            (
+                DYNAMIC_SECTION,
+                SHT_DYNAMIC,
+                8,
+                DYNAMIC_ENTRY_SIZE,
+                vec![0; dynamic_size],
+            ),
+        ];
+        for (name, sh_type, addralign, entsize, data) in sections {
+            self.output_sections.insert(
+                name.to_string(),
+                OutputSection {
+                    name: name.to_string(),
+                    header: SectionHeader {
+                        name_offset: 0,
+                        sh_type,
+                        flags: SHF_ALLOC,
+                        addr: 0,
+                        offset: 0,
+                        size: data.len() as u64,
+                        link: 0,
+                        info: 0,
+                        addralign,
+                        entsize,
+                    },
+                    data,
+                },
+            );
+        }
+        self.dynamic_symbols = names;
+    }
+
+    /// `.dynsym`: the null symbol, then each exported symbol at its final address. A
+    /// symbol that didn't make it into the output is left undefined.
+    fn dynamic_symbol_table(&self) -> Vec<u8> {
+        let section_index = self.loaded_section_indices();
+        let tls_vaddr = self.tls_header().map_or(0, |tls| tls.vaddr);
+        let mut table = vec![0; SYMBOL_SIZE];
+        let mut name_offset = 1;
+        for name in &self.dynamic_symbols {
+            let symbol = match self.global_symbols.get(name) {
+                Some(global) => Symbol {
+                    name_offset,
+                    info: (global.bind << 4) | global.sym_type,
+                    other: 0,
+                    // Only globals in loaded sections are exported; should one end up
+                    // elsewhere anyway, it has no address in the image to export
+                    shndx: match &global.section {
+                        None => SHN_ABS,
+                        Some(s) => section_index.get(s.as_str()).copied().unwrap_or(SHN_UNDEF),
+                    },
+                    value: if global.tls {
+                        global.final_addr - tls_vaddr
+                    } else {
+                        global.final_addr
+                    },
+                    size: global.size,
+                },
+                None => Symbol {
+                    name_offset,
+                    info: STB_GLOBAL << 4,
+                    other: 0,
+                    shndx: SHN_UNDEF,
+                    value: 0,
+                    size: 0,
+                },
+            };
+            symbol.encode(&mut table);
+            name_offset += name.len() as u32 + 1;
+        }
+        table
+    }
+
+    /// `.dynamic`, pointing the loader at the other sections of
+    /// [`LinkerContext::export_dynamic`].
+    fn dynamic_section_data(&self) -> Vec<u8> {
+        let section = |name: &str| &self.output_sections[name].header;
+        dynamic_section(&[
+            (DT_HASH, section(HASH_SECTION).addr),
+            (DT_STRTAB, section(DYNSTR_SECTION).addr),
+            (DT_SYMTAB, section(DYNSYM_SECTION).addr),
+            (DT_STRSZ, section(DYNSTR_SECTION).size),
+            (DT_SYMENT, SYMBOL_SIZE as u64),
+        ])
+    }
+
+    /// Allocatable output sections in the order of the section header table, which they
+    /// lead, by their section header index.
+    fn loaded_section_indices(&self) -> HashMap<&str, u16> {
+        self.loaded_sections()
+            .iter()
+            .enumerate()
+            .map(|(i, s)| (s.name.as_str(), i as u16 + 1))
+            .collect()
+    }
+
+    /// Allocatable output sections by address, as listed in the section header table.
+    fn loaded_sections(&self) -> Vec<&OutputSection> {
+        let mut loaded: Vec<_> = self
+            .output_sections
+            .values()
+            .filter(|s| s.header.flags & SHF_ALLOC != 0)
+            .collect();
+        loaded.sort_by(|a, b| (a.header.addr, &a.name).cmp(&(b.header.addr, &b.name)));
+        loaded
+    }
+
+    /// The build-id note, with the id computed over all other output sections.
+    fn build_id_note_data(&self) -> Vec<u8> {
+        let mut sections: Vec<_> = self
+            .output_sections
+            .values()
+            .filter(|s| s.name != BUILD_ID_SECTION && s.header.sh_type != SHT_NOBITS)
+            .collect();
+        // Unloaded sections all sit at address 0, so break ties by name
+        sections.sort_by(|a, b| (a.header.addr, &a.name).cmp(&(b.header.addr, &b.name)));
+        let contents: Vec<u8> = sections
+            .iter()
+            .flat_map(|s| self.output_section_data(s).into_owned())
+            .collect();
+        build_id_note(&self.build_id.compute(&contents))
+    }
+}
+
+/// The architecture whose relocation types [`apply_relocations_to`] applies.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TargetArch {
+    #[default]
+    AArch64,
+}
+
+/// Applies `relocs` to `section_bytes`, the contents of a section loaded at
+/// `section_vaddr`, with the same relocation math as [`LinkerContext::apply_relocations`]
+/// but without linking anything. `resolver` maps a relocation's symbol index to the
+/// symbol's address. Addends are taken from the `Rela`s as they are. There is no GOT, so
+/// GOT-relative relocations fail.
+pub fn apply_relocations_to(
+    section_bytes: &mut [u8],
+    relocs: &[Rela],
+    resolver: impl Fn(u32) -> Option<u64>,
+    section_vaddr: u64,
+    arch: TargetArch,
+) -> Result<(), RelocationError> {
+    let TargetArch::AArch64 = arch;
+    for rela in relocs {
+        let s = resolver(rela.get_symbol_index()).ok_or(RelocationError::UnresolvedSymbol {
+            symbol: rela.get_symbol_index(),
+            offset: rela.offset,
+        })?;
+        let place = usize::try_from(rela.offset).map_err(|_| RelocationError::OutOfBounds {
+            r_type: rela.get_type(),
+            offset: rela.offset,
+        })?;
+        let p = section_vaddr.wrapping_add(rela.offset);
+        relocate(
+            section_bytes,
+            place,
+            rela.get_type(),
+            s,
+            rela.addend,
+            p,
+            None,
+        )?;
+    }
+    Ok(())
+}
+
+/// Patches the place at `data[offset..]`, whose address is `p`, for a relocation of type
+/// `r_type` against a symbol at `s` with addend `a`. `got` is the GOT's address, if there
+/// is one.
+fn relocate(
+    data: &mut [u8],
+    offset: usize,
+    r_type: u32,
+    s: u64,
+    a: i64,
+    p: u64,
+    got: Option<u64>,
+) -> Result<(), RelocationError> {
+    let error_offset = offset as u64;
+    let out_of_range = || RelocationError::OutOfRange {
+        r_type,
+        offset: error_offset,
+    };
+    // Instructions are 4-byte aligned; patching at any other offset would read across an
+    // instruction boundary
+    if is_instruction_relocation(r_type) && !p.is_multiple_of(4) {
+        return Err(RelocationError::Misaligned {
+            r_type,
+            offset: error_offset,
+        });
+    }
+    let supported = matches!(
+        r_type,
+        R_AARCH64_ABS64
+            | R_AARCH64_GOTREL64
+            | R_AARCH64_CALL26
+            | R_AARCH64_JUMP26
+            | R_AARCH64_CONDBR19
+            | R_AARCH64_TSTBR14
+            | R_AARCH64_PREL32
+            | R_AARCH64_PREL64
+            | R_AARCH64_ADR_PREL_LO21
+    ) || movw_uabs_group(r_type).is_some();
+    if !supported {
+        return Err(RelocationError::Unsupported {
+            r_type,
+            offset: error_offset,
+        });
+    }
+    let out_of_bounds = RelocationError::OutOfBounds {
+        r_type,
+        offset: error_offset,
+    };
+    if r_type == R_AARCH64_ABS64 {
+        // Absolute 64-bit: S + A, typically a pointer initializer in data
+        let place: &mut [u8; 8] = place_mut(data, offset).ok_or(out_of_bounds)?;
+        *place = s.wrapping_add_signed(a).to_le_bytes();
+        return Ok(());
+    }
+    if r_type == R_AARCH64_GOTREL64 {
+        // S + A - GOT
+        let got = got.ok_or(RelocationError::NoGot {
+            r_type,
+            offset: error_offset,
+        })?;
+        let place: &mut [u8; 8] = place_mut(data, offset).ok_or(out_of_bounds)?;
+        *place = s.wrapping_add_signed(a).wrapping_sub(got).to_le_bytes();
+        return Ok(());
+    }
+    if r_type == R_AARCH64_PREL64 {
+        // PC-relative 64-bit: S + A - P, negative when the symbol is below the place
+        let place: &mut [u8; 8] = place_mut(data, offset).ok_or(out_of_bounds)?;
+        *place = s.wrapping_add_signed(a).wrapping_sub(p).to_le_bytes();
+        return Ok(());
+    }
+    let place: &mut [u8; 4] = place_mut(data, offset).ok_or(out_of_bounds)?;
+    let word = u32::from_le_bytes(*place);
+
+    let patched = match r_type {
+        R_AARCH64_CALL26 | R_AARCH64_JUMP26 => {
+            let imm26 = branch26_imm(s, a, p).ok_or_else(out_of_range)?;
+            (word & 0xFC00_0000) | imm26
+        }
+        // B.cond and CBZ/CBNZ keep imm19 in bits [23:5], TBZ/TBNZ keep imm14 in [18:5]
+        R_AARCH64_CONDBR19 => {
+            let imm19 = short_branch_imm(s, a, p, 19).ok_or_else(out_of_range)?;
+            (word & !(0x7_FFFF << 5)) | (imm19 << 5)
+        }
+        R_AARCH64_TSTBR14 => {
+            let imm14 = short_branch_imm(s, a, p, 14).ok_or_else(out_of_range)?;
+            (word & !(0x3FFF << 5)) | (imm14 << 5)
+        }
+        R_AARCH64_PREL32 => prel32(s, a, p).ok_or_else(out_of_range)?,
+        R_AARCH64_ADR_PREL_LO21 => {
+            let imm = adr_imm(s, a, p).ok_or_else(out_of_range)?;
+            (word & !ADR_IMM_MASK) | imm
+        }
+        _ => {
+            let (group, checked) = movw_uabs_group(r_type).expect("checked above");
+            let imm16 = movw_uabs_imm(s, a, group, checked).ok_or_else(out_of_range)?;
+            // MOVZ/MOVK keep imm16 in bits [20:5]
+            (word & !(0xFFFF << 5)) | (imm16 << 5)
+        }
+    };
+    *place = patched.to_le_bytes();
+    Ok(())
+}
+
+/// The `N` bytes at `offset` in `data`, if they are all there.
+fn place_mut<const N: usize>(data: &mut [u8], offset: usize) -> Option<&mut [u8; N]> {
+    data.get_mut(offset..offset.checked_add(N)?)?
+        .try_into()
+        .ok()
+}
+
+/// `SHT_RELA`, or `SHT_REL` whose addends are stored in the relocated places.
+fn is_relocation_section(section: &SectionHeader) -> bool {
+    section.sh_type == SHT_RELA || section.sh_type == SHT_REL
+}
+
+/// The output section and priority of a `.init_array.NNNNN` or `.fini_array.NNNNN` input
+/// section: constructors and destructors with a priority run in ascending priority order,
+/// before the ones in plain `.init_array` and `.fini_array`.
+fn init_priority(name: &str) -> Option<(&'static str, u32)> {
+    [".init_array", ".fini_array"]
+        .into_iter()
+        .find_map(|output| {
+            let digits = name.strip_prefix(output)?.strip_prefix('.')?;
+            if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+                return None;
+            }
+            Some((output, digits.parse().ok()?))
+        })
+}
+
+/// Checks the indices of an input before it is linked, see [`InputFile::check_indices`].
+fn check_input_indices(input: &InputFile) -> Result<(), LinkerError> {
+    input
+        .check_indices()
+        .map_err(|reason| LinkerError::Malformed {
+            file: input.filename.clone(),
+            reason,
+        })
+}
+
+/// Parses an ELF object held in `content`. Its indices are checked when it is added, see
+/// [`InputFile::check_indices`].
+fn parse_input_file(filename: String, content: &[u8]) -> Result<InputFile<'_>, LinkerError> {
+    let malformed = |reason: &str| LinkerError::Malformed {
+        file: filename.clone(),
+        reason: reason.to_string(),
+    };
+    let (_, header) = parse_elf_header(content).map_err(|_| malformed("invalid ELF header"))?;
+    check_input_header(&filename, &header)?;
+    let (_, sections) = parse_section_header_table(content, &header)
+        .map_err(|_| malformed("invalid section header table"))?;
+
+    // The string tables are looked up through indices and offsets taken from the file
+    let table = |index: usize, what: &str| {
+        let header = sections
+            .get(index)
+            .ok_or_else(|| malformed(&format!("{what} index {index} is out of range")))?;
+        section_data(content, header).map_err(|err| malformed(&format!("{what}: {err}")))
+    };
+    let shstrtab_data = table(
+        section_name_table_index(&header, &sections),
+        "section name table",
+    )?;
+
+    // An object without a symbol table, e.g. plain data, has no symbols to resolve
+    let symtab = sections
+        .iter()
+        .enumerate()
+        .find(|(_, h)| h.sh_type == SHT_SYMTAB);
+    let (mut symbols, strtab_data) = match symtab {
+        Some((_, symtab_h)) => {
+            let strtab_data = table(symtab_h.link as usize, "symbol string table")?;
+            let (_, symbols) = parse_symbol_table(content, symtab_h)
+                .map_err(|_| malformed("invalid symbol table"))?;
+            (symbols, strtab_data)
+        }
+        None => (Vec::new(), &[][..]),
+    };
+    // A symbol whose section index doesn't fit in st_shndx has SHN_XINDEX there, and
+    // its real index in the SHT_SYMTAB_SHNDX table linked to the symbol table
+    if let Some((symtab_idx, _)) = symtab
+        && symbols.iter().any(|symbol| symbol.shndx == SHN_XINDEX)
+    {
+        let shndx_h = sections
+            .iter()
+            .find(|h| h.sh_type == SHT_SYMTAB_SHNDX && h.link as usize == symtab_idx)
+            .ok_or_else(|| malformed("symbols use SHN_XINDEX without a SHT_SYMTAB_SHNDX table"))?;
+        let indices = section_data(content, shndx_h)
+            .map_err(|err| malformed(&format!("extended section index table: {err}")))?;
+        for (symbol_idx, symbol) in symbols.iter_mut().enumerate() {
+            if symbol.shndx != SHN_XINDEX {
+                continue;
+            }
+            let index = indices
+                .get(symbol_idx * 4..symbol_idx * 4 + 4)
+                .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+                .ok_or_else(|| {
+                    malformed(&format!(
+                        "symbol {symbol_idx} has no extended section index"
+                    ))
+                })?;
+            if index == SHN_UNDEF as u32 {
+                return Err(malformed(&format!(
+                    "symbol {symbol_idx} has extended section index 0"
+                )));
+            }
+            // Indices are kept in the symbol's u16 st_shndx, where the reserved range
+            // stands for SHN_ABS and the like, so sections from there on can't be
+            // told apart from them and are rejected
+            symbol.shndx = u16::try_from(index)
+                .ok()
+                .filter(|&index| index < SHN_LORESERVE)
+                .ok_or_else(|| {
+                    malformed(&format!(
+                        "symbol {symbol_idx} is in section {index}, but section indices \
+                         of {SHN_LORESERVE:#x} and up aren't supported"
+                    ))
+                })?;
+        }
+    }
+    if symbols.first().is_some_and(|symbol| !symbol.is_null()) {
+        return Err(malformed(
+            "the first symbol table entry is not the null symbol",
+        ));
+    }
+
+    let section_names: Vec<&str> = sections
+        .iter()
+        .map(|section| get_section_name(shstrtab_data, section).unwrap_or(""))
+        .collect();
+    let symbol_names = symbols
+        .iter()
+        .map(|symbol| Cow::Borrowed(get_symbol_name(strtab_data, symbol).unwrap_or("")))
+        .collect();
+    let mut decompressed_sections = HashMap::new();
+    for (section_idx, section) in sections.iter().enumerate() {
+        // Checked for every section, so their contents can be sliced out later on
+        let name = section_names[section_idx];
+        let compressed = section_data(content, section)
+            .map_err(|err| malformed(&format!("section {name}: {err}")))?;
+        if section.flags & SHF_COMPRESSED == 0 || section.sh_type == SHT_NOBITS {
+            continue;
+        }
+        let (_, chdr) = parse_compression_header(compressed)
+            .map_err(|_| malformed(&format!("section {name} has no compression header")))?;
+        let data = decompress_section(&chdr, compressed)
+            .ok_or_else(|| LinkerError::UnsupportedCompression {
+                file: filename.clone(),
+                section: name.to_string(),
+                ch_type: chdr.ch_type,
+            })?
+            .map_err(|err| malformed(&format!("section {name}: {err}")))?;
+        decompressed_sections.insert(section_idx, (chdr.ch_addralign, data));
+    }
+
+    Ok(InputFile {
+        filename,
+        content,
+        header,
+        sections,
+        symbols,
+        section_names: section_names.into_iter().map(Cow::Borrowed).collect(),
+        symbol_names,
+        section_data: Vec::new(),
+        decompressed_sections,
+    })
+}
+
+/// Checks that an input, read from a file or already parsed, is something this linker
+/// can link: the rest of the link assumes AArch64 ELF64 little-endian layouts.
+fn check_input_header(file: &str, header: &ElfHeader) -> Result<(), LinkerError> {
+    if header.class != EI_CLASS_64 {
+        return Err(LinkerError::UnsupportedClass {
+            file: file.to_string(),
+            class: header.class,
+        });
+    }
+    if header.data != EI_DATA_2LSB {
+        return Err(LinkerError::UnsupportedEndianness {
+            file: file.to_string(),
+            data: header.data,
+        });
+    }
+    // Linked files have no relocations left to apply, and their sections already
+    // have addresses, so they can't be merged into the output
+    if header.e_type != ET_REL {
+        return Err(LinkerError::NotRelocatable {
+            file: file.to_string(),
+            e_type: header.e_type,
+        });
+    }
+    if header.e_machine != EM_AARCH64 {
+        return Err(LinkerError::UnsupportedMachine {
+            file: file.to_string(),
+            machine: header.e_machine,
+        });
+    }
+    Ok(())
+}
+
+/// Index of the first relocation section whose `sh_info` isn't the index of a section.
+fn missing_relocation_target(sections: &[SectionHeader]) -> Option<usize> {
+    sections.iter().position(|section| {
+        is_relocation_section(section) && section.info as usize >= sections.len()
+    })
+}
+
+/// Relocations that patch an immediate field inside an AArch64 instruction.
+fn is_instruction_relocation(r_type: u32) -> bool {
+    matches!(
+        r_type,
+        R_AARCH64_CALL26
+            | R_AARCH64_JUMP26
+            | R_AARCH64_CONDBR19
+            | R_AARCH64_TSTBR14
+            | R_AARCH64_ADR_PREL_LO21
+    ) || movw_uabs_group(r_type).is_some()
+}
+
+/// The group (which 16-bit slice) of a `R_AARCH64_MOVW_UABS_G*` relocation, and whether
+/// it checks that the value fits below the end of that slice (the `_NC` variants don't).
+fn movw_uabs_group(r_type: u32) -> Option<(u32, bool)> {
+    match r_type {
+        R_AARCH64_MOVW_UABS_G0 => Some((0, true)),
+        R_AARCH64_MOVW_UABS_G0_NC => Some((0, false)),
+        R_AARCH64_MOVW_UABS_G1 => Some((1, true)),
+        R_AARCH64_MOVW_UABS_G1_NC => Some((1, false)),
+        R_AARCH64_MOVW_UABS_G2 => Some((2, true)),
+        R_AARCH64_MOVW_UABS_G2_NC => Some((2, false)),
+        // Nothing is left above bit 63 to check
+        R_AARCH64_MOVW_UABS_G3 => Some((3, false)),
+        _ => None,
+    }
+}
+
+/// The imm16 field of a MOVZ/MOVK patched by `R_AARCH64_MOVW_UABS_G<group>`: bits
+/// [16 * group + 15 : 16 * group] of `S + A`.
+///
+/// Returns `None` when a checked relocation's value needs bits above its slice.
+fn movw_uabs_imm(s: u64, a: i64, group: u32, checked: bool) -> Option<u32> {
+    let x = if checked {
+        let x = s.checked_add_signed(a)?;
+        if x >> (16 * (group + 1)) != 0 {
+            return None;
+        }
+        x
+    } else {
+        s.wrapping_add_signed(a)
+    };
+    Some(((x >> (16 * group)) & 0xFFFF) as u32)
+}
+
+/// The imm26 field of a B/BL patched by `R_AARCH64_CALL26`/`R_AARCH64_JUMP26`: bits [27:2] of `S + A - P`.
+///
+/// Returns `None` when the displacement doesn't fit the ±128MB branch range.
+fn branch26_imm(s: u64, a: i64, p: u64) -> Option<u32> {
+    let x = (s as i64).checked_add(a)?.checked_sub(p as i64)?;
+    if !(-(1 << 27)..(1 << 27)).contains(&x) {
+        return None;
+    }
+    // The arithmetic shift keeps the sign of backward branches
+    Some(((x >> 2) & 0x03FF_FFFF) as u32)
+}
+
+/// The `bits`-wide immediate of a conditional branch patched by `R_AARCH64_CONDBR19`
+/// (19 bits) or `R_AARCH64_TSTBR14` (14 bits): bits [bits + 1:2] of `S + A - P`.
+///
+/// Returns `None` when the displacement doesn't fit the branch range, ±1MB and ±32KB.
+fn short_branch_imm(s: u64, a: i64, p: u64, bits: u32) -> Option<u32> {
+    let x = (s as i64).checked_add(a)?.checked_sub(p as i64)?;
+    if !(-(1 << (bits + 1))..(1 << (bits + 1))).contains(&x) {
+        return None;
+    }
+    Some(((x >> 2) as u32) & ((1 << bits) - 1))
+}
+
+/// The word patched by `R_AARCH64_PREL32`: `S + A - P`, stored as two's complement when
+/// the symbol is below the place.
+///
+/// Returns `None` when the displacement is outside the range the AArch64 ELF ABI allows,
+/// -2^31 <= X < 2^32: it has to fit an `int32_t`, or a `uint32_t` for forward references.
+fn prel32(s: u64, a: i64, p: u64) -> Option<u32> {
+    let x = (s as i64).checked_add(a)?.checked_sub(p as i64)?;
+    if !(-(1 << 31)..(1 << 32)).contains(&x) {
+        return None;
+    }
+    Some(x as u32)
+}
+
+/// The immlo (bits [30:29]) and immhi (bits [23:5]) fields of an ADR
+const ADR_IMM_MASK: u32 = (0b11 << 29) | (0x7_FFFF << 5);
+
+/// The immediate of an ADR patched by `R_AARCH64_ADR_PREL_LO21`: `S + A - P`, split into
+/// its low 2 bits (immlo) and the 19 bits above (immhi).
+///
+/// Returns `None` when the displacement doesn't fit the ±1MB range of the signed 21-bit
+/// immediate.
+fn adr_imm(s: u64, a: i64, p: u64) -> Option<u32> {
+    let x = (s as i64).checked_add(a)?.checked_sub(p as i64)?;
+    if !(-(1 << 20)..(1 << 20)).contains(&x) {
+        return None;
+    }
+    let x = x as u32;
+    Some(((x & 0b11) << 29) | (((x >> 2) & 0x7_FFFF) << 5))
+}
+
+/// Placement priority of an output section: notes, .text.hot, .text, .text.unlikely,
+/// .eh_frame, .rodata, the RELRO sections, .tdata, .tbss, .data, .bss, then the rest.
+/// Sections sharing the code segment must come first to keep it contiguous, and RELRO
+/// leads the data segment so it starts on the segment's first page.
+///
+/// Orphans, sections not named here, are placed like GNU ld does: right after the last
+/// named section of the same kind, going by their type and flags. Only non-allocatable
+/// orphans go at the end.
+fn section_rank(section: &OutputSection) -> u32 {
+    // Unmerged sections are named `<name>(<file>:<index>)`
+    let name = section
+        .name
+        .split_once('(')
+        .map_or(section.name.as_str(), |(base, _)| base);
+    // Hot and cold code split out by the compiler, possibly per function
+    let is_text_group = |group: &str| {
+        name.strip_prefix(group)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('.'))
+    };
+    let named = match name {
+        INTERP_SECTION | BUILD_ID_SECTION | DYNSYM_SECTION | DYNSTR_SECTION | HASH_SECTION
+        | DYNAMIC_SECTION => Some(0),
+        _ if is_text_group(".text.hot") => Some(1),
+        ".text" => Some(2),
+        _ if is_text_group(".text.unlikely") => Some(3),
+        ".eh_frame" => Some(4),
+        ".rodata" => Some(5),
+        ".preinit_array" | ".init_array" | ".fini_array" | ".data.rel.ro" | ".got" => Some(6),
+        ".tdata" => Some(7),
+        ".tbss" => Some(8),
+        ".data" => Some(9),
+        ".bss" => Some(10),
+        _ => None,
+    };
+    if let Some(rank) = named {
+        return 2 * rank;
+    }
+    let flags = section.header.flags;
+    let nobits = section.header.sh_type == SHT_NOBITS;
+    let after = if flags & SHF_ALLOC == 0 {
+        11 // Everything else after
+    } else if section.header.sh_type == SHT_NOTE {
+        0
+    } else if flags & SHF_EXECINSTR != 0 {
+        3
+    } else if flags & SHF_TLS != 0 {
+        if nobits { 8 } else { 7 }
+    } else if flags & SHF_WRITE == 0 {
+        5
+    } else if nobits {
+        10
+    } else {
+        9
+    };
+    2 * after + 1
+}
+
+/// Whether a section is loaded in the R+X code segment. Notes, unwind tables, the
+/// interpreter path and the dynamic symbol tables are read-only, so they are placed
+/// alongside the code.
+fn in_code_segment(section: &OutputSection) -> bool {
+    let name = section
+        .name
+        .split_once('(')
+        .map_or(section.name.as_str(), |(base, _)| base);
+    section.header.flags & SHF_EXECINSTR != 0
+        || section.header.sh_type == SHT_NOTE
+        || name == ".eh_frame"
+        || matches!(
+            name,
+            INTERP_SECTION | DYNSYM_SECTION | DYNSTR_SECTION | HASH_SECTION | DYNAMIC_SECTION
+        )
+}
+
+/// Whether a section is the zero-initialized part of the TLS template.
+fn is_tbss(section: &OutputSection) -> bool {
+    section.header.flags & SHF_TLS != 0 && section.header.sh_type == SHT_NOBITS
+}
+
+/// Whether a section belongs in `PT_GNU_RELRO`: data only written by startup code and
+/// read-only afterwards.
+fn is_relro(section: &OutputSection) -> bool {
+    let name = section
+        .name
+        .split_once('(')
+        .map_or(section.name.as_str(), |(base, _)| base);
+    section.header.flags & SHF_ALLOC != 0
+        && (matches!(
+            name,
+            ".preinit_array" | ".init_array" | ".fini_array" | ".got"
+        ) || name == ".data.rel.ro"
+            || name.starts_with(".data.rel.ro."))
+}
+
+/// Whether input sections of this type are placed in the output: program data, and the
+/// constructor and destructor arrays, which are data with a type of their own.
+fn is_output_section_type(sh_type: u32) -> bool {
+    matches!(
+        sh_type,
+        SHT_PROGBITS | SHT_NOBITS | SHT_INIT_ARRAY | SHT_FINI_ARRAY | SHT_PREINIT_ARRAY
+    )
+}
+
+/// Whether `symbol`, named `name`, is a definition `resolve_symbols` resolves references
+/// to: a named `STB_GLOBAL` symbol that isn't undefined. Weak definitions are ignored.
+fn is_global_definition(symbol: &Symbol, name: &str) -> bool {
+    symbol.get_bind() == STB_GLOBAL && !name.is_empty() && symbol.shndx != SHN_UNDEF
+}
+
+/// Whether the program header table `[e_phoff, e_phoff + e_phnum * e_phentsize)` lies
+/// within the file image of some `PT_LOAD` segment.
+fn phdrs_are_loaded(header: &ElfHeader, program_headers: &[ProgramHeader]) -> bool {
+    let start = header.e_phoff;
+    let end = start + header.e_phnum as u64 * header.e_phentsize as u64;
+    program_headers
+        .iter()
+        .any(|ph| ph.p_type == PT_LOAD && start >= ph.offset && end <= ph.offset + ph.filesz)
+}
+
+/// Matches `name` against `pattern`, where `*` matches any (possibly empty) run of characters.
+fn glob_match(pattern: &str, name: &str) -> bool {
+    match pattern.split_once('*') {
+        None => pattern == name,
+        Some((prefix, rest)) => {
+            let Some(name) = name.strip_prefix(prefix) else {
+                return false;
+            };
+            (0..=name.len())
+                .filter(|&i| name.is_char_boundary(i))
+                .any(|i| glob_match(rest, &name[i..]))
+        }
+    }
+}
+
+/// Fills `gap`, which starts at address `addr`, with `instruction`, so that every 4-byte
+/// aligned word in it is a complete instruction.
+fn fill_with_instruction(gap: &mut [u8], addr: u64, instruction: u32) {
+    let bytes = instruction.to_le_bytes();
+    for (i, byte) in gap.iter_mut().enumerate() {
+        *byte = bytes[((addr + i as u64) % 4) as usize];
+    }
+}
+
+/// Copies `data` into `image` at `offset`, where the layout has made room for it.
+fn write_at(image: &mut [u8], offset: u64, data: &[u8]) -> Result<(), LinkerError> {
+    let end = offset
+        .checked_add(data.len() as u64)
+        .filter(|&end| end <= image.len() as u64)
+        .ok_or_else(|| LinkerError::InvalidOutput {
+            reason: format!(
+                "{:#x} bytes at offset {offset:#x} don't fit in the {:#x}-byte image",
+                data.len(),
+                image.len()
+            ),
+        })?;
+    image[offset as usize..end as usize].copy_from_slice(data);
+    Ok(())
+}
+
+fn too_high_for_hex(start: u64, image: &[u8]) -> LinkerError {
+    LinkerError::TooHighForHex {
+        end: start.saturating_add(image.len() as u64),
+    }
+}
+
+/// Writes `contents` to a new file at `path`, replacing any file there.
+fn write_file(path: &str, contents: impl AsRef<[u8]>) -> Result<(), LinkerError> {
+    fs::write(path, contents).map_err(|source| LinkerError::Io {
+        path: path.to_string(),
+        source,
+    })
+}
+
+/// Rounds `addr` up to a multiple of `align`. Returns `None` if `align` isn't a power of
+/// two or the result doesn't fit in 64 bits.
+fn align_up(addr: u64, align: u64) -> Option<u64> {
+    if !align.is_power_of_two() {
+        return None;
+    }
+    Some(addr.checked_add(align - 1)? & !(align - 1))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io;
+
+    use crate::{
+        dynamic::DT_NULL,
+        elf::{
+            header::{EI_CLASS_32, EI_DATA_2MSB, ET_DYN},
+            relocation::R_AARCH64_ADR_PREL_LO21,
+            symbol::{STB_WEAK, STT_FUNC, STT_NOTYPE},
+        },
+        test_utils::{
+            ObjectBuilder, decode_ihex, link, output_symbols, program_headers, section_headers,
+            start_object,
+        },
+    };
+
+    #[test]
+    fn test_reject_32bit_object() {
+        let mut obj = start_object();
+        obj[4] = EI_CLASS_32;
+        let mut linker = LinkerContext::default();
+        let err = linker.add_file("start32.o".to_string(), &obj).unwrap_err();
+        assert!(matches!(
+            err,
+            LinkerError::UnsupportedClass {
+                class: EI_CLASS_32,
+                ..
+            }
+        ));
+        let message = err.to_string();
+        assert!(message.contains("start32.o"));
+        assert!(message.contains("unsupported ELF class"));
+    }
+
+    #[test]
+    fn test_reject_big_endian_object() {
+        let mut obj = start_object();
+        obj[5] = EI_DATA_2MSB;
+        let mut linker = LinkerContext::default();
+        let err = linker.add_file("start_be.o".to_string(), &obj).unwrap_err();
+        assert!(matches!(
+            err,
+            LinkerError::UnsupportedEndianness {
+                data: EI_DATA_2MSB,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_reject_linked_input() {
+        // The linker's own output can't be fed back in
+        let objects = [start_object()];
+        let mut linker = LinkerContext::default();
+        link(&mut linker, &objects);
+        let image = linker.link_to_bytes().unwrap();
+        let err = LinkerContext::default()
+            .add_file("a.out".to_string(), &image)
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            LinkerError::NotRelocatable {
+                e_type: ET_EXEC,
+                ..
+            }
+        ));
+        assert_eq!(
+            err.to_string(),
+            "a.out: is an executable (e_type 2), only relocatable objects can be linked"
+        );
+
+        let mut obj = ObjectBuilder::new();
+        obj.e_type = ET_DYN;
+        let obj = obj.build();
+        let err = LinkerContext::default()
+            .add_file("libfoo.so".to_string(), &obj)
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            LinkerError::NotRelocatable { e_type: ET_DYN, .. }
+        ));
+    }
+
+    #[test]
+    fn test_accept_elf64_little_endian_object() {
+        let obj = start_object();
+        let mut linker = LinkerContext::default();
+        linker.add_file("start.o".to_string(), &obj).unwrap();
+    }
+
+    /// Returns the `desc` of the build-id note found through the `PT_NOTE` header.
+    fn build_id_of(image: &[u8]) -> Vec<u8> {
+        let note = program_headers(image)
+            .into_iter()
+            .find(|ph| ph.p_type == PT_NOTE)
+            .expect("no PT_NOTE header");
+        let note = &image[note.offset as usize..(note.offset + note.filesz) as usize];
+        let namesz = u32::from_le_bytes(note[0..4].try_into().unwrap());
+        let descsz = u32::from_le_bytes(note[4..8].try_into().unwrap()) as usize;
+        let n_type = u32::from_le_bytes(note[8..12].try_into().unwrap());
+        assert_eq!(namesz, 4);
+        assert_eq!(n_type, crate::build_id::NT_GNU_BUILD_ID);
+        assert_eq!(&note[12..16], b"GNU\0");
+        note[16..16 + descsz].to_vec()
+    }
+
+    fn link_with_build_id(kind: BuildIdKind) -> Vec<u8> {
+        let objects = vec![start_object()];
+        let mut linker = LinkerContext::default();
+        linker.build_id(kind);
+        link(&mut linker, &objects);
+        linker.link_to_bytes().unwrap()
+    }
+
+    #[test]
+    fn test_build_id_sha1_is_stable() {
+        let first = build_id_of(&link_with_build_id(BuildIdKind::Sha1));
+        let second = build_id_of(&link_with_build_id(BuildIdKind::Sha1));
+        assert_eq!(first.len(), 20);
+        assert_eq!(first, second);
+        assert_ne!(first, vec![0; 20]);
+    }
+
+    #[test]
+    fn test_build_id_lengths() {
+        assert_eq!(build_id_of(&link_with_build_id(BuildIdKind::Fast)).len(), 8);
+        assert_eq!(
+            build_id_of(&link_with_build_id(BuildIdKind::Uuid)).len(),
+            16
+        );
+    }
+
+    #[test]
+    fn test_no_build_id_by_default() {
+        let image = link_with_build_id(BuildIdKind::None);
+        let headers = program_headers(&image);
+        assert_eq!(headers.len(), 3);
+        assert!(headers.iter().all(|ph| ph.p_type != PT_NOTE));
+    }
+
+    #[test]
+    fn test_fill_byte_pads_segment_gap() {
+        let mut obj = ObjectBuilder::new();
+        let text = obj.text(&[0x14000000]);
+        obj.global_func("_start", text, 0, 4);
+        obj.data(".data", &[1, 2, 3, 4]);
+        let objects = vec![obj.build()];
+
+        let mut linker = LinkerContext::default();
+        linker.fill_byte(0xff);
+        link(&mut linker, &objects);
+        let image = linker.link_to_bytes().unwrap();
+
+        let headers = program_headers(&image);
+        let code_end = (headers[0].offset + headers[0].filesz) as usize;
+        let data_start = headers[1].offset as usize;
+        assert!(data_start > code_end);
+        assert!(image[code_end..data_start].iter().all(|&b| b == 0xff));
+        assert_eq!(&image[data_start..data_start + 4], &[1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn test_fill_byte_pads_between_merged_inputs() {
+        let mut obj = ObjectBuilder::new();
+        let text = obj.text(&[0x14000000]);
+        obj.global_func("_start", text, 0, 4);
+        obj.section(".rodata", SHT_PROGBITS, SHF_ALLOC, 1, &[1, 2, 3]);
+        obj.nobits(".bss", 1, 3);
+        let mut second = ObjectBuilder::new();
+        second.section(".rodata", SHT_PROGBITS, SHF_ALLOC, 8, &[4; 8]);
+        second.nobits(".bss", 8, 8);
+        let objects = [obj.build(), second.build()];
+
+        let mut linker = LinkerContext::default();
+        linker.fill_byte(0xff);
+        link(&mut linker, &objects);
+        let rodata = &linker.output_sections[".rodata"];
+        assert_eq!(
+            rodata.data,
+            [
+                1, 2, 3, 0xff, 0xff, 0xff, 0xff, 0xff, 4, 4, 4, 4, 4, 4, 4, 4
+            ]
+        );
+        // .bss stays zeros, whatever the fill byte
+        let bss = &linker.output_sections[".bss"];
+        assert_eq!(bss.header.size, 16);
+        assert!(bss.data.iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn test_bss_from_multiple_files_does_not_overlap() {
+        let mut first = ObjectBuilder::new();
+        let text = first.text(&[0x14000000]);
+        first.global_func("_start", text, 0, 4);
+        let bss = first.nobits(".bss", 4, 4);
+        first.global_object("counter", bss, 0, 4);
+
+        let mut second = ObjectBuilder::new();
+        let bss = second.nobits(".bss", 4, 8);
+        second.global_object("buffer", bss, 0, 8);
+
+        let objects = vec![first.build(), second.build()];
+        let mut linker = LinkerContext::default();
+        link(&mut linker, &objects);
+
+        let bss = &linker.output_sections[".bss"];
+        assert_eq!(bss.header.size, 12);
+        let counter = linker.global_symbols["counter"].final_addr;
+        let buffer = linker.global_symbols["buffer"].final_addr;
+        assert_eq!(counter, bss.header.addr);
+        assert_eq!(buffer, bss.header.addr + 4);
+    }
+
+    #[test]
+    fn test_no_merge_keeps_input_sections_separate() {
+        let mut first = ObjectBuilder::new();
+        let text = first.text(&[0x94000000, 0x14000000]); // bl helper; b .
+        first.global_func("_start", text, 0, 8);
+        let helper = first.undefined("helper");
+        first.rela(text, 0, helper, R_AARCH64_CALL26, 0);
+
+        let mut second = ObjectBuilder::new();
+        let text = second.text(&[0xd65f03c0]); // ret
+        second.global_func("helper", text, 0, 4);
+
+        let objects = vec![first.build(), second.build()];
+        let mut linker = LinkerContext::default();
+        linker.no_merge(true);
+        link(&mut linker, &objects);
+
+        let first_text = &linker.output_sections[".text(0.o:1)"];
+        let second_text = &linker.output_sections[".text(1.o:1)"];
+        assert!(!linker.output_sections.contains_key(".text"));
+        assert_ne!(first_text.header.addr, second_text.header.addr);
+        assert_eq!(first_text.header.size, 8);
+
+        let start = linker.global_symbols["_start"].final_addr;
+        let helper = linker.global_symbols["helper"].final_addr;
+        assert_eq!(start, first_text.header.addr);
+        assert_eq!(helper, second_text.header.addr);
+
+        // The call in the first file is patched to reach the second file's section
+        let bl = u32::from_le_bytes(first_text.data[0..4].try_into().unwrap());
+        assert_eq!(bl, 0x94000000 | ((helper - start) >> 2) as u32);
+    }
+
+    #[test]
+    fn test_branch26_imm_encoding() {
+        // Forward and backward branches
+        assert_eq!(branch26_imm(0x400020, 0, 0x400000), Some(0x8));
+        assert_eq!(branch26_imm(0x400000, 0, 0x400010), Some(0x03FF_FFFC));
+        // A negative addend moves the target backwards too
+        assert_eq!(branch26_imm(0x400010, -0x10, 0x400000), Some(0));
+        // Only bits [27:2] of the displacement are encoded, so a displacement of -6
+        // rounds towards negative infinity to -2 words
+        assert_eq!(branch26_imm(0x400000, -6, 0x400000), Some(0x03FF_FFFE));
+        // ±128MB range limits
+        assert_eq!(branch26_imm(0, -(1 << 27), 0), Some(0x0200_0000));
+        assert_eq!(branch26_imm(0, (1 << 27) - 4, 0), Some(0x01FF_FFFF));
+        assert_eq!(branch26_imm(0, 1 << 27, 0), None);
+        assert_eq!(branch26_imm(0, -(1 << 27) - 4, 0), None);
+    }
+
+    #[test]
+    fn test_backward_call_across_files() {
+        // The callee's file comes first, so the call branches backwards
+        let mut callee = ObjectBuilder::new();
+        let text = callee.text(&[0xd65f03c0, 0xd65f03c0]); // ret; ret
+        callee.global_func("helper", text, 4, 4);
+
+        let mut caller = ObjectBuilder::new();
+        let text = caller.text(&[0xd503201f, 0x94000000]); // nop; bl helper
+        caller.global_func("_start", text, 0, 8);
+        let helper = caller.undefined("helper");
+        caller.rela(text, 4, helper, R_AARCH64_CALL26, 0);
+
+        let objects = vec![callee.build(), caller.build()];
+        let mut linker = LinkerContext::default();
+        link(&mut linker, &objects);
+
+        // helper is at .text + 4, the bl at .text + 8 + 4: a displacement of -8 bytes
+        let text = &linker.output_sections[".text"];
+        let bl = u32::from_le_bytes(text.data[12..16].try_into().unwrap());
+        assert_eq!(bl, 0x94000000 | 0x03FF_FFFE);
+        assert_eq!(
+            format_instruction(bl, text.header.addr + 12, |_| None),
+            format!("bl {:#x}", text.header.addr + 4)
+        );
+    }
+
+    #[test]
+    fn test_unaligned_instruction_relocation_is_rejected() {
+        let mut obj = ObjectBuilder::new();
+        let text = obj.text(&[0xd503201f, 0x94000000]);
+        let start = obj.global_func("_start", text, 0, 8);
+        obj.rela(text, 2, start, R_AARCH64_CALL26, 0);
+        let obj = obj.build();
+
+        let mut linker = LinkerContext::default();
+        linker.add_file("bad.o".to_string(), &obj).unwrap();
+        linker.layout_and_merge_sections().unwrap();
+        linker.resolve_symbols().unwrap();
+        let err = linker.apply_relocations().unwrap_err();
+        assert!(matches!(err, LinkerError::BadRelocation { offset: 2, .. }));
+
+        // The instructions are left untouched
+        let text = &linker.output_sections[".text"];
+        assert_eq!(&text.data[4..8], &0x94000000u32.to_le_bytes());
+    }
+
+    #[test]
+    fn test_eh_frame_is_relocated_and_covered_by_gnu_eh_frame() {
+        let mut obj = ObjectBuilder::new();
+        let text = obj.text(&[0xd503201f, 0x14000000]);
+        obj.global_func("_start", text, 4, 4);
+        let text_sym = obj.section_symbol(text);
+        let eh_frame = obj.section(".eh_frame", SHT_PROGBITS, SHF_ALLOC, 8, &[0; 0x30]);
+        // FDE pc_begin: PREL32 against the .text section symbol
+        obj.rela(eh_frame, 0x1c, text_sym, R_AARCH64_PREL32, 0);
+        let objects = vec![obj.build()];
+
+        let mut linker = LinkerContext::default();
+        link(&mut linker, &objects);
+        let text_addr = linker.output_sections[".text"].header.addr;
+        let eh_frame = &linker.output_sections[".eh_frame"];
+        let pc_begin = i32::from_le_bytes(eh_frame.data[0x1c..0x20].try_into().unwrap());
+        assert_eq!(
+            pc_begin as i64,
+            text_addr as i64 - (eh_frame.header.addr + 0x1c) as i64
+        );
+
+        let image = linker.link_to_bytes().unwrap();
+        let headers = program_headers(&image);
+        let eh_frame_header = headers
+            .iter()
+            .find(|ph| ph.p_type == PT_GNU_EH_FRAME)
+            .expect("no PT_GNU_EH_FRAME header");
+        assert_eq!(eh_frame_header.vaddr, eh_frame.header.addr);
+        assert_eq!(eh_frame_header.memsz, 0x30);
+        let offset = eh_frame_header.offset as usize;
+        assert_eq!(&image[offset..offset + 0x30], &eh_frame.data[..]);
+        // It lives in the read-only code segment
+        let code = &headers[0];
+        assert!(eh_frame_header.vaddr + eh_frame_header.memsz <= code.vaddr + code.memsz);
+    }
+
+    #[test]
+    fn test_symbol_location() {
+        let mut obj = ObjectBuilder::new();
+        let text = obj.text(&[0xd503201f, 0x14000000]);
+        obj.global_func("_start", text, 4, 4);
+        let data = obj.data(".data", &[0; 16]);
+        obj.global_object("table", data, 8, 8);
+        let objects = vec![obj.build()];
+
+        let mut linker = LinkerContext::default();
+        link(&mut linker, &objects);
+
+        assert_eq!(
+            linker.symbol_location("_start"),
+            Some(SymbolLocation {
+                section: ".text".to_string(),
+                segment_flags: PF_R | PF_X,
+                offset_in_section: 4,
+            })
+        );
+        let table = linker.symbol_location("table").unwrap();
+        assert_eq!(table.section, ".data");
+        assert_eq!(table.segment_flags, PF_R | PF_W);
+        assert_eq!(table.offset_in_section, 8);
+        assert_eq!(linker.symbol_location("missing"), None);
+    }
+
+    #[test]
+    fn test_tbss_extends_tls_template_memsz_only() {
+        let mut object = ObjectBuilder::new();
+        let text = object.text(&[0x14000000]);
+        object.global_func("_start", text, 0, 4);
+        // __thread int counter = 42;
+        let tdata = object.section(
+            ".tdata",
+            SHT_PROGBITS,
+            SHF_ALLOC | SHF_WRITE | SHF_TLS,
+            4,
+            &42u32.to_le_bytes(),
+        );
+        object.symbol("counter", STB_GLOBAL, STT_TLS, tdata, 0, 4);
+        // __thread long buffer[8];
+        let tbss = object.nobits(".tbss", 8, 64);
+        object.section_mut(tbss).flags |= SHF_TLS;
+        object.symbol("buffer", STB_GLOBAL, STT_TLS, tbss, 0, 64);
+        let data = object.data(".data", &7u64.to_le_bytes());
+        object.global_object("plain", data, 0, 8);
+
+        let objects = vec![object.build()];
+        let mut linker = LinkerContext::default();
+        link(&mut linker, &objects);
+        let image = linker.link_to_bytes().unwrap();
+        let headers = program_headers(&image);
+
+        let tls = headers.iter().find(|h| h.p_type == PT_TLS).unwrap();
+        assert_eq!(tls.filesz, 4);
+        // .tbss starts at the next 8-byte boundary after .tdata
+        assert_eq!(tls.memsz, 8 + 64);
+        assert_eq!(tls.align, 8);
+        assert_eq!(&image[tls.offset as usize..][..4], &42u32.to_le_bytes());
+
+        assert_eq!(linker.tls_offset("counter"), Some(TCB_SIZE));
+        assert_eq!(linker.tls_offset("buffer"), Some(TCB_SIZE + 8));
+        assert_eq!(linker.tls_offset("plain"), None);
+
+        // .tbss takes no room in the image: .data follows .tdata directly and is
+        // loaded at the address its symbols were given
+        let data_segment = headers
+            .iter()
+            .find(|h| h.p_type == PT_LOAD && h.flags == DATA_SEGMENT_FLAGS)
+            .unwrap();
+        let plain = linker.global_symbols["plain"].final_addr;
+        let offset = data_segment.offset + (plain - data_segment.vaddr);
+        assert_eq!(&image[offset as usize..][..8], &7u64.to_le_bytes());
+        assert_eq!(linker.symbol_location("buffer").unwrap().section, ".tbss");
+        assert_eq!(linker.symbol_location("plain").unwrap().section, ".data");
+    }
+
+    #[test]
+    fn test_trace_symbol_records_definition_and_reference() {
+        let mut first = ObjectBuilder::new();
+        let text = first.text(&[0x94000000, 0x14000000]); // bl helper; b .
+        first.global_func("_start", text, 0, 8);
+        let helper = first.undefined("helper");
+        first.rela(text, 0, helper, R_AARCH64_CALL26, 0);
+
+        let mut second = ObjectBuilder::new();
+        let text = second.text(&[0xd65f03c0]); // ret
+        second.global_func("helper", text, 0, 4);
+
+        let objects = vec![first.build(), second.build()];
+        let mut linker = LinkerContext::default();
+        linker.trace_symbol("helper");
+        link(&mut linker, &objects);
+
+        assert_eq!(
+            linker.trace_events(),
+            [
+                TraceEvent::Definition {
+                    symbol: "helper".to_string(),
+                    file: "1.o".to_string(),
+                    bind: STB_GLOBAL,
+                    sym_type: STT_FUNC,
+                },
+                TraceEvent::Reference {
+                    symbol: "helper".to_string(),
+                    file: "0.o".to_string(),
+                    section: ".text".to_string(),
+                    offset: 0,
+                    r_type: R_AARCH64_CALL26,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_gc_sections_keeps_referenced_sections() {
+        let mut object = ObjectBuilder::new();
+        let exec = SHF_ALLOC | SHF_EXECINSTR;
+        let start = object.section(".text._start", SHT_PROGBITS, exec, 4, &[0, 0, 0, 0x94]); // bl used
+        object.global_func("_start", start, 0, 4);
+        let used = object.section(
+            ".text.used",
+            SHT_PROGBITS,
+            exec,
+            4,
+            &[0xc0, 0x03, 0x5f, 0xd6],
+        );
+        let used_sym = object.section_symbol(used);
+        object.rela(start, 0, used_sym, R_AARCH64_CALL26, 0);
+        let unused = object.section(
+            ".text.unused",
+            SHT_PROGBITS,
+            exec,
+            4,
+            &[0xc0, 0x03, 0x5f, 0xd6],
+        );
+        object.global_func("unused", unused, 0, 4);
+
+        let objects = vec![object.build()];
+        let mut linker = LinkerContext::default();
+        linker.gc_sections(true);
+        link(&mut linker, &objects);
+
+        assert!(linker.output_sections.contains_key(".text._start"));
+        assert!(linker.output_sections.contains_key(".text.used"));
+        assert!(!linker.output_sections.contains_key(".text.unused"));
+        assert!(!linker.global_symbols.contains_key("unused"));
+    }
+
+    #[test]
+    fn test_gc_sections_follows_strong_definition() {
+        let exec = SHF_ALLOC | SHF_EXECINSTR;
+        let mut weak = ObjectBuilder::new();
+        let start = weak.section(".text._start", SHT_PROGBITS, exec, 4, &[0, 0, 0, 0x94]); // bl foo
+        weak.global_func("_start", start, 0, 4);
+        let foo = weak.undefined("foo");
+        weak.rela(start, 0, foo, R_AARCH64_CALL26, 0);
+        let weak_foo = weak.section(".text.weak", SHT_PROGBITS, exec, 4, &[0; 4]);
+        weak.symbol("foo", STB_WEAK, STT_FUNC, weak_foo, 0, 4);
+
+        let mut strong = ObjectBuilder::new();
+        let strong_foo = strong.section(".text.strong", SHT_PROGBITS, exec, 4, &[0; 4]);
+        strong.global_func("foo", strong_foo, 0, 4);
+
+        let objects = [weak.build(), strong.build()];
+        let mut linker = LinkerContext::default();
+        linker.gc_sections(true);
+        link(&mut linker, &objects);
+        // The weak definition is ignored by resolution, so GC follows the strong one
+        assert!(linker.output_sections.contains_key(".text.strong"));
+        assert!(!linker.output_sections.contains_key(".text.weak"));
+        assert_eq!(
+            linker.global_symbols["foo"].final_addr,
+            linker.output_sections[".text.strong"].header.addr
+        );
+    }
+
+    #[test]
+    fn test_gc_sections_roots_custom_entry() {
+        let mut object = ObjectBuilder::new();
+        let exec = SHF_ALLOC | SHF_EXECINSTR;
+        let reset = object.section(
+            ".text.reset",
+            SHT_PROGBITS,
+            exec,
+            4,
+            &[0xc0, 0x03, 0x5f, 0xd6],
+        );
+        object.global_func("reset_handler", reset, 0, 4);
+        let start = object.section(
+            ".text._start",
+            SHT_PROGBITS,
+            exec,
+            4,
+            &[0xc0, 0x03, 0x5f, 0xd6],
+        );
+        object.global_func("_start", start, 0, 4);
+        let objects = [object.build()];
+
+        let mut linker = LinkerContext::default();
+        linker
+            .gc_sections(true)
+            .entry(EntryPoint::Symbol("reset_handler".to_string()));
+        link(&mut linker, &objects);
+        assert!(linker.output_sections.contains_key(".text.reset"));
+        assert!(!linker.output_sections.contains_key(".text._start"));
+        let image = linker.link_to_bytes().unwrap();
+        let (_, header) = parse_elf_header(&image).unwrap();
+        assert_eq!(
+            header.e_entry,
+            linker.global_symbols["reset_handler"].final_addr
+        );
+
+        // A missing entry symbol is reported by its name
+        let mut linker = LinkerContext::default();
+        linker
+            .gc_sections(true)
+            .entry(EntryPoint::Symbol("missing".to_string()));
+        link(&mut linker, &objects);
+        let err = linker.link_to_bytes().unwrap_err();
+        assert!(matches!(err, LinkerError::UndefinedEntry { ref symbol } if symbol == "missing"));
+        assert_eq!(err.to_string(), "no entry point: `missing` is not defined");
+
+        // An entry address roots nothing, which is warned about
+        let mut linker = LinkerContext::default();
+        linker.gc_sections(true).entry(EntryPoint::Address(0x1000));
+        link(&mut linker, &objects);
+        assert!(!linker.output_sections.contains_key(".text.reset"));
+        assert_eq!(
+            linker.warnings(),
+            ["the entry point is an address, so gc_sections keeps no code for it"]
+        );
+
+        // unless the code there is kept by name
+        let mut linker = LinkerContext::default();
+        linker
+            .gc_sections(true)
+            .entry(EntryPoint::OffsetFromBase(0))
+            .keep_undefined("reset_handler");
+        link(&mut linker, &objects);
+        assert!(linker.output_sections.contains_key(".text.reset"));
+        assert!(linker.warnings().is_empty());
+    }
+
+    #[test]
+    fn test_reference_into_discarded_section_is_an_error() {
+        let mut object = ObjectBuilder::new();
+        let text = object.text(&[0x94000000]); // bl helper
+        object.global_func("_start", text, 0, 4);
+        let exec = SHF_ALLOC | SHF_EXECINSTR;
+        let helper = object.section(
+            ".text.helper",
+            SHT_PROGBITS,
+            exec,
+            4,
+            &[0xc0, 0x03, 0x5f, 0xd6],
+        );
+        let helper_sym = object.global_func("helper", helper, 0, 4);
+        object.rela(text, 0, helper_sym, R_AARCH64_CALL26, 0);
+
+        let objects = [object.build()];
+        let mut linker = LinkerContext::default();
+        linker.gc_sections(true);
+        linker.add_file("0.o".to_string(), &objects[0]).unwrap();
+        linker.layout_and_merge_sections().unwrap();
+        assert!(linker.output_sections.contains_key(".text.helper"));
+
+        // Simulate reachability missing the edge
+        linker.input_section_outputs.remove(&(0, helper as usize));
+        linker
+            .discarded_sections
+            .insert((0, helper as usize), ".text.helper".to_string());
+        linker.resolve_symbols().unwrap();
+        let err = linker.apply_relocations().unwrap_err();
+        assert!(matches!(
+            err,
+            LinkerError::DiscardedSectionReference { ref symbol, ref discarded_section, .. }
+                if symbol == "helper" && discarded_section == ".text.helper"
+        ));
+    }
+
+    #[test]
+    fn test_segment_map_places_rodata_in_code_segment() {
+        let mut object = ObjectBuilder::new();
+        let text = object.text(&[0x14000000]);
+        object.global_func("_start", text, 0, 4);
+        let rodata = object.section(".rodata", SHT_PROGBITS, SHF_ALLOC, 8, b"hello\0");
+        object.global_object("greeting", rodata, 0, 6);
+        object.data(".data", &[1, 2, 3, 4]);
+
+        let objects = [object.build()];
+        let mut linker = LinkerContext::default();
+        linker.segment_map(vec![SegmentSpec::new(&[".text*", ".rodata*"], PF_R | PF_X)]);
+        link(&mut linker, &objects);
+        let image = linker.link_to_bytes().unwrap();
+
+        let loads: Vec<_> = program_headers(&image)
+            .into_iter()
+            .filter(|h| h.p_type == PT_LOAD)
+            .collect();
+        assert_eq!(loads.len(), 2);
+        let rodata = &linker.output_sections[".rodata"];
+        let code = loads
+            .iter()
+            .find(|h| rodata.header.addr >= h.vaddr && rodata.header.addr < h.vaddr + h.memsz)
+            .unwrap();
+        assert_eq!(code.flags, PF_R | PF_X);
+        assert_eq!(
+            linker.symbol_location("greeting").unwrap().segment_flags,
+            PF_R | PF_X
+        );
+        let offset = (code.offset + rodata.header.addr - code.vaddr) as usize;
+        assert_eq!(&image[offset..offset + 6], b"hello\0");
+        // Unmatched .data still lands in the default data segment
+        assert_eq!(
+            linker.segment_flags(linker.segment_index(&linker.output_sections[".data"])),
+            DATA_SEGMENT_FLAGS
+        );
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match(".text*", ".text"));
+        assert!(glob_match(".text*", ".text.hot"));
+        assert!(glob_match("*data*", ".rodata.str1.1"));
+        assert!(!glob_match(".text*", ".rodata"));
+        assert!(!glob_match(".data", ".data.rel"));
+    }
+
+    #[test]
+    fn test_program_headers_are_loaded() {
+        let objects = [start_object()];
+        let mut linker = LinkerContext::default();
+        linker.build_id(BuildIdKind::Sha1);
+        link(&mut linker, &objects);
+        let image = linker.link_to_bytes().unwrap();
+
+        let (_, header) = parse_elf_header(&image).unwrap();
+        let headers = program_headers(&image);
+        assert_eq!(header.e_phnum as usize, headers.len());
+        assert!(phdrs_are_loaded(&header, &headers));
+
+        // Moving the first segment past the headers breaks the invariant
+        let mut moved = headers.clone();
+        moved[0].offset = PAGE_SIZE;
+        assert!(!phdrs_are_loaded(&header, &moved));
+
+        // Asking for a build ID after layout leaves no room for its PT_NOTE
+        let mut linker = LinkerContext::default();
+        link(&mut linker, &objects);
+        linker.build_id(BuildIdKind::Sha1);
+        let err = linker.link_to_bytes().unwrap_err();
+        assert!(err.to_string().contains("slots left by layout"), "{err}");
+    }
+
+    #[test]
+    fn test_link_without_inputs_is_an_error() {
+        let mut linker = LinkerContext::default();
+        linker.layout_and_merge_sections().unwrap();
+        linker.resolve_symbols().unwrap();
+        linker.apply_relocations().unwrap();
+        assert!(matches!(
+            linker.link_to_bytes(),
+            Err(LinkerError::NoInputFiles)
+        ));
+    }
+
+    #[test]
+    fn test_movw_uabs_materializes_48bit_address() {
+        let mut object = ObjectBuilder::new();
+        // movz x0, #:abs_g2:far; movk x0, #:abs_g1_nc:far; movk x0, #:abs_g0_nc:far
+        let text = object.text(&[0xd2c00000, 0xf2a00000, 0xf2800000, 0x14000000]);
+        object.global_func("_start", text, 0, 16);
+        let far = object.symbol("far", STB_GLOBAL, STT_NOTYPE, SHN_ABS, 0x1234_5678_9abc, 0);
+        object.rela(text, 0, far, R_AARCH64_MOVW_UABS_G2, 0);
+        object.rela(text, 4, far, R_AARCH64_MOVW_UABS_G1_NC, 0);
+        object.rela(text, 8, far, R_AARCH64_MOVW_UABS_G0_NC, 0);
+
+        let objects = [object.build()];
+        let mut linker = LinkerContext::default();
+        link(&mut linker, &objects);
+
+        let text = &linker.output_sections[".text"].data;
+        let imm16 = |i: usize| {
+            let insn = u32::from_le_bytes(text[i * 4..i * 4 + 4].try_into().unwrap());
+            (insn >> 5) & 0xFFFF
+        };
+        assert_eq!([imm16(0), imm16(1), imm16(2)], [0x1234, 0x5678, 0x9abc]);
+    }
+
+    #[test]
+    fn test_movw_uabs_overflow_checks() {
+        assert_eq!(movw_uabs_imm(0x1_0000, 0, 0, false), Some(0));
+        assert_eq!(movw_uabs_imm(0x1_0000, 0, 0, true), None);
+        assert_eq!(movw_uabs_imm(0xffff, 1, 1, true), Some(1));
+        assert_eq!(movw_uabs_imm(u64::MAX, 0, 3, false), Some(0xffff));
+    }
+
+    #[test]
+    fn test_cached_names_match_lookups() {
+        let objects = [std::fs::read("materials/hello.o").unwrap()];
+        let mut linker = LinkerContext::default();
+        linker.add_file("hello.o".to_string(), &objects[0]).unwrap();
+
+        let file = &linker.input_files[0];
+        let table = |section: &SectionHeader| {
+            &file.content[section.offset as usize..(section.offset + section.size) as usize]
+        };
+        let shstrtab =
+            table(&file.sections[section_name_table_index(&file.header, &file.sections)]);
+        assert_eq!(file.section_names.len(), file.sections.len());
+        for (section, name) in file.sections.iter().zip(&file.section_names) {
+            assert_eq!(get_section_name(shstrtab, section).unwrap_or(""), *name);
+        }
+        let symtab = file
+            .sections
+            .iter()
+            .find(|s| s.sh_type == SHT_SYMTAB)
+            .unwrap();
+        let strtab = table(&file.sections[symtab.link as usize]);
+        assert_eq!(file.symbol_names.len(), file.symbols.len());
+        for (symbol, name) in file.symbols.iter().zip(&file.symbol_names) {
+            assert_eq!(get_symbol_name(strtab, symbol).unwrap_or(""), *name);
+        }
+        assert!(file.symbol_names.iter().any(|name| name == "main"));
+    }
+
+    /// The first qemu-user binary for AArch64 that can be run, if any.
+    fn qemu_aarch64() -> Option<&'static str> {
+        ["qemu-aarch64", "qemu-aarch64-static"]
+            .into_iter()
+            .find(|qemu| {
+                std::process::Command::new(qemu)
+                    .arg("--version")
+                    .output()
+                    .is_ok_and(|output| output.status.success())
+            })
+    }
+
+    /// Links a program that writes a message and exits with 42, and runs it. It needs
+    /// qemu-user for AArch64, so it only runs with `cargo test -- --ignored`, as CI does.
+    #[test]
+    #[ignore = "needs qemu-aarch64, run with `cargo test -- --ignored`"]
+    fn test_linked_program_runs_under_qemu() {
+        let qemu = qemu_aarch64().expect("qemu-aarch64 is not installed");
+
+        const MESSAGE: &[u8] = b"hello from elkr\n";
+        let mut main = ObjectBuilder::new();
+        let code = [
+            0x10000000 | (6 << 5) | 1,                      // adr x1, message (+24)
+            0xd2800020,                                     // mov x0, #1
+            0xd2800000 | ((MESSAGE.len() as u32) << 5) | 2, // mov x2, #len
+            0xd2800808,                                     // mov x8, #64 (write)
+            0xd4000001,                                     // svc #0
+            0x94000000,                                     // bl exit_42
+        ];
+        let mut text: Vec<u8> = code
+            .iter()
+            .flat_map(|insn: &u32| insn.to_le_bytes())
+            .collect();
+        text.extend_from_slice(MESSAGE);
+        let text = main.section(".text", SHT_PROGBITS, SHF_ALLOC | SHF_EXECINSTR, 4, &text);
+        main.global_func("_start", text, 0, 24);
+        let exit_42 = main.undefined("exit_42");
+        main.rela(text, 20, exit_42, R_AARCH64_CALL26, 0);
+
+        let mut exit = ObjectBuilder::new();
+        // mov x0, #42; mov x8, #93 (exit); svc #0
+        let text = exit.text(&[0xd2800540, 0xd2800ba8, 0xd4000001]);
+        exit.global_func("exit_42", text, 0, 12);
+
+        let objects = vec![main.build(), exit.build()];
+        let mut linker = LinkerContext::default();
+        link(&mut linker, &objects);
+        let path = std::env::temp_dir().join(format!("elkr-e2e-{}", std::process::id()));
+        linker.write_executable(path.to_str().unwrap()).unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        }
+
+        let output = std::process::Command::new(qemu).arg(&path).output();
+        std::fs::remove_file(&path).unwrap();
+        let output = output.unwrap();
+        assert_eq!(output.stdout, MESSAGE);
+        assert_eq!(output.status.code(), Some(42));
+    }
+
+    #[test]
+    fn test_global_symbols_outlive_input_buffers() {
+        let global_symbols = {
+            let objects = [start_object()];
+            let mut linker = LinkerContext::default();
+            link(&mut linker, &objects);
+            std::mem::take(&mut linker.global_symbols)
+            // `objects` is dropped here; the symbol map must not borrow from it
+        };
+        let start = &global_symbols["_start"];
+        assert_eq!(start._name, "_start");
+        assert!(start.final_addr > 0x400_000);
+    }
+
+    #[test]
+    fn test_conflicting_section_flags_are_reported() {
+        let mut first = ObjectBuilder::new();
+        let text = first.text(&[0x14000000]);
+        first.global_func("_start", text, 0, 4);
+        first.data(".data", &[1, 2, 3, 4]);
+
+        let mut second = ObjectBuilder::new();
+        // A read-only ".data"
+        second.section(".data", SHT_PROGBITS, SHF_ALLOC, 8, &[5, 6, 7, 8]);
+
+        let objects = vec![first.build(), second.build()];
+        let mut linker = LinkerContext::default();
+        link(&mut linker, &objects);
+
+        assert_eq!(linker.warnings().len(), 1);
+        assert!(linker.warnings()[0].contains("1.o: section .data"));
+        // The merged section stays writable
+        let data = &linker.output_sections[".data"];
+        assert_ne!(data.header.flags & SHF_WRITE, 0);
+        // The second input keeps its 8-byte alignment
+        assert_eq!(data.data, [1, 2, 3, 4, 0, 0, 0, 0, 5, 6, 7, 8]);
+    }
+
+    #[cfg(feature = "zlib")]
+    #[test]
+    fn test_compressed_section_is_decompressed() {
+        use std::io::Write;
+
+        use crate::elf::compression::ELFCOMPRESS_ZLIB;
+
+        let debug_info: Vec<u8> = (0..200u8).collect();
+        let mut encoder =
+            flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(&debug_info).unwrap();
+        let mut contents = Vec::new();
+        contents.extend_from_slice(&ELFCOMPRESS_ZLIB.to_le_bytes());
+        contents.extend_from_slice(&0u32.to_le_bytes());
+        contents.extend_from_slice(&(debug_info.len() as u64).to_le_bytes());
+        contents.extend_from_slice(&1u64.to_le_bytes());
+        contents.extend_from_slice(&encoder.finish().unwrap());
+
+        let mut object = ObjectBuilder::new();
+        let text = object.text(&[0x14000000]);
+        object.global_func("_start", text, 0, 4);
+        object.section(".debug_info", SHT_PROGBITS, SHF_COMPRESSED, 1, &contents);
+
+        let objects = [object.build()];
+        let mut linker = LinkerContext::default();
+        linker.keep_sections(true);
+        link(&mut linker, &objects);
+
+        let section = &linker.output_sections[".debug_info"];
+        assert_eq!(section.header.size, debug_info.len() as u64);
+        assert_eq!(section.header.flags & SHF_COMPRESSED, 0);
+        assert_eq!(section.data, debug_info);
+        // Not loaded, but still written out
+        assert_eq!(section.header.addr, 0);
+        let image = linker.link_to_bytes().unwrap();
+        assert!(image.ends_with(&debug_info));
+    }
+
+    #[test]
+    fn test_unsupported_compression_is_an_error() {
+        let mut contents = vec![0; 24];
+        contents[0] = 0x7f; // ch_type
+        let mut object = ObjectBuilder::new();
+        object.section(".debug_info", SHT_PROGBITS, SHF_COMPRESSED, 1, &contents);
+
+        let object = object.build();
+        let mut linker = LinkerContext::default();
+        assert!(matches!(
+            linker.add_file("0.o".to_string(), &object),
+            Err(LinkerError::UnsupportedCompression { ch_type: 0x7f, .. })
+        ));
+    }
+
+    #[test]
+    fn test_offset_vaddr_mapping() {
+        let mut object = ObjectBuilder::new();
+        let text = object.text(&[0xd503201f, 0x14000000]); // nop; b .
+        object.global_func("_start", text, 0, 8);
+        object.data(".data", &[1, 2, 3, 4]);
+        let bss = object.nobits(".bss", 8, 16);
+        object.global_object("zeroed", bss, 0, 16);
+
+        let objects = [object.build()];
+        let mut linker = LinkerContext::default();
+        link(&mut linker, &objects);
+        let image = linker.link_to_bytes().unwrap();
+
+        let entry = linker.global_symbols["_start"].final_addr;
+        let offset = linker.vaddr_to_offset(entry).unwrap() as usize;
+        assert_eq!(&image[offset..offset + 4], &0xd503201fu32.to_le_bytes());
+        assert_eq!(linker.offset_to_vaddr(offset as u64), Some(entry));
+
+        let data = linker.output_sections[".data"].header.addr;
+        let offset = linker.vaddr_to_offset(data).unwrap();
+        assert_eq!(&image[offset as usize..][..4], &[1, 2, 3, 4]);
+        assert_eq!(linker.offset_to_vaddr(offset), Some(data));
+
+        // .bss has memory but no file bytes
+        assert_eq!(
+            linker.vaddr_to_offset(linker.global_symbols["zeroed"].final_addr),
+            None
+        );
+        assert_eq!(linker.vaddr_to_offset(0x10), None);
+    }
+
+    #[test]
+    fn test_flat_binary_image() {
+        let mut object = ObjectBuilder::new();
+        let text = object.text(&[0xd503201f, 0x14000000]); // nop; b .
+        object.global_func("_start", text, 4, 4);
+        object.data(".data", &[1, 2, 3, 4]);
+        object.nobits(".bss", 8, 64);
+
+        let objects = [object.build()];
+        let mut linker = LinkerContext::default();
+        linker.fill_byte(0xff);
+        link(&mut linker, &objects);
+        let (start, image) = linker.flat_image();
+
+        let text = &linker.output_sections[".text"];
+        let data = &linker.output_sections[".data"];
+        assert_eq!(start, text.header.addr);
+        // Ends with .data; the trailing .bss has no bytes to write
+        assert_eq!(image.len() as u64, data.header.addr + 4 - start);
+        let entry = (linker.global_symbols["_start"].final_addr - start) as usize;
+        assert_eq!(&image[entry..entry + 4], &0x14000000u32.to_le_bytes());
+        assert_eq!(image[(text.header.addr + 8 - start) as usize], 0xff);
+        assert!(image.ends_with(&[1, 2, 3, 4]));
+    }
+
+    #[test]
+    fn test_ihex_matches_flat_image() {
+        let mut object = ObjectBuilder::new();
+        let text = object.text(&[0xd503201f, 0x14000000]);
+        object.global_func("_start", text, 4, 4);
+        object.data(".data", &[1, 2, 3, 4]);
+
+        let objects = [object.build()];
+        let mut linker = LinkerContext::default();
+        link(&mut linker, &objects);
+        let (start, image) = linker.flat_image();
+
+        let path = std::env::temp_dir().join(format!("elkr-ihex-{}.hex", std::process::id()));
+        let path = path.to_str().unwrap();
+        linker.write_ihex(path).unwrap();
+        let hex = fs::read_to_string(path).unwrap();
+        fs::remove_file(path).unwrap();
+
+        let entry = linker.global_symbols["_start"].final_addr;
+        assert_eq!(decode_ihex(&hex), (start, image, Some(entry)));
+    }
+
+    #[test]
+    fn test_data_only_link() {
+        let mut table = ObjectBuilder::new();
+        let rodata = table.data(".rodata", &[1, 2, 3, 4]);
+        table.global_object("table", rodata, 0, 4);
+        let data = table.data(".data", &[5, 6, 7, 8]);
+        table.global_object("counter", data, 0, 4);
+        let table = table.build();
+
+        // Without an entry point there is nothing to run
+        let objects = [table.clone()];
+        let mut linker = LinkerContext::default();
+        link(&mut linker, &objects);
+        assert!(matches!(
+            linker.link_to_bytes(),
+            Err(LinkerError::NoEntryPoint)
+        ));
+
+        let mut main = ObjectBuilder::new();
+        let text = main.text(&[0xd503201f]);
+        main.global_func("_start", text, 0, 4);
+        let objects = [table, main.build()];
+        let mut linker = LinkerContext::default();
+        link(&mut linker, &objects);
+        let image = linker.link_to_bytes().unwrap();
+        let entry = linker.global_symbols["_start"].final_addr;
+        let offset = linker.vaddr_to_offset(entry).unwrap() as usize;
+        assert_eq!(&image[offset..offset + 4], &0xd503201fu32.to_le_bytes());
+        let counter = linker.global_symbols["counter"].final_addr;
+        let offset = linker.vaddr_to_offset(counter).unwrap() as usize;
+        assert_eq!(&image[offset..offset + 4], &[5, 6, 7, 8]);
+    }
+
+    #[test]
+    fn test_set_section_align() {
+        let mut object = ObjectBuilder::new();
+        let text = object.text(&[0xd503201f]);
+        object.global_func("_start", text, 0, 4);
+        object.section(".rodata", SHT_PROGBITS, SHF_ALLOC, 8, &[1; 8]);
+
+        let objects = [object.build()];
+        // Share the code segment, so .rodata doesn't start on a page of its own anyway
+        let segments = || vec![SegmentSpec::new(&[".text", ".rodata"], PF_R | PF_X)];
+        let mut linker = LinkerContext::default();
+        linker.segment_map(segments());
+        link(&mut linker, &objects);
+        let rodata = &linker.output_sections[".rodata"];
+        assert_ne!(rodata.header.addr % PAGE_SIZE, 0);
+
+        let mut linker = LinkerContext::default();
+        linker
+            .segment_map(segments())
+            .set_section_align(".rodata", 0x1000);
+        link(&mut linker, &objects);
+        let rodata = &linker.output_sections[".rodata"];
+        assert_eq!(rodata.header.addr % 0x1000, 0);
+        assert_eq!(rodata.header.addralign, 0x1000);
+        // A weaker request doesn't lower the declared alignment
+        let mut linker = LinkerContext::default();
+        linker
+            .segment_map(segments())
+            .set_section_align(".rodata", 2);
+        link(&mut linker, &objects);
+        assert_eq!(linker.output_sections[".rodata"].header.addralign, 8);
+    }
+
+    #[test]
+    fn test_data_memsz_covers_bss_alignment_gap() {
+        let mut object = ObjectBuilder::new();
+        let text = object.text(&[0xd503201f]);
+        object.global_func("_start", text, 0, 4);
+        object.data(".data", &[1, 2, 3]);
+        object.nobits(".bss", 64, 16);
+
+        let objects = [object.build()];
+        let mut linker = LinkerContext::default();
+        link(&mut linker, &objects);
+        let image = linker.link_to_bytes().unwrap();
+
+        let data = &linker.output_sections[".data"];
+        let bss = &linker.output_sections[".bss"];
+        let gap = bss.header.addr - (data.header.addr + 3);
+        assert!(gap > 0);
+        let segment = program_headers(&image)
+            .into_iter()
+            .find(|h| h.p_type == PT_LOAD && h.vaddr == data.header.addr)
+            .unwrap();
+        assert_eq!(segment.filesz, 3);
+        // Zero-filled up to the end of .bss, padding included, not just 3 + 16 bytes
+        assert_eq!(segment.memsz, 3 + gap + 16);
+        assert_eq!(
+            segment.vaddr + segment.memsz,
+            bss.header.addr + bss.header.size
+        );
+    }
+
+    #[test]
+    fn test_program_headers_after_layout() {
+        let mut object = ObjectBuilder::new();
+        let text = object.text(&[0xd503201f]);
+        object.global_func("_start", text, 0, 4);
+        object.data(".data", &[1, 2, 3, 4]);
+
+        let objects = [object.build()];
+        let mut linker = LinkerContext::default();
+        link(&mut linker, &objects);
+        assert!(linker.program_headers().is_empty());
+        linker.finalize().unwrap();
+        let loads: Vec<_> = linker
+            .program_headers()
+            .iter()
+            .filter(|h| h.p_type == PT_LOAD)
+            .collect();
+        assert_eq!(loads.len(), 2);
+        assert_eq!(loads[0].flags, PF_R | PF_X);
+        assert_eq!(loads[1].flags, PF_R | PF_W);
+
+        let image = linker.link_to_bytes().unwrap();
+        let written = program_headers(&image);
+        assert_eq!(written.len(), linker.program_headers().len());
+        for (ours, theirs) in linker.program_headers().iter().zip(&written) {
+            assert_eq!((ours.vaddr, ours.filesz), (theirs.vaddr, theirs.filesz));
+        }
+    }
+
+    #[test]
+    fn test_finalize_matches_written_image() {
+        let mut object = ObjectBuilder::new();
+        let text = object.text(&[0xd503201f]);
+        object.global_func("_start", text, 0, 4);
+        object.data(".data", &[1, 2, 3, 4]);
+        object.nobits(".bss", 8, 32);
+        object.section(".comment", SHT_PROGBITS, 0, 1, b"elkr\0");
+
+        let objects = [object.build()];
+        let mut linker = LinkerContext::default();
+        linker.keep_sections(true);
+        link(&mut linker, &objects);
+        assert_eq!(linker.image_size(), None);
+        linker.finalize().unwrap();
+
+        let image = linker.link_to_bytes().unwrap();
+        assert_eq!(linker.image_size(), Some(image.len() as u64));
+        assert!(image.ends_with(b"elkr\0"));
+        let headers = program_headers(&image);
+        assert_eq!(headers.len(), linker.program_headers().len());
+        for (ours, theirs) in linker.program_headers().iter().zip(&headers) {
+            assert_eq!(
+                (
+                    ours.p_type,
+                    ours.offset,
+                    ours.vaddr,
+                    ours.filesz,
+                    ours.memsz
+                ),
+                (
+                    theirs.p_type,
+                    theirs.offset,
+                    theirs.vaddr,
+                    theirs.filesz,
+                    theirs.memsz
+                )
+            );
+        }
+        // Serializing without finalizing first gives the same bytes
+        let mut unfinalized = LinkerContext::default();
+        unfinalized.keep_sections(true);
+        link(&mut unfinalized, &objects);
+        assert_eq!(unfinalized.link_to_bytes().unwrap(), image);
+    }
+
+    #[test]
+    fn test_section_filters() {
+        let mut first = ObjectBuilder::new();
+        let text = first.text(&[0xd503201f]);
+        first.global_func("_start", text, 0, 4);
+        first.data(".data", &[1, 1, 1, 1]);
+        first.data(".rodata", &[3, 3, 3, 3]);
+        let mut second = ObjectBuilder::new();
+        second.data(".data", &[2, 2, 2, 2]);
+        let objects = [first.build(), second.build()];
+
+        let mut linker = LinkerContext::default();
+        linker.exclude_section("1.*", ".data");
+        link(&mut linker, &objects);
+        assert_eq!(linker.output_sections[".data"].data, [1, 1, 1, 1]);
+        let image = linker.link_to_bytes().unwrap();
+        assert!(!image.windows(4).any(|w| w == [2, 2, 2, 2]));
+
+        // Only .text and .data survive from 0.o; 1.o is unaffected
+        let mut linker = LinkerContext::default();
+        linker
+            .include_section("0.o", ".text")
+            .include_section("0.o", ".data");
+        link(&mut linker, &objects);
+        assert!(!linker.output_sections.contains_key(".rodata"));
+        assert_eq!(
+            linker.output_sections[".data"].data,
+            [1, 1, 1, 1, 0, 0, 0, 0, 2, 2, 2, 2]
+        );
+    }
+
+    #[test]
+    fn test_relocations_against_dropped_section_warn() {
+        let mut object = ObjectBuilder::new();
+        let text = object.text(&[0xd503201f]);
+        let start = object.global_func("_start", text, 0, 4);
+        let info = object.section(".debug_info", SHT_PROGBITS, 0, 1, &[0; 16]);
+        object.rela(info, 0, start, R_AARCH64_ABS64, 0);
+        object.rela(info, 8, start, R_AARCH64_ABS64, 0);
+
+        let objects = [object.build()];
+        let mut linker = LinkerContext::default();
+        link(&mut linker, &objects);
+        assert_eq!(
+            linker.warnings(),
+            [
+                "0.o: 2 relocation(s) against non-allocatable section .debug_info not applied, the section was dropped"
+            ]
+        );
+
+        // Kept, the section is no longer dropped
+        let mut linker = LinkerContext::default();
+        linker.keep_sections(true);
+        link(&mut linker, &objects);
+        assert!(linker.warnings().is_empty());
+    }
+
+    #[test]
+    fn test_keep_symbols_emits_symtab() {
+        let mut object = ObjectBuilder::new();
+        let text = object.text(&[0xd503201f, 0xd503201f, 0xd65f03c0]);
+        object.global_func("_start", text, 0, 4);
+        object.global_func("helper", text, 4, 8);
+        let data = object.data(".data", &[0; 8]);
+        object.global_object("counter", data, 4, 4);
+
+        let objects = [object.build()];
+        let mut linker = LinkerContext::default();
+        linker.keep_symbols(true);
+        link(&mut linker, &objects);
+        let image = linker.link_to_bytes().unwrap();
+
+        let sections = section_headers(&image);
+        assert_eq!(sections[0].1.sh_type, 0);
+        let symbols = output_symbols(&image);
+        assert_eq!(symbols[0].0, "");
+        let (_, helper) = symbols.iter().find(|(name, _)| name == "helper").unwrap();
+        assert_eq!(helper.get_type(), STT_FUNC);
+        assert_eq!(helper.get_bind(), STB_GLOBAL);
+        assert_eq!(helper.size, 8);
+        assert_eq!(
+            helper.value,
+            linker.output_sections[".text"].header.addr + 4
+        );
+        assert_eq!(sections[helper.shndx as usize].0, ".text");
+        let (_, counter) = symbols.iter().find(|(name, _)| name == "counter").unwrap();
+        assert_eq!(counter.get_type(), STT_OBJECT);
+        assert_eq!(sections[counter.shndx as usize].0, ".data");
+
+        // Without the option the output has no section headers at all
+        let mut linker = LinkerContext::default();
+        link(&mut linker, &objects);
+        let image = linker.link_to_bytes().unwrap();
+        assert_eq!(parse_elf_header(&image).unwrap().1.e_shoff, 0);
+    }
+
+    #[test]
+    fn test_symtab_locals_precede_globals() {
+        // Locals and globals interleaved in both inputs' symbol tables
+        let mut first = ObjectBuilder::new();
+        let text = first.text(&[0xd503201f; 4]);
+        first.symbol("first_loop", STB_LOCAL, STT_NOTYPE, text, 0, 0);
+        first.global_func("_start", text, 0, 8);
+        first.symbol("first_tail", STB_LOCAL, STT_FUNC, text, 8, 4);
+        first.global_func("helper", text, 8, 8);
+        first.symbol("first_end", STB_LOCAL, STT_NOTYPE, text, 12, 0);
+        let mut second = ObjectBuilder::new();
+        let data = second.data(".data", &[0; 16]);
+        second.global_object("counter", data, 0, 8);
+        second.symbol("second_state", STB_LOCAL, STT_OBJECT, data, 8, 8);
+        let objects = [first.build(), second.build()];
+
+        let mut linker = LinkerContext::default();
+        linker.keep_symbols(true);
+        link(&mut linker, &objects);
+        let image = linker.link_to_bytes().unwrap();
+
+        let symbols = output_symbols(&image);
+        let first_global = symbols
+            .iter()
+            .position(|(_, symbol)| symbol.get_bind() != STB_LOCAL)
+            .unwrap();
+        assert!(
+            symbols[first_global..]
+                .iter()
+                .all(|(_, symbol)| symbol.get_bind() != STB_LOCAL)
+        );
+        let locals: Vec<&str> = symbols[1..first_global]
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .filter(|name| !name.is_empty())
+            .collect();
+        for name in ["first_loop", "first_tail", "first_end", "second_state"] {
+            assert!(locals.contains(&name), "{name} in {locals:?}");
+        }
+        let (_, symtab) = section_headers(&image)
+            .into_iter()
+            .find(|(_, s)| s.sh_type == SHT_SYMTAB)
+            .unwrap();
+        // sh_info is one past the last local, counting the null symbol
+        let local_count = first_global - 1;
+        assert_eq!(symtab.info as usize, local_count + 1);
+    }
+
+    #[test]
+    fn test_rel_relocations_use_implicit_addends() {
+        let mut object = ObjectBuilder::new();
+        object.rel = true;
+        // bl with imm26 = 2 words, then a word holding 0x10
+        let text = object.text(&[0x9400_0002, 0x10]);
+        let start = object.global_func("_start", text, 0, 8);
+        let callee = object.undefined("callee");
+        object.rela(text, 0, callee, R_AARCH64_CALL26, 0);
+        object.rela(text, 4, start, R_AARCH64_PREL32, 0);
+        let mut other = ObjectBuilder::new();
+        let other_text = other.text(&[0xd503201f; 4]);
+        other.global_func("callee", other_text, 0, 16);
+
+        let objects = [object.build(), other.build()];
+        let mut linker = LinkerContext::default();
+        link(&mut linker, &objects);
+        let text = &linker.output_sections[".text"];
+        let start = text.header.addr;
+        let callee = linker.global_symbols["callee"].final_addr;
+        let insn = u32::from_le_bytes(text.data[0..4].try_into().unwrap());
+        assert_eq!(insn, 0x9400_0000 | branch26_imm(callee, 8, start).unwrap());
+        // S + A - P with S = _start, A = 0x10 and P = _start + 4
+        let prel = u32::from_le_bytes(text.data[4..8].try_into().unwrap());
+        assert_eq!(prel, 0x10 - 4);
+    }
+
+    #[test]
+    fn test_rel_relocation_without_implicit_addend_is_rejected() {
+        let mut object = ObjectBuilder::new();
+        object.rel = true;
+        let text = object.text(&[0xd280_0000]);
+        let start = object.global_func("_start", text, 0, 4);
+        object.rela(text, 0, start, R_AARCH64_MOVW_UABS_G0, 0);
+
+        let objects = [object.build()];
+        let mut linker = LinkerContext::default();
+        linker.add_file("0.o".to_string(), &objects[0]).unwrap();
+        linker.layout_and_merge_sections().unwrap();
+        linker.resolve_symbols().unwrap();
+        assert!(matches!(
+            linker.apply_relocations(),
+            Err(LinkerError::BadRelocation { .. })
+        ));
+    }
+
+    #[test]
+    fn test_file_offset_overflow() {
+        // Two huge alignments push the second unloaded section past 2^64
+        let mut object = ObjectBuilder::new();
+        let text = object.text(&[0x14000000]); // b .
+        object.global_func("_start", text, 0, 4);
+        object.section(".comment.a", SHT_PROGBITS, 0, 1 << 63, &[1]);
+        object.section(".comment.b", SHT_PROGBITS, 0, 1 << 63, &[2]);
+        let objects = [object.build()];
+
+        let mut linker = LinkerContext::default();
+        linker.keep_sections(true);
+        link(&mut linker, &objects);
+        let err = linker.finalize().unwrap_err();
+        assert!(matches!(
+            err,
+            LinkerError::OffsetOverflow { ref section } if section == ".comment.b"
+        ));
+        assert_eq!(
+            err.to_string(),
+            "section .comment.b doesn't fit in the output file"
+        );
+    }
+
+    #[test]
+    fn test_write_at_checks_bounds() {
+        let mut image = vec![0; 8];
+        write_at(&mut image, 4, &[1, 2, 3, 4]).unwrap();
+        assert_eq!(image, [0, 0, 0, 0, 1, 2, 3, 4]);
+        let err = write_at(&mut image, 6, &[5, 6, 7]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invalid output: 0x3 bytes at offset 0x6 don't fit in the 0x8-byte image"
+        );
+        assert!(write_at(&mut image, u64::MAX, &[1]).is_err());
+    }
+
+    #[test]
+    fn test_align_up() {
+        assert_eq!(align_up(0x1001, 0x1000), Some(0x2000));
+        assert_eq!(align_up(0x2000, 0x1000), Some(0x2000));
+        assert_eq!(align_up(7, 1), Some(7));
+        assert_eq!(align_up(u64::MAX - 0x10, 0x1000), None);
+        assert_eq!(align_up(u64::MAX, 1), Some(u64::MAX));
+        assert_eq!(align_up(0x10, 0x30), None);
+        assert_eq!(align_up(0x10, 0), None);
+    }
+
+    #[test]
+    fn test_non_power_of_two_alignment_is_rejected() {
+        let mut object = ObjectBuilder::new();
+        let text = object.text(&[0xd503201f]);
+        object.global_func("_start", text, 0, 4);
+        object.section(".rodata", SHT_PROGBITS, SHF_ALLOC, 12, &[0; 4]);
+
+        let objects = [object.build()];
+        let mut linker = LinkerContext::default();
+        linker.add_file("0.o".to_string(), &objects[0]).unwrap();
+        assert!(matches!(
+            linker.layout_and_merge_sections(),
+            Err(LinkerError::BadAlignment { section, align: 12 }) if section == ".rodata"
+        ));
+    }
+
+    #[test]
+    fn test_section_relative_reference_uses_own_contribution() {
+        let mut a = ObjectBuilder::new();
+        let a_text = a.text(&[0xd503201f; 4]);
+        a.global_func("foo", a_text, 0, 16);
+        // B calls a local function at .text+8 through its section symbol, as assemblers do
+        let mut b = ObjectBuilder::new();
+        let b_text = b.text(&[0x9400_0000, 0xd503201f, 0xd65f03c0]);
+        b.global_func("_start", b_text, 0, 8);
+        let b_section = b.section_symbol(b_text);
+        b.rela(b_text, 0, b_section, R_AARCH64_CALL26, 8);
+
+        let objects = [a.build(), b.build()];
+        let mut linker = LinkerContext::default();
+        link(&mut linker, &objects);
+        let text = &linker.output_sections[".text"];
+        let start = linker.global_symbols["_start"].final_addr;
+        // B's .text follows A's 16 bytes in the merged section
+        assert_eq!(start, text.header.addr + 16);
+        let offset = (start - text.header.addr) as usize;
+        let insn = u32::from_le_bytes(text.data[offset..offset + 4].try_into().unwrap());
+        assert_eq!(
+            insn,
+            0x9400_0000 | branch26_imm(start + 8, 0, start).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_require_defined() {
+        let mut object = ObjectBuilder::new();
+        let text = object.text(&[0xd503201f]);
+        object.global_func("_start", text, 0, 4);
+        let data = object.data(".data", &[1, 0, 0, 0]);
+        object.global_object("version_stamp", data, 0, 4);
+        let objects = [object.build()];
+
+        let mut linker = LinkerContext::default();
+        linker.require_defined("version_stamp");
+        link(&mut linker, &objects);
+
+        let mut linker = LinkerContext::default();
+        linker
+            .require_defined("version_stamp")
+            .require_defined("init_hook");
+        linker.add_file("0.o".to_string(), &objects[0]).unwrap();
+        linker.layout_and_merge_sections().unwrap();
+        assert!(matches!(
+            linker.resolve_symbols(),
+            Err(LinkerError::RequiredSymbolMissing { symbol }) if symbol == "init_hook"
+        ));
+    }
+
+    #[test]
+    fn test_keep_undefined_survives_gc() {
+        let exec = SHF_ALLOC | SHF_EXECINSTR;
+        let mut object = ObjectBuilder::new();
+        let start = object.section(
+            ".text._start",
+            SHT_PROGBITS,
+            exec,
+            4,
+            &[0x1f, 0x20, 0x03, 0xd5],
+        );
+        object.global_func("_start", start, 0, 4);
+        let hook = object.section(
+            ".text.hook",
+            SHT_PROGBITS,
+            exec,
+            4,
+            &[0xc0, 0x03, 0x5f, 0xd6],
+        );
+        object.global_func("hook", hook, 0, 4);
+        let objects = [object.build()];
+
+        let mut linker = LinkerContext::default();
+        linker.gc_sections(true);
+        link(&mut linker, &objects);
+        assert!(!linker.output_sections.contains_key(".text.hook"));
+
+        let mut linker = LinkerContext::default();
+        linker.gc_sections(true).keep_undefined("hook");
+        link(&mut linker, &objects);
+        assert!(linker.output_sections.contains_key(".text.hook"));
+        assert!(linker.global_symbols.contains_key("hook"));
+    }
+
+    #[test]
+    fn test_global_in_non_alloc_section() {
+        let mut object = ObjectBuilder::new();
+        let text = object.text(&[0xd503201f]);
+        object.global_func("_start", text, 0, 4);
+        let info = object.section(".debug_info", SHT_PROGBITS, 0, 1, &[0; 16]);
+        object.global_object("debug_marker", info, 8, 4);
+        let objects = [object.build()];
+
+        let mut linker = LinkerContext::default();
+        link(&mut linker, &objects);
+        assert!(!linker.global_symbols.contains_key("debug_marker"));
+        assert_eq!(
+            linker.warnings(),
+            [
+                "0.o: global symbol `debug_marker` is defined in non-allocatable section .debug_info, which was dropped"
+            ]
+        );
+
+        // Kept, it resolves to its offset in the unloaded section
+        let mut linker = LinkerContext::default();
+        linker.keep_sections(true);
+        link(&mut linker, &objects);
+        assert!(linker.warnings().is_empty());
+        assert_eq!(linker.global_symbols["debug_marker"].final_addr, 8);
+    }
+
+    #[test]
+    fn test_assert_address() {
+        let mut object = ObjectBuilder::new();
+        let text = object.text(&[0xd503201f]);
+        object.global_func("_start", text, 0, 4);
+        let bss = object.nobits(".bss", 8, 64);
+        object.global_object("_end", bss, 64, 0);
+        let objects = [object.build()];
+
+        let mut linker = LinkerContext::default();
+        linker
+            .assert_address(AddrAssertion::at_most("_end", 0x0804_0000), "image too big")
+            .assert_address(AddrAssertion::at_least("_start", 0x40_0000), "below base");
+        link(&mut linker, &objects);
+
+        let mut linker = LinkerContext::default();
+        linker.assert_address(AddrAssertion::at_most("_end", 0x40_1000), "image too big");
+        linker.add_file("0.o".to_string(), &objects[0]).unwrap();
+        linker.layout_and_merge_sections().unwrap();
+        let err = linker.resolve_symbols().unwrap_err();
+        assert_eq!(err.to_string(), "assertion failed: image too big");
+    }
+
+    #[test]
+    fn test_prel32_negative_addend() {
+        let mut object = ObjectBuilder::new();
+        let text = object.text(&[0xd503201f, 0]);
+        let start = object.global_func("_start", text, 0, 8);
+        object.rela(text, 4, start, R_AARCH64_PREL32, -8);
+
+        let objects = [object.build()];
+        let mut linker = LinkerContext::default();
+        link(&mut linker, &objects);
+        // S + A - P with S = _start, A = -8 and P = _start + 4
+        let text = &linker.output_sections[".text"];
+        let prel = i32::from_le_bytes(text.data[4..8].try_into().unwrap());
+        assert_eq!(prel, -12);
+    }
+
+    #[test]
+    fn test_prel32_range() {
+        let p = 0x1_0000_0000;
+        assert_eq!(prel32(p - 4, 0, p), Some((-4i32) as u32));
+        assert_eq!(prel32(p, -(1 << 31), p), Some(0x8000_0000));
+        assert_eq!(prel32(p, -(1 << 31) - 1, p), None);
+        // Forward displacements may use the full unsigned range
+        assert_eq!(prel32(p + 0xffff_ffff, 0, p), Some(0xffff_ffff));
+        assert_eq!(prel32(p + (1 << 32), 0, p), None);
+
+        let mut data = [0; 4];
+        assert_eq!(
+            relocate(&mut data, 0, R_AARCH64_PREL32, 0, 0, p, None),
+            Err(RelocationError::OutOfRange {
+                r_type: R_AARCH64_PREL32,
+                offset: 0
+            })
+        );
+    }
+
+    #[test]
+    fn test_prel64_symbol_below_place() {
+        let mut object = ObjectBuilder::new();
+        let text = object.text(&[0xd503201f]);
+        let start = object.global_func("_start", text, 0, 4);
+        let data = object.data(".data", &[0; 16]);
+        object.rela(data, 8, start, R_AARCH64_PREL64, 2);
+
+        let objects = [object.build()];
+        let mut linker = LinkerContext::default();
+        link(&mut linker, &objects);
+        let data = &linker.output_sections[".data"];
+        let start = linker.global_symbols["_start"].final_addr;
+        let prel = i64::from_le_bytes(data.data[8..16].try_into().unwrap());
+        // .data is laid out after .text, so the displacement is negative
+        assert_eq!(prel, start as i64 + 2 - (data.header.addr + 8) as i64);
+        assert!(prel < 0);
+    }
+
+    #[test]
+    fn test_relro_covers_init_array() {
+        let mut object = ObjectBuilder::new();
+        let text = object.text(&[0xd503201f]);
+        object.global_func("_start", text, 0, 4);
+        let write = SHF_ALLOC | SHF_WRITE;
+        object.section(".data", SHT_PROGBITS, write, 8, &[1; 8]);
+        object.section(".init_array", SHT_INIT_ARRAY, write, 8, &[2; 16]);
+
+        let objects = [object.build()];
+        let mut linker = LinkerContext::default();
+        link(&mut linker, &objects);
+        linker.finalize().unwrap();
+        let init_array = &linker.output_sections[".init_array"].header;
+        let relro = linker
+            .program_headers()
+            .iter()
+            .find(|h| h.p_type == PT_GNU_RELRO)
+            .expect("no PT_GNU_RELRO header");
+        assert_eq!(relro.vaddr % PAGE_SIZE, 0);
+        assert!(relro.vaddr <= init_array.addr);
+        assert!(init_array.addr + init_array.size <= relro.vaddr + relro.memsz);
+        // Loaders only protect whole pages, so it ends on a page boundary too
+        assert_eq!((relro.vaddr + relro.memsz) % PAGE_SIZE, 0);
+        assert_eq!(relro.filesz, relro.memsz);
+        // .data stays writable
+        let data = &linker.output_sections[".data"].header;
+        assert!(data.addr >= relro.vaddr + relro.memsz);
+
+        let image = linker.link_to_bytes().unwrap();
+        let offset = linker.vaddr_to_offset(init_array.addr).unwrap() as usize;
+        assert_eq!(image[offset..offset + 16], [2; 16]);
+    }
+
+    #[test]
+    fn test_display_program_header_and_section() {
+        let header = ProgramHeader {
+            p_type: PT_LOAD,
+            flags: PF_R | PF_X,
+            offset: 0,
+            vaddr: 0x40_0000,
+            paddr: 0x40_0000,
+            filesz: 0x7c,
+            memsz: 0x7c,
+            align: PAGE_SIZE,
+        };
+        assert_eq!(
+            header.to_string(),
+            "LOAD           0x000000 0x0000000000400000 0x00007c 0x00007c R E 0x1000"
+        );
+        assert!(header.to_string().contains("R E"));
+
+        let mut object = ObjectBuilder::new();
+        let text = object.text(&[0xd503201f]);
+        object.global_func("_start", text, 0, 4);
+        let objects = [object.build()];
+        let mut linker = LinkerContext::default();
+        link(&mut linker, &objects);
+        let text = &linker.output_sections[".text"];
+        assert_eq!(
+            text.summary(),
+            format!(".text @ {:#x}, size 0x4, flags AX", text.header.addr)
+        );
+    }
+
+    #[test]
+    fn test_adr_imm_encoding() {
+        // adr x0, .+0x10: immlo = 0, immhi = 4
+        assert_eq!(adr_imm(0x400010, 0, 0x400000), Some(4 << 5));
+        // adr x0, .-1: all ones, in both fields
+        assert_eq!(adr_imm(0x400000, -1, 0x400000), Some(ADR_IMM_MASK));
+        assert_eq!(
+            adr_imm(0, (1 << 20) - 1, 0),
+            Some((0b11 << 29) | (0x3_FFFF << 5))
+        );
+        assert_eq!(adr_imm(0, 1 << 20, 0), None);
+        assert_eq!(adr_imm(0, -(1 << 20) - 1, 0), None);
+    }
+
+    #[test]
+    fn test_adr_prel_lo21() {
+        let mut object = ObjectBuilder::new();
+        // adr x0, message; ret
+        let text = object.text(&[0x1000_0000, 0xd65f03c0]);
+        object.global_func("_start", text, 0, 8);
+        let rodata = object.data(".rodata", b"hi\0\0");
+        let message = object.global_object("message", rodata, 1, 2);
+        object.rela(text, 0, message, R_AARCH64_ADR_PREL_LO21, 0);
+
+        let objects = [object.build()];
+        let mut linker = LinkerContext::default();
+        link(&mut linker, &objects);
+        let text = &linker.output_sections[".text"];
+        let adr = u32::from_le_bytes(text.data[0..4].try_into().unwrap());
+        let message = linker.global_symbols["message"].final_addr;
+        assert_eq!(
+            adr,
+            0x1000_0000 | adr_imm(message, 0, text.header.addr).unwrap()
+        );
+        assert_eq!(
+            format_instruction(adr, text.header.addr, |_| None),
+            format!("adr x0, {message:#x}")
+        );
+    }
+
+    #[test]
+    fn test_adr_prel_lo21_out_of_range() {
+        let mut object = ObjectBuilder::new();
+        let text = object.text(&[0x1000_0000]);
+        object.global_func("_start", text, 0, 4);
+        let bss = object.nobits(".bss", 8, 0x20_0000);
+        let far = object.global_object("far", bss, 0x10_0000, 8);
+        object.rela(text, 0, far, R_AARCH64_ADR_PREL_LO21, 0);
+
+        let objects = [object.build()];
+        let mut linker = LinkerContext::default();
+        linker.add_file("0.o".to_string(), &objects[0]).unwrap();
+        linker.layout_and_merge_sections().unwrap();
+        linker.resolve_symbols().unwrap();
+        let err = linker.apply_relocations().unwrap_err();
+        assert!(matches!(
+            err,
+            LinkerError::RelocationOutOfRange {
+                r_type: R_AARCH64_ADR_PREL_LO21,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_section_order() {
+        let mut object = ObjectBuilder::new();
+        let text = object.text(&[0xd503201f]);
+        object.global_func("_start", text, 0, 4);
+        object.data(".data", &[1; 8]);
+        object.section(".rodata", SHT_PROGBITS, SHF_ALLOC, 8, &[2; 8]);
+        let objects = [object.build()];
+
+        let mut linker = LinkerContext::default();
+        linker.section_order(vec![".data".to_string(), ".text".to_string()]);
+        link(&mut linker, &objects);
+        let addr = |name: &str| linker.output_sections[name].header.addr;
+        assert!(addr(".data") < addr(".text"));
+        // .rodata isn't listed, so it follows .data in the segment they share
+        assert!(addr(".data") < addr(".rodata"));
+        assert!(addr(".rodata") < addr(".text"));
+
+        let image = linker.link_to_bytes().unwrap();
+        let headers = program_headers(&image);
+        assert_eq!(headers[0].flags, DATA_SEGMENT_FLAGS);
+        assert_eq!(headers[1].flags, CODE_SEGMENT_FLAGS);
+        let offset = linker.vaddr_to_offset(addr(".data")).unwrap() as usize;
+        assert_eq!(image[offset..offset + 8], [1; 8]);
+    }
+
+    #[test]
+    fn test_merged_section_takes_strictest_alignment() {
+        let build = |align: u64| {
+            let mut object = ObjectBuilder::new();
+            let exec = SHF_ALLOC | SHF_EXECINSTR;
+            object.section(
+                ".text",
+                SHT_PROGBITS,
+                exec,
+                align,
+                &[0x1f, 0x20, 0x03, 0xd5],
+            );
+            object
+        };
+        let mut loose = build(4);
+        loose.global_func("_start", 1, 0, 4);
+        let strict = build(64);
+
+        for objects in [
+            [loose.build(), strict.build()],
+            [strict.build(), loose.build()],
+        ] {
+            let mut linker = LinkerContext::default();
+            link(&mut linker, &objects);
+            let text = &linker.output_sections[".text"].header;
+            assert_eq!(text.addralign, 64);
+            assert_eq!(text.addr % 64, 0);
+        }
+    }
+
+    #[cfg(feature = "zlib")]
+    #[test]
+    fn test_compress_debug_sections() {
+        use std::io::Read;
+
+        use crate::elf::compression::{ELFCOMPRESS_ZLIB, parse_compression_header};
+
+        let debug_info: Vec<u8> = (0..200u8).cycle().take(1000).collect();
+        let mut object = ObjectBuilder::new();
+        let text = object.text(&[0x14000000]);
+        object.global_func("_start", text, 0, 4);
+        object.section(".debug_info", SHT_PROGBITS, 0, 1, &debug_info);
+        object.section(".comment", SHT_PROGBITS, 0, 1, b"elkr\0");
+
+        let objects = [object.build()];
+        let mut linker = LinkerContext::default();
+        linker
+            .keep_sections(true)
+            .keep_symbols(true)
+            .compress_debug_sections(CompressKind::Zlib);
+        link(&mut linker, &objects);
+        let image = linker.link_to_bytes().unwrap();
+
+        let headers = section_headers(&image);
+        let (_, debug) = headers
+            .iter()
+            .find(|(name, _)| name == ".debug_info")
+            .unwrap();
+        assert_ne!(debug.flags & SHF_COMPRESSED, 0);
+        let contents = &image[debug.offset as usize..(debug.offset + debug.size) as usize];
+        let (stream, chdr) = parse_compression_header(contents).unwrap();
+        assert_eq!(chdr.ch_type, ELFCOMPRESS_ZLIB);
+        assert_eq!(chdr.ch_size, debug_info.len() as u64);
+        assert_eq!(chdr.ch_addralign, 1);
+        let mut decompressed = Vec::new();
+        flate2::read::ZlibDecoder::new(stream)
+            .read_to_end(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, debug_info);
+        // Only debug sections are compressed
+        let (_, comment) = headers.iter().find(|(name, _)| name == ".comment").unwrap();
+        assert_eq!(comment.flags & SHF_COMPRESSED, 0);
+    }
+
+    #[test]
+    fn test_zero_addralign_becomes_one() {
+        let mut object = ObjectBuilder::new();
+        let text = object.text(&[0xd503201f]);
+        object.global_func("_start", text, 0, 4);
+        object.section(".data", SHT_PROGBITS, SHF_ALLOC | SHF_WRITE, 0, &[1, 2, 3]);
+        object.section(".comment", SHT_PROGBITS, 0, 0, b"elkr\0");
+
+        let objects = [object.build()];
+        let mut linker = LinkerContext::default();
+        linker.keep_sections(true).keep_symbols(true);
+        link(&mut linker, &objects);
+        let image = linker.link_to_bytes().unwrap();
+        for (name, header) in section_headers(&image).iter().skip(1) {
+            assert!(header.addralign >= 1, "{name} has addralign 0");
+        }
+        assert_eq!(linker.output_sections[".data"].header.addralign, 1);
+    }
+
+    #[test]
+    fn test_apply_relocations_to_buffer() {
+        // nop; bl <symbol 1>
+        let mut code = [0x1f, 0x20, 0x03, 0xd5, 0x00, 0x00, 0x00, 0x94];
+        let call = |symbol: u64| Rela {
+            offset: 4,
+            info: (symbol << 32) | R_AARCH64_CALL26 as u64,
+            addend: 0,
+        };
+        let resolver = |symbol: u32| (symbol == 1).then_some(0x1000);
+        apply_relocations_to(&mut code, &[call(1)], resolver, 0x2000, TargetArch::AArch64).unwrap();
+        let bl = u32::from_le_bytes(code[4..8].try_into().unwrap());
+        assert_eq!(bl, 0x9400_0000 | branch26_imm(0x1000, 0, 0x2004).unwrap());
+        assert_eq!(format_instruction(bl, 0x2004, |_| None), "bl 0x1000");
+
+        assert_eq!(
+            apply_relocations_to(&mut code, &[call(2)], resolver, 0x2000, TargetArch::AArch64),
+            Err(RelocationError::UnresolvedSymbol {
+                symbol: 2,
+                offset: 4
+            })
+        );
+        let past_end = Rela {
+            offset: 8,
+            ..call(1)
+        };
+        assert!(matches!(
+            apply_relocations_to(
+                &mut code,
+                &[past_end],
+                resolver,
+                0x2000,
+                TargetArch::AArch64
+            ),
+            Err(RelocationError::OutOfBounds { offset: 8, .. })
+        ));
+    }
+
+    #[test]
+    fn test_load_segments_are_congruent_with_file_offsets() {
+        let mut object = ObjectBuilder::new();
+        let text = object.text(&[0xd503201f, 0xd65f03c0]);
+        object.global_func("_start", text, 0, 8);
+        object.section(".rodata", SHT_PROGBITS, SHF_ALLOC, 16, &[1; 5]);
+        object.section(".data", SHT_PROGBITS, SHF_ALLOC | SHF_WRITE, 64, &[2; 3]);
+
+        let objects = [object.build()];
+        let mut linker = LinkerContext::default();
+        linker.keep_symbols(true);
+        link(&mut linker, &objects);
+        let image = linker.link_to_bytes().unwrap();
+
+        // mmap needs vaddr ≡ offset (mod page size), the headers in the code segment included
+        let loads: Vec<_> = program_headers(&image)
+            .into_iter()
+            .filter(|h| h.p_type == PT_LOAD)
+            .collect();
+        assert_eq!(loads[0].offset, 0);
+        for load in &loads {
+            assert_eq!(load.vaddr % PAGE_SIZE, load.offset % PAGE_SIZE);
+        }
+        // .text follows the headers, and sits at the same distance from the segment start
+        // in the file as in memory
+        for (name, header) in section_headers(&image) {
+            if header.flags & SHF_ALLOC == 0 {
+                continue;
+            }
+            let load = loads
+                .iter()
+                .find(|h| header.addr >= h.vaddr && header.addr < h.vaddr + h.memsz)
+                .unwrap();
+            assert_eq!(
+                header.addr - load.vaddr,
+                header.offset - load.offset,
+                "{name}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_nop_fill_between_code_sections() {
+        let mut first = ObjectBuilder::new();
+        let text = first.text(&[0xd65f03c0]); // ret
+        first.global_func("_start", text, 0, 4);
+        let mut second = ObjectBuilder::new();
+        let exec = SHF_ALLOC | SHF_EXECINSTR;
+        second.section(
+            ".text",
+            SHT_PROGBITS,
+            exec,
+            16,
+            &0xd65f03c0u32.to_le_bytes(),
+        );
+        second.section(".data", SHT_PROGBITS, SHF_ALLOC | SHF_WRITE, 8, &[1]);
+        second.section(".data", SHT_PROGBITS, SHF_ALLOC | SHF_WRITE, 8, &[2]);
+        let objects = [first.build(), second.build()];
+
+        let mut linker = LinkerContext::default();
+        linker.no_merge(true).code_fill(FillKind::Nop);
+        link(&mut linker, &objects);
+        let image = linker.link_to_bytes().unwrap();
+        let (start, flat) = linker.flat_image();
+
+        let mut texts: Vec<_> = linker
+            .output_sections
+            .values()
+            .filter(|s| s.name.starts_with(".text"))
+            .map(|s| &s.header)
+            .collect();
+        texts.sort_by_key(|h| h.addr);
+        let gap = texts[0].addr + 4..texts[1].addr;
+        assert!(!gap.is_empty());
+        let words = |bytes: &[u8]| -> Vec<u32> {
+            bytes
+                .chunks(4)
+                .map(|w| u32::from_le_bytes(w.try_into().unwrap()))
+                .collect()
+        };
+        let offset = linker.vaddr_to_offset(gap.start).unwrap() as usize;
+        let len = (gap.end - gap.start) as usize;
+        assert!(
+            words(&image[offset..offset + len])
+                .iter()
+                .all(|&w| w == AARCH64_NOP)
+        );
+        let flat_offset = (gap.start - start) as usize;
+        assert_eq!(
+            flat[flat_offset..flat_offset + len],
+            image[offset..offset + len]
+        );
+
+        // Data is still zero-filled
+        let mut datas: Vec<_> = linker
+            .output_sections
+            .values()
+            .filter(|s| s.name.starts_with(".data"))
+            .map(|s| &s.header)
+            .collect();
+        datas.sort_by_key(|h| h.addr);
+        let offset = linker.vaddr_to_offset(datas[0].addr).unwrap() as usize;
+        assert_eq!(image[offset..offset + 9], [1, 0, 0, 0, 0, 0, 0, 0, 2]);
+    }
+
+    #[test]
+    fn test_write_map_json() {
+        let mut object = ObjectBuilder::new();
+        let text = object.text(&[0xd503201f, 0xd503201f]);
+        object.global_func("_start", text, 0, 8);
+        let data = object.data(".data", &[1, 2, 3, 4]);
+        object.global_object("value", data, 0, 4);
+        let objects = [object.build()];
+        let mut linker = LinkerContext::default();
+        link(&mut linker, &objects);
+
+        let path = std::env::temp_dir().join(format!("elkr-map-{}.json", std::process::id()));
+        let path = path.to_str().unwrap();
+        linker.write_map_json(path).unwrap();
+        let out = fs::read(path).unwrap();
+        fs::remove_file(path).unwrap();
+        let map: serde_json::Value = serde_json::from_slice(&out).unwrap();
+
+        let sections = map["sections"].as_array().unwrap();
+        assert_eq!(sections.len(), linker.output_sections.len());
+        let value = map["symbols"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|s| s["name"] == "value")
+            .unwrap();
+        assert_eq!(value["addr"], linker.global_symbols["value"].final_addr);
+        assert_eq!(value["section"], ".data");
+        let segments = map["segments"].as_array().unwrap();
+        assert_eq!(segments[0]["type"], "LOAD");
+        assert_eq!(segments[0]["offset"], 0);
+    }
+
+    #[test]
+    fn test_symbol_in_rela_section_is_not_resolved() {
+        let mut object = ObjectBuilder::new();
+        let text = object.text(&[0x94000000]);
+        object.global_func("_start", text, 0, 4);
+        let callee = object.undefined("callee");
+        object.rela(text, 0, callee, R_AARCH64_CALL26, 0);
+        // .rela.text directly follows .text
+        object.global_func("bogus", text + 1, 0, 4);
+        let mut other = ObjectBuilder::new();
+        let other_text = other.text(&[0xd65f03c0]);
+        other.global_func("callee", other_text, 0, 4);
+        let objects = [object.build(), other.build()];
+
+        let mut linker = LinkerContext::default();
+        link(&mut linker, &objects);
+        assert!(!linker.global_symbols.contains_key("bogus"));
+        assert_eq!(
+            linker.warnings(),
+            [
+                "0.o: global symbol `bogus` claims to be defined in .rela.text, which holds no code or data"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_pad_to_size() {
+        let mut object = ObjectBuilder::new();
+        let text = object.text(&[0xd503201f]);
+        object.global_func("_start", text, 0, 4);
+        let objects = [object.build()];
+        let mut linker = LinkerContext::default();
+        linker.fill_byte(0xff).pad_to_size(0x1_0000);
+        link(&mut linker, &objects);
+        linker.finalize().unwrap();
+
+        let image = linker.link_to_bytes().unwrap();
+        assert_eq!(image.len(), 0x1_0000);
+        let size = linker.image_size().unwrap() as usize;
+        assert!(image[size..].iter().all(|&b| b == 0xff));
+        // The program headers describe the contents, not the padding
+        assert!(
+            linker
+                .program_headers()
+                .iter()
+                .all(|h| h.offset + h.filesz <= size as u64)
+        );
+
+        let path = std::env::temp_dir().join(format!("elkr-pad-{}.bin", std::process::id()));
+        linker.write_binary(path.to_str().unwrap()).unwrap();
+        let binary = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(binary.len(), 0x1_0000);
+        assert_eq!(binary[..4], 0xd503201fu32.to_le_bytes());
+        assert!(binary[4..].iter().all(|&b| b == 0xff));
+
+        linker.pad_to_size(16);
+        assert!(matches!(
+            linker.link_to_bytes(),
+            Err(LinkerError::ImageTooLarge { limit: 16, .. })
+        ));
+    }
+
+    #[test]
+    fn test_abs64_in_data() {
+        let mut first = ObjectBuilder::new();
+        let text = first.text(&[0xd503201f, 0xd65f03c0]);
+        first.global_func("_start", text, 0, 8);
+        first.data(".data", &[0xaa; 16]);
+        // The pointer lives in the second input's .data, after the first one's
+        let mut second = ObjectBuilder::new();
+        let data = second.data(".data", &[0; 16]);
+        let target = second.undefined("_start");
+        second.rela(data, 8, target, R_AARCH64_ABS64, 4);
+        let objects = [first.build(), second.build()];
+        let mut linker = LinkerContext::default();
+        link(&mut linker, &objects);
+
+        let pointer = (linker.global_symbols["_start"].final_addr + 4).to_le_bytes();
+        assert_eq!(linker.input_section_offsets[&(1, data as usize)], 16);
+        let data = &linker.output_sections[".data"];
+        assert_eq!(data.data[..16], [0xaa; 16]);
+        assert_eq!(data.data[24..32], pointer);
+
+        let image = linker.link_to_bytes().unwrap();
+        let file_offset = linker.vaddr_to_offset(data.header.addr + 24).unwrap() as usize;
+        assert_eq!(image[file_offset..file_offset + 8], pointer);
+    }
+
+    #[test]
+    fn test_relocation_without_symbol() {
+        let mut object = ObjectBuilder::new();
+        let text = object.text(&[0xd503201f]);
+        object.global_func("_start", text, 0, 4);
+        let data = object.data(".data", &[0xff; 8]);
+        object.rela(data, 0, 0, R_AARCH64_ABS64, 0x1234);
+        let objects = [object.build()];
+        let mut linker = LinkerContext::default();
+        link(&mut linker, &objects);
+        assert_eq!(
+            linker.output_sections[".data"].data,
+            0x1234u64.to_le_bytes()
+        );
+
+        // A symbol table that doesn't start with the null symbol is rejected
+        let mut image = objects[0].clone();
+        let (_, symtab) = section_headers(&image)
+            .into_iter()
+            .find(|(name, _)| name == ".symtab")
+            .unwrap();
+        image[symtab.offset as usize + 6] = 1;
+        let mut linker = LinkerContext::default();
+        assert!(matches!(
+            linker.add_file("0.o".to_string(), &image),
+            Err(LinkerError::Malformed { reason, .. }) if reason.contains("null symbol")
+        ));
+    }
+
+    #[test]
+    fn test_skipped_sections() {
+        let mut object = ObjectBuilder::new();
+        let text = object.text(&[0xd503201f]);
+        object.global_func("_start", text, 0, 4);
+        object.section(".comment", SHT_PROGBITS, 0, 1, b"GCC\0");
+        object.section(".note.ABI-tag", SHT_NOTE, SHF_ALLOC, 4, &[0; 16]);
+        object.data(".data", &[0; 8]);
+        object.section(".rela.misc", SHT_PROGBITS, SHF_ALLOC, 1, &[1]);
+        let objects = [object.build()];
+
+        let mut linker = LinkerContext::default();
+        linker.exclude_section("*", ".data");
+        link(&mut linker, &objects);
+        let skipped = |name: &str, reason| SkippedSection {
+            file: "0.o".to_string(),
+            name: name.to_string(),
+            reason,
+        };
+        assert_eq!(
+            linker.skipped_sections(),
+            [
+                skipped(".comment", SkipReason::NotAllocatable),
+                skipped(".note.ABI-tag", SkipReason::NotProgbitsOrNobits),
+                skipped(".data", SkipReason::Excluded),
+                skipped(".rela.misc", SkipReason::RelocationName),
+            ]
+        );
+        assert!(
+            linker
+                .output_sections
+                .keys()
+                .all(|name| name != ".rela.misc")
+        );
+        assert_eq!(
+            linker.warnings(),
+            [
+                "0.o: section .rela.misc has type 1, but its name is reserved for relocations, so it is left out"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_reject_wx() {
+        let mut object = ObjectBuilder::new();
+        let text = object.text(&[0xd503201f]);
+        object.global_func("_start", text, 0, 4);
+        object.data(".data", &[0; 8]);
+        let objects = [object.build()];
+
+        let rwx = PF_R | PF_W | PF_X;
+        let mut linker = LinkerContext::default();
+        linker
+            .segment_map(vec![SegmentSpec::new(&[".text", ".data"], rwx)])
+            .reject_wx(true);
+        link(&mut linker, &objects);
+        assert!(matches!(
+            linker.finalize(),
+            Err(LinkerError::WxSegment { vaddr: 0x40_0000 })
+        ));
+
+        // Overriding the flags of the code segment alone
+        let mut linker = LinkerContext::default();
+        linker.segment_flags_override(".text", rwx);
+        link(&mut linker, &objects);
+        linker.finalize().unwrap();
+        assert_eq!(linker.program_headers()[0].flags, rwx);
+        assert_eq!(linker.program_headers()[1].flags, PF_R | PF_W);
+        linker.reject_wx(true);
+        assert!(matches!(
+            linker.finalize(),
+            Err(LinkerError::WxSegment { .. })
+        ));
+    }
+
+    #[test]
+    fn test_duplicate_section_names_in_one_object() {
+        let mut object = ObjectBuilder::new();
+        let first = object.text(&[0xd503201f, 0xd503201f]);
+        object.global_func("_start", first, 0, 8);
+        let second = object.text(&[0xd65f03c0, 0xd65f03c0, 0xd65f03c0]);
+        let one = object.symbol("one", STB_LOCAL, STT_FUNC, first, 4, 4);
+        let two = object.symbol("two", STB_LOCAL, STT_FUNC, second, 8, 4);
+        let data = object.data(".data", &[0; 16]);
+        object.rela(data, 0, one, R_AARCH64_ABS64, 0);
+        object.rela(data, 8, two, R_AARCH64_ABS64, 0);
+        let objects = [object.build()];
+        let mut linker = LinkerContext::default();
+        link(&mut linker, &objects);
+
+        // Both inputs land in one .text, one after the other
+        let text = &linker.output_sections[".text"];
+        assert_eq!(text.header.size, 20);
+        assert_eq!(linker.input_section_offsets[&(0, first as usize)], 0);
+        assert_eq!(linker.input_section_offsets[&(0, second as usize)], 8);
+        let data = &linker.output_sections[".data"].data;
+        assert_eq!(data[..8], (text.header.addr + 4).to_le_bytes());
+        assert_eq!(data[8..], (text.header.addr + 16).to_le_bytes());
+    }
+
+    #[test]
+    fn test_entry_point() {
+        let mut object = ObjectBuilder::new();
+        let text = object.text(&[0xd503201f, 0xd65f03c0]);
+        object.global_func("_start", text, 0, 4);
+        object.global_func("reset", text, 4, 4);
+        let objects = [object.build()];
+        let e_entry = |entry: EntryPoint| {
+            let mut linker = LinkerContext::default();
+            linker.entry(entry);
+            link(&mut linker, &objects);
+            let image = linker.link_to_bytes().unwrap();
+            u64::from_le_bytes(image[24..32].try_into().unwrap())
+        };
+
+        assert_eq!(e_entry(EntryPoint::OffsetFromBase(0)), BASE_ADDR);
+        assert_eq!(e_entry(EntryPoint::OffsetFromBase(0x80)), BASE_ADDR + 0x80);
+        assert_eq!(e_entry(EntryPoint::Address(0x1234)), 0x1234);
+        let start = e_entry(EntryPoint::Default);
+        assert_eq!(e_entry(EntryPoint::Symbol("reset".to_string())), start + 4);
+    }
+
+    #[test]
+    fn test_nobits_offset_is_never_read() {
+        let mut object = ObjectBuilder::new();
+        let text = object.text(&[0xd503201f]);
+        object.global_func("_start", text, 0, 4);
+        let bss = object.nobits(".bss", 8, 0x10_0000);
+        object.global_object("buffer", bss, 0x100, 8);
+        // Point .bss far past the end of the file; only its size matters
+        object.section_mut(bss).offset = Some(0xdead_0000);
+        let objects = [object.build()];
+
+        let mut linker = LinkerContext::default();
+        link(&mut linker, &objects);
+        let bss = &linker.output_sections[".bss"];
+        assert_eq!(bss.header.size, 0x10_0000);
+        assert_eq!(
+            linker.global_symbols["buffer"].final_addr,
+            bss.header.addr + 0x100
+        );
+        linker.link_to_bytes().unwrap();
+    }
+
+    #[test]
+    fn test_sort_symbols_by_address() {
+        let mut object = ObjectBuilder::new();
+        let text = object.text(&[0xd503201f, 0xd503201f, 0xd65f03c0]);
+        object.global_func("_start", text, 8, 4);
+        object.global_func("b_first", text, 0, 4);
+        object.global_func("a_second", text, 4, 4);
+        let objects = [object.build()];
+
+        let names = |sort| {
+            let mut linker = LinkerContext::default();
+            linker.keep_symbols(true).sort_symbols(sort);
+            link(&mut linker, &objects);
+            let image = linker.link_to_bytes().unwrap();
+            let (_, symtab) = section_headers(&image)
+                .into_iter()
+                .find(|(name, _)| name == ".symtab")
+                .unwrap();
+            // Only the null symbol is local
+            assert_eq!(symtab.info, 1);
+            output_symbols(&image)
+                .into_iter()
+                .skip(1)
+                .map(|(name, _)| name)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(names(SymbolSort::Name), ["_start", "a_second", "b_first"]);
+        assert_eq!(
+            names(SymbolSort::Address),
+            ["b_first", "a_second", "_start"]
+        );
+    }
+
+    #[test]
+    fn test_gotrel64() {
+        let mut object = ObjectBuilder::new();
+        let text = object.text(&[0xd503201f]);
+        object.global_func("_start", text, 0, 4);
+        object.data(".got", &[0; 16]);
+        let data = object.data(".data", &[0; 16]);
+        let value = object.global_object("value", data, 8, 8);
+        object.rela(data, 0, value, R_AARCH64_GOTREL64, 4);
+        let objects = [object.build()];
+        let mut linker = LinkerContext::default();
+        link(&mut linker, &objects);
+
+        let got_base = linker.output_sections[".got"].header.addr;
+        assert_eq!(linker.global_symbols[GOT_SYMBOL].final_addr, got_base);
+        let expected = linker.global_symbols["value"].final_addr + 4 - got_base;
+        assert_eq!(
+            linker.output_sections[".data"].data[..8],
+            expected.to_le_bytes()
+        );
+
+        // Without a .got there is nothing to be relative to
+        let mut buffer = [0; 8];
+        let rela = Rela {
+            offset: 0,
+            info: R_AARCH64_GOTREL64 as u64,
+            addend: 0,
+        };
+        assert_eq!(
+            apply_relocations_to(&mut buffer, &[rela], |_| Some(0), 0, TargetArch::AArch64),
+            Err(RelocationError::NoGot {
+                r_type: R_AARCH64_GOTREL64,
+                offset: 0
+            })
+        );
+    }
+
+    #[test]
+    fn test_dynamic_linker() {
+        let mut object = ObjectBuilder::new();
+        let text = object.text(&[0xd503201f]);
+        object.global_func("_start", text, 0, 4);
+        object.data(".data", &[0; 8]);
+        let objects = [object.build()];
+        let mut linker = LinkerContext::default();
+        linker.dynamic_linker("/lib/ld-linux-aarch64.so.1");
+        link(&mut linker, &objects);
+        let image = linker.link_to_bytes().unwrap();
+
+        let headers = program_headers(&image);
+        assert_eq!(headers[0].p_type, PT_INTERP);
+        let interp = headers[0];
+        let path = &image[interp.offset as usize..(interp.offset + interp.filesz) as usize];
+        assert_eq!(path, b"/lib/ld-linux-aarch64.so.1\0");
+        // Loaded by the first segment
+        let first = headers.iter().find(|h| h.p_type == PT_LOAD).unwrap();
+        assert!(interp.vaddr >= first.vaddr && interp.vaddr < first.vaddr + first.filesz);
+        assert!(headers[1..].iter().all(|h| h.p_type != PT_INTERP));
+    }
+
+    #[test]
+    fn test_phases_run_in_order() {
+        let objects = [start_object()];
+        let mut linker = LinkerContext::default();
+        linker.add_file("0.o".to_string(), &objects[0]).unwrap();
+        assert!(matches!(
+            linker.apply_relocations(),
+            Err(LinkerError::InvalidPhaseOrder {
+                phase: "apply_relocations",
+                previous: "resolve_symbols"
+            })
+        ));
+        assert!(matches!(
+            linker.finalize(),
+            Err(LinkerError::InvalidPhaseOrder { .. })
+        ));
+        linker.layout_and_merge_sections().unwrap();
+        assert!(matches!(
+            linker.layout_and_merge_sections(),
+            Err(LinkerError::InvalidPhaseOrder { .. })
+        ));
+        // The layout has been made without any later input
+        let err = linker.add_file("1.o".to_string(), &objects[0]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "add_file can only run before layout_and_merge_sections"
+        );
+        let parsed = ParsedObject {
+            filename: "empty.o".to_string(),
+            header: ElfHeader::relocatable(EM_AARCH64),
+            sections: Vec::new(),
+            section_names: Vec::new(),
+            section_data: Vec::new(),
+            symbols: Vec::new(),
+            symbol_names: Vec::new(),
+        };
+        assert!(matches!(
+            linker.add_parsed(parsed),
+            Err(LinkerError::PhasePassed {
+                phase: "add_parsed",
+                ..
+            })
+        ));
+        linker.resolve_symbols().unwrap();
+        linker.apply_relocations().unwrap();
+        linker.finalize().unwrap();
+        linker.link_to_bytes().unwrap();
+
+        // A phase that fails isn't done, so the next one can't run
+        let mut linker = LinkerContext::default();
+        linker.require_defined("missing");
+        linker.add_file("0.o".to_string(), &objects[0]).unwrap();
+        linker.layout_and_merge_sections().unwrap();
+        assert!(linker.resolve_symbols().is_err());
+        assert!(matches!(
+            linker.apply_relocations(),
+            Err(LinkerError::InvalidPhaseOrder {
+                phase: "apply_relocations",
+                previous: "resolve_symbols"
+            })
+        ));
+    }
+
+    #[test]
+    fn test_trap_fill_between_code_sections() {
+        let mut first = ObjectBuilder::new();
+        let text = first.text(&[0xd65f03c0]);
+        first.global_func("_start", text, 0, 4);
+        let mut second = ObjectBuilder::new();
+        let exec = SHF_ALLOC | SHF_EXECINSTR;
+        second.section(".text", SHT_PROGBITS, exec, 16, &[0xc0, 0x03, 0x5f, 0xd6]);
+        let objects = [first.build(), second.build()];
+
+        let mut linker = LinkerContext::default();
+        linker.no_merge(true).code_fill(FillKind::Trap);
+        link(&mut linker, &objects);
+        let (_, flat) = linker.flat_image();
+        let words: Vec<u32> = flat
+            .chunks(4)
+            .map(|w| u32::from_le_bytes(w.try_into().unwrap()))
+            .collect();
+        // ret, padding up to the 16-byte aligned second .text, ret
+        assert!(words.len() > 2);
+        assert_eq!(words[0], 0xd65f03c0);
+        assert_eq!(words[words.len() - 1], 0xd65f03c0);
+        assert!(
+            words[1..words.len() - 1]
+                .iter()
+                .all(|&w| w == AARCH64_BRK_0)
+        );
+    }
+
+    #[test]
+    fn test_is_executable() {
+        let mut object = ObjectBuilder::new();
+        let text = object.text(&[0xd503201f]);
+        object.global_func("_start", text, 0, 4);
+        let data = object.data(".data", &[0; 8]);
+        object.global_object("value", data, 0, 8);
+        let objects = [object.build()];
+        let mut linker = LinkerContext::default();
+        link(&mut linker, &objects);
+        let start = linker.global_symbols["_start"].final_addr;
+        let value = linker.global_symbols["value"].final_addr;
+        assert!(!linker.is_executable(start));
+
+        linker.finalize().unwrap();
+        assert!(linker.is_executable(start));
+        assert!(!linker.is_executable(value));
+        assert!(!linker.is_executable(0));
+    }
+
+    #[test]
+    fn test_hot_and_unlikely_text_placement() {
+        let mut object = ObjectBuilder::new();
+        let exec = SHF_ALLOC | SHF_EXECINSTR;
+        let nop = &AARCH64_NOP.to_le_bytes();
+        object.section(".text.unlikely", SHT_PROGBITS, exec, 4, nop);
+        object.section(".text.unlikely.cold_fn", SHT_PROGBITS, exec, 4, nop);
+        let text = object.text(&[0xd503201f]);
+        object.global_func("_start", text, 0, 4);
+        object.section(".text.hot", SHT_PROGBITS, exec, 4, nop);
+        let objects = [object.build()];
+        let mut linker = LinkerContext::default();
+        link(&mut linker, &objects);
+
+        let addr = |name: &str| linker.output_sections[name].header.addr;
+        assert!(addr(".text.hot") < addr(".text"));
+        assert!(addr(".text") < addr(".text.unlikely"));
+        assert!(addr(".text.unlikely") < addr(".text.unlikely.cold_fn"));
+        // Adjacent, in the code segment
+        assert_eq!(addr(".text"), addr(".text.hot") + 4);
+        assert_eq!(addr(".text.unlikely"), addr(".text") + 4);
+    }
+
+    #[test]
+    fn test_add_archive_extracts_needed_members() {
+        let mut main = ObjectBuilder::new();
+        let text = main.text(&[0x94000000]); // bl foo
+        main.global_func("_start", text, 0, 4);
+        let foo = main.undefined("foo");
+        main.rela(text, 0, foo, R_AARCH64_CALL26, 0);
+
+        let member = |name: &str, calls: Option<&str>| {
+            let mut object = ObjectBuilder::new();
+            let text = object.text(&[0x94000000]);
+            object.global_func(name, text, 0, 4);
+            if let Some(callee) = calls {
+                let callee = object.undefined(callee);
+                object.rela(text, 0, callee, R_AARCH64_CALL26, 0);
+            }
+            (format!("lib.a({name}.o)"), object.build())
+        };
+        // bar is only needed once foo is pulled in
+        let members = [
+            member("unused", None),
+            member("bar", None),
+            member("foo", Some("bar")),
+        ];
+        let objects = [main.build()];
+
+        let mut linker = LinkerContext::default();
+        linker.add_archive(&members).unwrap();
+        link(&mut linker, &objects);
+        let files: Vec<&str> = linker
+            .input_files
+            .iter()
+            .map(|file| file.filename.as_str())
+            .collect();
+        assert_eq!(files, ["0.o", "lib.a(foo.o)", "lib.a(bar.o)"]);
+        assert!(linker.global_symbols.contains_key("foo"));
+        assert!(linker.global_symbols.contains_key("bar"));
+        assert!(!linker.global_symbols.contains_key("unused"));
+
+        // A member defining the entry point is extracted when no other input does
+        let mut linker = LinkerContext::default();
+        linker
+            .entry(EntryPoint::Symbol("unused".to_string()))
+            .add_archive(&members)
+            .unwrap();
+        link(&mut linker, &[]);
+        assert!(linker.global_symbols.contains_key("unused"));
+        assert!(!linker.global_symbols.contains_key("foo"));
+
+        // So is one defining a symbol kept with keep_undefined, and what it needs
+        let mut linker = LinkerContext::default();
+        linker
+            .keep_undefined("unused")
+            .add_archive(&members)
+            .unwrap();
+        link(&mut linker, &objects);
+        assert!(linker.global_symbols.contains_key("unused"));
+        assert!(linker.global_symbols.contains_key("bar"));
+
+        // Malformed members are reported even if they wouldn't be extracted
+        let bad = [("lib.a(bad.o)".to_string(), b"not an object".to_vec())];
+        let mut linker = LinkerContext::default();
+        assert!(matches!(
+            linker.add_archive(&bad),
+            Err(LinkerError::Malformed { file, .. }) if file == "lib.a(bad.o)"
+        ));
+    }
+
+    #[test]
+    fn test_add_parsed_object() {
+        let header = |sh_type, flags, size, info, entsize| SectionHeader {
+            name_offset: 0,
+            sh_type,
+            flags,
+            addr: 0,
+            offset: 0,
+            size,
+            link: 0,
+            info,
+            addralign: if sh_type == SHT_NULL { 0 } else { 8 },
+            entsize,
+        };
+        let symbol = |info, shndx, value| Symbol {
+            name_offset: 0,
+            info,
+            other: 0,
+            shndx,
+            value,
+            size: if shndx == 0 { 0 } else { 4 },
+        };
+        // bl helper; ret; helper: ret
+        let text: Vec<u8> = [0x9400_0000u32, 0xd65f03c0, 0xd65f03c0]
+            .iter()
+            .flat_map(|insn| insn.to_le_bytes())
+            .collect();
+        let mut rela = Vec::new();
+        rela.extend_from_slice(&0u64.to_le_bytes());
+        rela.extend_from_slice(&((2u64 << 32) | R_AARCH64_CALL26 as u64).to_le_bytes());
+        rela.extend_from_slice(&0i64.to_le_bytes());
+        let func = (STB_GLOBAL << 4) | STT_FUNC;
+        let object = || ParsedObject {
+            filename: "parsed.o".to_string(),
+            header: ElfHeader::relocatable(EM_AARCH64),
+            sections: vec![
+                header(SHT_NULL, 0, 0, 0, 0),
+                header(SHT_PROGBITS, SHF_ALLOC | SHF_EXECINSTR, 12, 0, 0),
+                header(SHT_RELA, SHF_INFO_LINK, 24, 1, 24),
+                header(SHT_PROGBITS, SHF_ALLOC | SHF_WRITE, 4, 0, 0),
+            ],
+            section_names: ["", ".text", ".rela.text", ".data"]
+                .map(String::from)
+                .to_vec(),
+            section_data: vec![Vec::new(), text.clone(), rela.clone(), vec![1, 2, 3, 4]],
+            symbols: vec![symbol(0, 0, 0), symbol(func, 1, 0), symbol(func, 1, 8)],
+            symbol_names: ["", "_start", "helper"].map(String::from).to_vec(),
+        };
+        let object_with_symbol_at_zero = ParsedObject {
+            filename: "bad.o".to_string(),
+            header: ElfHeader::relocatable(EM_AARCH64),
+            sections: Vec::new(),
+            section_names: Vec::new(),
+            section_data: Vec::new(),
+            symbols: vec![symbol(func, 1, 0)],
+            symbol_names: vec!["_start".to_string()],
+        };
+
+        let mut linker = LinkerContext::default();
+        linker.add_parsed(object()).unwrap();
+        linker.layout_and_merge_sections().unwrap();
+        linker.resolve_symbols().unwrap();
+        linker.apply_relocations().unwrap();
+        let image = linker.link_to_bytes().unwrap();
+
+        let text_addr = linker.output_sections[".text"].header.addr;
+        let offset = linker.vaddr_to_offset(text_addr).unwrap() as usize;
+        assert_eq!(image[offset..offset + 4], 0x9400_0002u32.to_le_bytes());
+        let data_addr = linker.output_sections[".data"].header.addr;
+        let offset = linker.vaddr_to_offset(data_addr).unwrap() as usize;
+        assert_eq!(image[offset..offset + 4], [1, 2, 3, 4]);
+
+        let mut linker = LinkerContext::default();
+        assert!(matches!(
+            linker.add_parsed(object_with_symbol_at_zero),
+            Err(LinkerError::Malformed { .. })
+        ));
+
+        // Checked the same way as objects read from files
+        let error = |object: ParsedObject| {
+            LinkerContext::default()
+                .add_parsed(object)
+                .unwrap_err()
+                .to_string()
+        };
+        let mut x86 = object();
+        x86.header.e_machine = 62;
+        assert_eq!(
+            error(x86),
+            "parsed.o: unsupported machine 62 (only AArch64 = 183 is supported)"
+        );
+        let mut big_endian = object();
+        big_endian.header.data = EI_DATA_2MSB;
+        assert!(error(big_endian).contains("unsupported ELF data encoding"));
+        let mut symbol_out_of_range = object();
+        symbol_out_of_range.symbols[2].shndx = 9;
+        assert_eq!(
+            error(symbol_out_of_range),
+            "parsed.o: malformed ELF file: symbol 2 is in section 9, which doesn't exist"
+        );
+        let mut relocation_out_of_range = object();
+        relocation_out_of_range.section_data[2][8..16]
+            .copy_from_slice(&((7u64 << 32) | R_AARCH64_CALL26 as u64).to_le_bytes());
+        assert_eq!(
+            error(relocation_out_of_range),
+            "parsed.o: malformed ELF file: relocation section .rela.text refers to symbol 7, which doesn't exist"
+        );
+    }
+
+    #[test]
+    fn test_trim_zero_tails() {
+        let mut object = ObjectBuilder::new();
+        let text = object.text(&[0xd503201f]);
+        object.global_func("_start", text, 0, 4);
+        let mut contents = vec![0; 0x2000];
+        contents[..4].copy_from_slice(&[1, 2, 3, 4]);
+        let data = object.data(".data", &contents);
+        object.global_object("table", data, 0x100, 8);
+        object.nobits(".bss", 8, 16);
+        let objects = [object.build()];
+
+        let link_with = |trim| {
+            let mut linker = LinkerContext::default();
+            linker.trim_zero_tails(trim);
+            link(&mut linker, &objects);
+            linker.finalize().unwrap();
+            linker
+        };
+        let plain = link_with(false);
+        let trimmed = link_with(true);
+        let plain_image = plain.link_to_bytes().unwrap();
+        let image = trimmed.link_to_bytes().unwrap();
+        assert!(image.len() + 0x1000 < plain_image.len());
+
+        let data = &trimmed.output_sections[".data"];
+        let bss = &trimmed.output_sections[".bss"];
+        assert_eq!(data.header.size, 8);
+        assert_eq!(bss.header.addr, data.header.addr + 8);
+        assert_eq!(
+            bss.header.addr + bss.header.size,
+            plain.output_sections[".bss"].header.addr + 16
+        );
+        // Same addresses and memory image, smaller file image
+        let load = |linker: &LinkerContext| linker.program_headers()[1];
+        assert_eq!(load(&trimmed).memsz, load(&plain).memsz);
+        assert!(load(&trimmed).filesz < load(&plain).filesz);
+        assert_eq!(
+            trimmed.global_symbols["table"].final_addr,
+            plain.global_symbols["table"].final_addr
+        );
+        assert_eq!(trimmed.symbol_location("table").unwrap().section, ".bss");
+    }
+
+    #[test]
+    fn test_trim_zero_tails_of_two_segments() {
+        let mut object = ObjectBuilder::new();
+        let text = object.text(&[0xd503201f]);
+        object.global_func("_start", text, 0, 4);
+        let data = object.data(".data", &[1, 0, 0, 0, 0, 0, 0, 0]);
+        object.global_object("first", data, 4, 4);
+        let sdata = object.data(".sdata", &[2, 0, 0, 0, 0, 0, 0, 0]);
+        object.global_object("second", sdata, 4, 4);
+        let objects = [object.build()];
+
+        let mut linker = LinkerContext::default();
+        linker.trim_zero_tails(true).segment_map(vec![
+            SegmentSpec::new(&[".text"], PF_R | PF_X),
+            SegmentSpec::new(&[".data"], PF_R | PF_W),
+            SegmentSpec::new(&[".sdata"], PF_R | PF_W),
+        ]);
+        link(&mut linker, &objects);
+        linker.finalize().unwrap();
+
+        // Each tail gets a NOBITS section of its own, at the addresses it had
+        for (name, symbol) in [(".data", "first"), (".sdata", "second")] {
+            let section = &linker.output_sections[name];
+            assert_eq!(section.header.size, 1);
+            let location = linker.symbol_location(symbol).unwrap();
+            let bss = &linker.output_sections[&location.section];
+            assert_ne!(bss.name, name);
+            assert_eq!(bss.header.sh_type, SHT_NOBITS);
+            assert_eq!(bss.header.addr, section.header.addr + 1);
+            assert_eq!(bss.header.size, 7);
+        }
+        assert!(linker.output_sections.contains_key(".bss"));
+        assert!(linker.output_sections.contains_key(".bss.1"));
+    }
+
+    #[test]
+    fn test_relocation_free_object_links() {
+        // Only `_start: b .`, no relocations and no other symbols
+        let objects = [start_object()];
+        let mut linker = LinkerContext::default();
+        link(&mut linker, &objects);
+        linker.finalize().unwrap();
+        let image = linker.link_to_bytes().unwrap();
+
+        let (_, header) = parse_elf_header(&image).unwrap();
+        assert_eq!(header.e_type, ET_EXEC);
+        assert_eq!(header.e_entry, linker.global_symbols["_start"].final_addr);
+        assert!(program_headers(&image).iter().any(|h| h.p_type == PT_LOAD
+            && h.flags & PF_X != 0
+            && (h.vaddr..h.vaddr + h.memsz).contains(&header.e_entry)));
+        let offset = linker.vaddr_to_offset(header.e_entry).unwrap() as usize;
+        assert_eq!(image[offset..offset + 4], 0x1400_0000u32.to_le_bytes());
+
+        // A data-only object without a symbol table links alongside it
+        let mut blob = ObjectBuilder::new();
+        blob.data(".data", &[1, 2, 3, 4]);
+        blob.patch_section(".symtab", |symtab| symtab.sh_type = SHT_PROGBITS);
+        let objects = [start_object(), blob.build()];
+        let mut linker = LinkerContext::default();
+        link(&mut linker, &objects);
+        assert!(linker.input_files[1].symbols.is_empty());
+        assert_eq!(linker.output_sections[".data"].data, [1, 2, 3, 4]);
+        linker.link_to_bytes().unwrap();
+    }
+
+    #[test]
+    fn test_icf_folds_identical_functions() {
+        // `bl <target>; ret`
+        let caller = |name: &str, target: &str| {
+            let mut obj = ObjectBuilder::new();
+            let text = obj.text(&[0x9400_0000, 0xd65f_03c0]);
+            obj.global_func(name, text, 0, 8);
+            let target = obj.undefined(target);
+            obj.rela(text, 0, target, R_AARCH64_CALL26, 0);
+            obj.build()
+        };
+        let mut callees = ObjectBuilder::new();
+        let text = callees.text(&[0xd280_0020, 0xd65f_03c0, 0xd280_0040, 0xd65f_03c0]);
+        callees.global_func("helper", text, 0, 8);
+        callees.global_func("other", text, 8, 8);
+        let objects = [
+            start_object(),
+            callees.build(),
+            caller("first", "helper"),
+            caller("second", "helper"),
+            caller("third", "other"),
+        ];
+
+        let mut linker = LinkerContext::default();
+        linker.icf(true);
+        link(&mut linker, &objects);
+        let addr = |name: &str| linker.global_symbols[name].final_addr;
+        assert_eq!(addr("first"), addr("second"));
+        assert_ne!(addr("first"), addr("third"));
+        // _start, the callees and two callers
+        assert_eq!(linker.output_sections[".text"].data.len(), 4 + 16 + 8 + 8);
+        let offset = (addr("first") - linker.output_sections[".text"].header.addr) as usize;
+        let call = &linker.output_sections[".text"].data[offset..offset + 4];
+        let expected = 0x9400_0000 | branch26_imm(addr("helper"), 0, addr("first")).unwrap();
+        assert_eq!(call, expected.to_le_bytes());
+
+        let mut linker = LinkerContext::default();
+        link(&mut linker, &objects);
+        assert_ne!(
+            linker.global_symbols["first"].final_addr,
+            linker.global_symbols["second"].final_addr
+        );
+    }
+
+    #[test]
+    fn test_relocation_stats() {
+        let mut obj = ObjectBuilder::new();
+        // bl helper; adrp x0, helper; bl helper; helper: ret
+        let text = obj.text(&[0x9400_0000, 0x9000_0000, 0x9400_0000, 0xd65f_03c0]);
+        obj.global_func("_start", text, 0, 12);
+        let helper = obj.global_func("helper", text, 12, 4);
+        obj.rela(text, 0, helper, R_AARCH64_CALL26, 0);
+        // R_AARCH64_ADR_PREL_PG_HI21 isn't supported
+        obj.rela(text, 4, helper, 275, 0);
+        obj.rela(text, 8, helper, R_AARCH64_CALL26, 0);
+        let data = obj.data(".data", &[0; 16]);
+        obj.rela(data, 0, helper, R_AARCH64_ABS64, 0);
+        let missing = obj.undefined("missing");
+        obj.rela(data, 8, missing, R_AARCH64_ABS64, 0);
+        let objects = [obj.build()];
+
+        let mut linker = LinkerContext::default();
+        link(&mut linker, &objects);
+        let stats = linker.relocation_stats();
+        assert_eq!(
+            stats.applied,
+            BTreeMap::from([(R_AARCH64_CALL26, 2), (R_AARCH64_ABS64, 1)])
+        );
+        assert_eq!(stats.unresolved, BTreeMap::from([(R_AARCH64_ABS64, 1)]));
+        assert_eq!(stats.unsupported, BTreeMap::from([(275, 1)]));
+        assert_eq!(
+            stats.to_string(),
+            "relocations: 3 applied, 1 unresolved, 1 unsupported\n  \
+             type 257: 1 applied, 1 unresolved, 0 unsupported\n  \
+             type 275: 0 applied, 0 unresolved, 1 unsupported\n  \
+             type 283: 2 applied, 0 unresolved, 0 unsupported"
+        );
+    }
+
+    #[test]
+    fn test_set_entry_address_after_finalize() {
+        let objects = [start_object()];
+        let mut linker = LinkerContext::default();
+        link(&mut linker, &objects);
+        linker.finalize().unwrap();
+        let path = std::env::temp_dir().join(format!("elkr-entry-{}", std::process::id()));
+        let e_entry = |linker: &LinkerContext| {
+            linker.write_executable(path.to_str().unwrap()).unwrap();
+            let image = fs::read(&path).unwrap();
+            parse_elf_header(&image).unwrap().1.e_entry
+        };
+        let start = linker.global_symbols["_start"].final_addr;
+        assert_eq!(e_entry(&linker), start);
+
+        let before = linker.link_to_bytes().unwrap();
+        linker.set_entry_address(start + 0x40);
+        assert_eq!(e_entry(&linker), start + 0x40);
+        // Only e_entry changes
+        let after = linker.link_to_bytes().unwrap();
+        assert_eq!(before[..24], after[..24]);
+        assert_eq!(before[32..], after[32..]);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_relocation_section_info_out_of_range() {
+        let mut obj = ObjectBuilder::new();
+        let text = obj.text(&[0x9400_0000]);
+        let start = obj.global_func("_start", text, 0, 4);
+        obj.rela(text, 0, start, R_AARCH64_CALL26, 0);
+        obj.patch_section(".rela.text", |rela| rela.info = 99);
+        let obj = obj.build();
+
+        let err = LinkerContext::default()
+            .add_file("bad.o".to_string(), &obj)
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "bad.o: malformed ELF file: relocation section .rela.text applies to section 99, which doesn't exist"
+        );
+
+        // A symbol index past the symbol table is caught up front, too
+        let mut obj = ObjectBuilder::new();
+        let text = obj.text(&[0x9400_0000]);
+        obj.global_func("_start", text, 0, 4);
+        obj.rela(text, 0, 42, R_AARCH64_CALL26, 0);
+        let obj = obj.build();
+        let err = LinkerContext::default()
+            .add_file("bad.o".to_string(), &obj)
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "bad.o: malformed ELF file: relocation section .rela.text refers to symbol 42, which doesn't exist"
+        );
+    }
+
+    #[test]
+    fn test_resolution_order() {
+        let handler = || {
+            let mut obj = ObjectBuilder::new();
+            let text = obj.text(&[0xd65f_03c0]);
+            obj.global_func("handler", text, 0, 4);
+            obj.build()
+        };
+        let objects = [start_object(), handler(), handler()];
+        let resolve = |order: ResolutionOrder| {
+            let mut linker = LinkerContext::default();
+            linker.resolution_order(order);
+            for (i, obj) in objects.iter().enumerate() {
+                linker.add_file(format!("{i}.o"), obj).unwrap();
+            }
+            linker.layout_and_merge_sections().unwrap();
+            linker.resolve_symbols().map(|()| linker)
+        };
+        let section_start = |linker: &LinkerContext, file_idx| {
+            let key = (file_idx, 1);
+            linker.output_sections[&linker.input_section_outputs[&key]]
+                .header
+                .addr
+                + linker.input_section_offsets[&key]
+        };
+
+        let linker = resolve(ResolutionOrder::First).unwrap();
+        assert_eq!(
+            linker.global_symbols["handler"].final_addr,
+            section_start(&linker, 1)
+        );
+        assert!(linker.warnings().is_empty());
+
+        let linker = resolve(ResolutionOrder::Last).unwrap();
+        assert_eq!(
+            linker.global_symbols["handler"].final_addr,
+            section_start(&linker, 2)
+        );
+        assert_eq!(
+            linker.warnings(),
+            ["2.o: `handler` overrides the definition in 1.o"]
+        );
+
+        let err = resolve(ResolutionOrder::ErrorOnConflict).err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "2.o: duplicate definition of `handler`, first defined in 1.o"
+        );
+    }
+
+    #[test]
+    fn test_section_header_cross_references() {
+        let mut object = ObjectBuilder::new();
+        let text = object.text(&[0x1400_0000]);
+        let start = object.global_func("_start", text, 0, 4);
+        let data = object.data(".data", &[0; 8]);
+        object.global_object("value", data, 0, 8);
+        object.rela(data, 0, start, R_AARCH64_ABS64, 0);
+        object.section(".comment", SHT_PROGBITS, 0, 1, b"elkr\0");
+        let objects = [object.build()];
+        let mut linker = LinkerContext::default();
+        linker.keep_symbols(true).keep_sections(true);
+        link(&mut linker, &objects);
+        let image = linker.link_to_bytes().unwrap();
+
+        // Read back the way readelf does: through e_shstrndx and the sh_link fields
+        let (_, header) = parse_elf_header(&image).unwrap();
+        let (_, sections) = parse_section_header_table(&image, &header).unwrap();
+        let contents = |section: &SectionHeader| section_data(&image, section).unwrap();
+        let shstrtab = contents(&sections[header.e_shstrndx as usize]);
+        let name = |section| get_section_name(shstrtab, section).unwrap();
+        assert_eq!(sections[0].sh_type, SHT_NULL);
+        assert_eq!(name(&sections[0]), "");
+        assert_eq!(name(&sections[header.e_shstrndx as usize]), ".shstrtab");
+
+        let symtab = sections.iter().find(|s| s.sh_type == SHT_SYMTAB).unwrap();
+        let strtab = &sections[symtab.link as usize];
+        assert_eq!(strtab.sh_type, SHT_STRTAB);
+        assert_eq!(name(strtab), ".strtab");
+        let (_, symbols) = parse_symbol_table(&image, symtab).unwrap();
+        let value = symbols
+            .iter()
+            .find(|s| get_symbol_name(contents(strtab), s) == Some("value"))
+            .unwrap();
+        assert_eq!(name(&sections[value.shndx as usize]), ".data");
+
+        // Relocations are applied, so nothing refers back into the inputs
+        assert!(sections.iter().all(|s| !is_relocation_section(s)));
+        assert!(
+            sections
+                .iter()
+                .filter(|s| s.sh_type != SHT_SYMTAB)
+                .all(|s| s.link == 0 && s.info == 0)
+        );
+    }
+
+    #[test]
+    fn test_init_array_priority_order() {
+        let constructors = |name: &str, pointer: u8| {
+            let mut obj = ObjectBuilder::new();
+            let write = SHF_ALLOC | SHF_WRITE;
+            obj.section(name, SHT_INIT_ARRAY, write, 8, &[pointer; 8]);
+            obj.build()
+        };
+        let objects = [
+            start_object(),
+            constructors(".init_array", 1),
+            constructors(".init_array.00200", 2),
+            constructors(".init_array.00100", 3),
+        ];
+        let mut linker = LinkerContext::default();
+        link(&mut linker, &objects);
+
+        let init_array = &linker.output_sections[".init_array"];
+        let pointers: Vec<u8> = init_array.data.chunks(8).map(|p| p[0]).collect();
+        assert_eq!(pointers, [3, 2, 1]);
+        assert_eq!(linker.input_section_offsets[&(3, 1)], 0);
+        assert!(
+            !linker
+                .output_sections
+                .keys()
+                .any(|name| name.starts_with(".init_array."))
+        );
+
+        assert_eq!(
+            init_priority(".init_array.65535"),
+            Some((".init_array", 65535))
+        );
+        assert_eq!(
+            init_priority(".fini_array.00101"),
+            Some((".fini_array", 101))
+        );
+        assert_eq!(init_priority(".init_array"), None);
+        assert_eq!(init_priority(".init_array."), None);
+        assert_eq!(init_priority(".init_array.+1"), None);
+        assert_eq!(init_priority(".init_arrayx.1"), None);
+    }
+
+    #[test]
+    fn test_verify_output() {
+        let mut object = ObjectBuilder::new();
+        let text = object.text(&[0x1400_0000]);
+        object.global_func("_start", text, 0, 4);
+        object.data(".data", &[1; 8]);
+        let objects = [object.build()];
+        let mut linker = LinkerContext::default();
+        link(&mut linker, &objects);
+        let image = linker.link_to_bytes().unwrap();
+        linker.verify_output(&image).unwrap();
+
+        let reason = |image: &[u8]| match linker.verify_output(image) {
+            Err(LinkerError::InvalidOutput { reason }) => reason,
+            other => panic!("expected an invalid output, got {other:?}"),
+        };
+        let phdr = |i: usize| ELF_HEADER_SIZE as usize + i * PROGRAM_HEADER_SIZE as usize;
+        let (code, data) = (phdr(0), phdr(1));
+
+        let mut truncated = image.clone();
+        truncated.truncate(image.len() - 1);
+        assert!(reason(&truncated).contains("past the end of the file"));
+
+        let mut misaligned = image.clone();
+        let offset = u64::from_le_bytes(image[data + 8..data + 16].try_into().unwrap());
+        misaligned[data + 8..data + 16].copy_from_slice(&(offset - 8).to_le_bytes());
+        assert!(reason(&misaligned).contains("congruent"));
+
+        let mut overlapping = image.clone();
+        overlapping[data + 16..data + 24].copy_from_slice(&BASE_ADDR.to_le_bytes());
+        overlapping[data + 8..data + 16].copy_from_slice(&0u64.to_le_bytes());
+        assert!(reason(&overlapping).contains("overlap"));
+
+        let mut not_executable = image.clone();
+        not_executable[code + 4..code + 8].copy_from_slice(&PF_R.to_le_bytes());
+        assert!(reason(&not_executable).contains("isn't in an executable segment"));
+    }
+
+    #[test]
+    fn test_local_symbols_get_final_addresses() {
+        let mut first = ObjectBuilder::new();
+        let text = first.text(&[0x1400_0000]);
+        first.global_func("_start", text, 0, 4);
+        let mut second = ObjectBuilder::new();
+        let text = second.text(&[0xd503_201f, 0xd65f_03c0]);
+        second.symbol("helper", STB_LOCAL, STT_FUNC, text, 4, 4);
+        second.section_symbol(text);
+        let objects = [first.build(), second.build()];
+
+        let mut linker = LinkerContext::default();
+        linker.keep_symbols(true);
+        link(&mut linker, &objects);
+        let image = linker.link_to_bytes().unwrap();
+
+        // .text of the second file follows the first's 4 bytes
+        let text = &linker.output_sections[".text"];
+        assert_eq!(linker.input_section_offsets[&(1, 1)], 4);
+        let symbols = output_symbols(&image);
+        let (_, helper) = symbols.iter().find(|(name, _)| name == "helper").unwrap();
+        assert_eq!(helper.value, text.header.addr + 4 + 4);
+        assert_eq!(helper.get_bind(), STB_LOCAL);
+        assert_eq!(helper.get_type(), STT_FUNC);
+        let (_, text_header) = &section_headers(&image)[helper.shndx as usize];
+        assert_eq!(text_header.addr, text.header.addr);
+        // Section symbols stay out, and locals come before the globals
+        assert_eq!(symbols.len(), 3);
+        assert_eq!(symbols[1].0, "helper");
+    }
+
+    #[test]
+    fn test_warning_policy() {
+        // The two .data inputs disagree on whether the section is writable
+        let mut read_only = ObjectBuilder::new();
+        read_only.section(".data", SHT_PROGBITS, SHF_ALLOC, 8, &[1; 8]);
+        let mut writable = ObjectBuilder::new();
+        writable.data(".data", &[2; 8]);
+        let objects = [start_object(), read_only.build(), writable.build()];
+        let add_files = |policy| {
+            let mut linker = LinkerContext::default();
+            linker.warning_policy(policy);
+            for (i, obj) in objects.iter().enumerate() {
+                linker.add_file(format!("{i}.o"), obj).unwrap();
+            }
+            linker
+        };
+
+        let mut linker = add_files(WarningPolicy::Warn);
+        linker.layout_and_merge_sections().unwrap();
+        assert_eq!(linker.warnings().len(), 1);
+
+        let mut linker = add_files(WarningPolicy::Silent);
+        linker.layout_and_merge_sections().unwrap();
+        linker.resolve_symbols().unwrap();
+        linker.apply_relocations().unwrap();
+        linker.link_to_bytes().unwrap();
+        assert!(linker.warnings().is_empty());
+
+        let mut linker = add_files(WarningPolicy::Fatal);
+        let err = linker.layout_and_merge_sections().unwrap_err();
+        assert!(matches!(err, LinkerError::FatalWarning { .. }));
+        assert!(
+            err.to_string()
+                .starts_with("warning treated as an error: 2.o: section .data has type 1")
+        );
+    }
+
+    #[test]
+    fn test_extended_symbol_section_index() {
+        let object = |with_table: bool, index: Option<u32>| {
+            let mut obj = ObjectBuilder::new();
+            let text = obj.text(&[0x14000000]); // b .
+            obj.data(".rodata", &[0; 8]);
+            let data = obj.data(".data", &[0; 8]);
+            obj.global_func("_start", text, 0, 4);
+            obj.global_object("value", SHN_XINDEX, 4, 4);
+            if with_table {
+                // One entry per symbol (null, _start, value), linked to .symtab right after it
+                let indices: Vec<u8> = [0, 0, index.unwrap_or(data as u32)]
+                    .iter()
+                    .flat_map(|index| index.to_le_bytes())
+                    .collect();
+                let shndx = obj.section(".symtab_shndx", SHT_SYMTAB_SHNDX, 0, 4, &indices);
+                obj.section_mut(shndx).link = shndx as u32 + 1;
+            }
+            obj.build()
+        };
+
+        let objects = [object(true, None)];
+        let mut linker = LinkerContext::default();
+        link(&mut linker, &objects);
+        let value = &linker.global_symbols["value"];
+        assert_eq!(value.section.as_deref(), Some(".data"));
+        assert!(linker.skipped_sections.is_empty());
+
+        let obj = object(false, None);
+        let err = LinkerContext::default()
+            .add_file("xindex.o".to_string(), &obj)
+            .unwrap_err();
+        assert!(err.to_string().contains("SHT_SYMTAB_SHNDX"), "{err}");
+
+        // The reserved range can't be told apart from SHN_ABS and the like
+        let obj = object(true, Some(0xff05));
+        let err = LinkerContext::default()
+            .add_file("xindex.o".to_string(), &obj)
+            .unwrap_err();
+        assert!(
+            err.to_string().contains(
+                "symbol 2 is in section 65285, but section indices of 0xff00 and up aren't supported"
+            ),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn test_align_entry() {
+        // _start sits 4 bytes into the second .text input, after a 12-byte helper
+        let mut first = ObjectBuilder::new();
+        let text = first.text(&[0xd503201f, 0xd503201f, 0xd65f03c0]); // nop; nop; ret
+        first.global_func("helper", text, 0, 12);
+        let mut second = ObjectBuilder::new();
+        let text = second.text(&[0xd503201f, 0x94000000]); // nop; bl helper
+        second.global_func("_start", text, 4, 4);
+        let helper = second.undefined("helper");
+        second.rela(text, 4, helper, R_AARCH64_CALL26, 0);
+        let objects = [first.build(), second.build()];
+
+        let mut linker = LinkerContext::default();
+        linker.align_entry(64);
+        link(&mut linker, &objects);
+        linker.finalize().unwrap();
+        let image = linker.link_to_bytes().unwrap();
+
+        let (_, header) = parse_elf_header(&image).unwrap();
+        assert_eq!(header.e_entry % 64, 0);
+        assert_eq!(header.e_entry, linker.global_symbols["_start"].final_addr);
+        // The call still reaches helper across the padding
+        let offset = linker.vaddr_to_offset(header.e_entry).unwrap() as usize;
+        let insn = u32::from_le_bytes(image[offset..offset + 4].try_into().unwrap());
+        let helper = linker.global_symbols["helper"].final_addr;
+        let imm = branch26_imm(helper, 0, header.e_entry).unwrap();
+        assert_eq!(insn, 0x9400_0000 | imm);
+
+        // With a code fill, the padding before the entry runs it
+        let mut linker = LinkerContext::default();
+        linker.align_entry(64).code_fill(FillKind::Trap);
+        link(&mut linker, &objects);
+        let image = linker.link_to_bytes().unwrap();
+        let entry = linker.global_symbols["_start"].final_addr - 4;
+        let helper_end = linker.global_symbols["helper"].final_addr + 12;
+        let start = linker.vaddr_to_offset(helper_end).unwrap() as usize;
+        let end = linker.vaddr_to_offset(entry).unwrap() as usize;
+        assert!(end > start);
+        for insn in image[start..end].chunks(4) {
+            assert_eq!(insn, AARCH64_BRK_0.to_le_bytes());
+        }
+
+        let mut linker = LinkerContext::default();
+        linker.align_entry(48);
+        for (idx, object) in objects.iter().enumerate() {
+            linker.add_file(format!("{idx}.o"), object).unwrap();
+        }
+        assert!(matches!(
+            linker.layout_and_merge_sections(),
+            Err(LinkerError::BadAlignment { align: 48, .. })
+        ));
+    }
+
+    #[test]
+    fn test_align_entry_follows_resolution_order() {
+        // Both inputs define _start; the second one wins with ResolutionOrder::Last
+        let mut first = ObjectBuilder::new();
+        let text = first.text(&[0xd503201f, 0xd65f03c0]);
+        first.global_func("_start", text, 4, 4);
+        let mut second = ObjectBuilder::new();
+        let text = second.text(&[0xd503201f, 0xd503201f, 0xd65f03c0]);
+        second.global_func("_start", text, 8, 4);
+        let objects = [first.build(), second.build()];
+
+        let mut linker = LinkerContext::default();
+        linker
+            .align_entry(64)
+            .resolution_order(ResolutionOrder::Last);
+        link(&mut linker, &objects);
+        let image = linker.link_to_bytes().unwrap();
+        let (_, header) = parse_elf_header(&image).unwrap();
+        assert_eq!(header.e_entry, linker.global_symbols["_start"].final_addr);
+        assert_eq!(header.e_entry % 64, 0);
+        assert_eq!(
+            linker.input_section_offsets[&(1, text as usize)] % 64,
+            64 - 8
+        );
+    }
+
+    #[test]
+    fn test_inputs_aligned_within_merged_section() {
+        let mut first = ObjectBuilder::new();
+        let text = first.text(&[0xd65f03c0]); // ret
+        first.global_func("_start", text, 0, 4);
+        first.section(".data", SHT_PROGBITS, SHF_ALLOC | SHF_WRITE, 4, &[1; 4]);
+        let mut second = ObjectBuilder::new();
+        let text = second.section(
+            ".text",
+            SHT_PROGBITS,
+            SHF_ALLOC | SHF_EXECINSTR,
+            16,
+            &0xd65f03c0u32.to_le_bytes(),
+        );
+        second.global_func("aligned_func", text, 0, 4);
+        let data = second.section(".data", SHT_PROGBITS, SHF_ALLOC | SHF_WRITE, 64, &[2; 8]);
+        second.global_object("table", data, 0, 8);
+        let objects = [first.build(), second.build()];
+
+        let mut linker = LinkerContext::default();
+        linker.code_fill(FillKind::Nop);
+        link(&mut linker, &objects);
+        let image = linker.link_to_bytes().unwrap();
+
+        // The strict input lands on its own alignment after the 4-byte one
+        assert_eq!(linker.input_section_offsets[&(1, data as usize)], 64);
+        let table = linker.global_symbols["table"].final_addr;
+        assert_eq!(table % 64, 0);
+        assert_eq!(table, linker.output_sections[".data"].header.addr + 64);
+        let func = linker.global_symbols["aligned_func"].final_addr;
+        assert_eq!(func % 16, 0);
+        // The padding between the two code inputs is filled with nops
+        let start = linker.vaddr_to_offset(func - 12).unwrap() as usize;
+        for insn in image[start..start + 12].chunks(4) {
+            assert_eq!(insn, AARCH64_NOP.to_le_bytes());
+        }
+    }
+
+    #[test]
+    fn test_export_dynamic() {
+        let mut obj = ObjectBuilder::new();
+        let text = obj.text(&[0x14000000, 0xd65f03c0]); // b .; ret
+        obj.global_func("_start", text, 0, 4);
+        obj.global_func("exported", text, 4, 4);
+        let data = obj.data(".data", &[0; 8]);
+        obj.global_object("counter", data, 0, 8);
+        let objects = [obj.build()];
+
+        let mut linker = LinkerContext::default();
+        linker.export_dynamic(true).keep_symbols(true);
+        link(&mut linker, &objects);
+        linker.finalize().unwrap();
+        let image = linker.link_to_bytes().unwrap();
+        linker.verify_output(&image).unwrap();
+
+        let sections = section_headers(&image);
+        let (_, dynsym) = sections.iter().find(|(name, _)| name == ".dynsym").unwrap();
+        assert_eq!(dynsym.sh_type, SHT_DYNSYM);
+        let (dynstr_name, dynstr) = &sections[dynsym.link as usize];
+        assert_eq!(dynstr_name, ".dynstr");
+        let dynstr_data = section_data(&image, dynstr).unwrap();
+        let (_, symbols) = parse_symbol_table(&image, dynsym).unwrap();
+        assert!(symbols[0].is_null());
+        for name in ["_start", "exported", "counter"] {
+            let symbol = symbols
+                .iter()
+                .find(|s| get_symbol_name(dynstr_data, s) == Some(name))
+                .unwrap();
+            assert_eq!(
+                symbol.value, linker.global_symbols[name].final_addr,
+                "{name}"
+            );
+            assert_eq!(
+                sections[symbol.shndx as usize].0,
+                if name == "counter" { ".data" } else { ".text" }
+            );
+        }
+
+        // PT_DYNAMIC leads to the same tables
+        let dynamic = program_headers(&image)
+            .into_iter()
+            .find(|h| h.p_type == PT_DYNAMIC)
+            .unwrap();
+        let offset = dynamic.offset as usize;
+        let entries: Vec<(u64, u64)> = image[offset..offset + dynamic.filesz as usize]
+            .chunks(16)
+            .map(|entry| {
+                let word = |at: usize| u64::from_le_bytes(entry[at..at + 8].try_into().unwrap());
+                (word(0), word(8))
+            })
+            .collect();
+        assert!(entries.contains(&(DT_SYMTAB, dynsym.addr)));
+        assert!(entries.contains(&(DT_STRTAB, dynstr.addr)));
+        let (_, hash) = sections.iter().find(|(name, _)| name == ".hash").unwrap();
+        assert!(entries.contains(&(DT_HASH, hash.addr)));
+        assert_eq!(entries.last(), Some(&(DT_NULL, 0)));
+    }
+
+    #[test]
+    fn test_export_dynamic_skips_unloaded_definition() {
+        // `info` resolves to the first definition, in a kept non-allocatable section,
+        // though the second input defines it in .data
+        let mut first = ObjectBuilder::new();
+        let text = first.text(&[0x14000000]); // b .
+        first.global_func("_start", text, 0, 4);
+        let comment = first.section(".comment", SHT_PROGBITS, 0, 1, b"info\0");
+        first.global_object("info", comment, 0, 5);
+        let mut second = ObjectBuilder::new();
+        let data = second.data(".data", &[0; 8]);
+        second.global_object("info", data, 0, 8);
+        let objects = [first.build(), second.build()];
+
+        let mut linker = LinkerContext::default();
+        linker
+            .export_dynamic(true)
+            .keep_sections(true)
+            .keep_symbols(true);
+        link(&mut linker, &objects);
+        assert_eq!(
+            linker.global_symbols["info"].section.as_deref(),
+            Some(".comment")
+        );
+        let image = linker.link_to_bytes().unwrap();
+
+        let sections = section_headers(&image);
+        let (_, dynsym) = sections.iter().find(|(name, _)| name == ".dynsym").unwrap();
+        let dynstr_data = section_data(&image, &sections[dynsym.link as usize].1).unwrap();
+        let (_, symbols) = parse_symbol_table(&image, dynsym).unwrap();
+        let names: Vec<_> = symbols
+            .iter()
+            .skip(1)
+            .map(|s| get_symbol_name(dynstr_data, s).unwrap())
+            .collect();
+        assert_eq!(names, ["_start"]);
+    }
+
+    #[test]
+    fn test_warn_input_section_address() {
+        let mut obj = ObjectBuilder::new();
+        let text = obj.text(&[0x14000000]); // b .
+        obj.section_mut(text).addr = 0x8000;
+        obj.global_func("_start", text, 0, 4);
+        let objects = [obj.build()];
+
+        let mut linker = LinkerContext::default();
+        link(&mut linker, &objects);
+        assert_eq!(
+            linker.warnings(),
+            ["0.o: section .text has address 0x8000, which is ignored"]
+        );
+        // The section is placed like any other
+        assert_ne!(linker.global_symbols["_start"].final_addr, 0x8000);
+
+        let objects = [start_object()];
+        let mut linker = LinkerContext::default();
+        link(&mut linker, &objects);
+        assert!(linker.warnings().is_empty());
+    }
+
+    #[test]
+    fn test_conditional_branch_relocations() {
+        // b.eq target; tbz w0, #3, target, reaching an external label in the next file
+        let mut caller = ObjectBuilder::new();
+        let text = caller.text(&[0x5400_0000, 0x3618_0000]);
+        caller.global_func("_start", text, 0, 8);
+        let target = caller.undefined("target");
+        caller.rela(text, 0, target, R_AARCH64_CONDBR19, 0);
+        caller.rela(text, 4, target, R_AARCH64_TSTBR14, 0);
+        let mut callee = ObjectBuilder::new();
+        let text = callee.text(&[0xd65f03c0]); // ret
+        callee.global_func("target", text, 0, 4);
+        let objects = [caller.build(), callee.build()];
+
+        let mut linker = LinkerContext::default();
+        link(&mut linker, &objects);
+        let code = &linker.output_sections[".text"].data;
+        let word = |at: usize| u32::from_le_bytes(code[at..at + 4].try_into().unwrap());
+        // target directly follows the 8 bytes of _start
+        assert_eq!(word(0), 0x5400_0000 | (2 << 5));
+        assert_eq!(word(4), 0x3618_0000 | (1 << 5));
+
+        // Backward branches wrap within the field, and the ranges are ±1MB and ±32KB
+        let p = 0x10_0000;
+        assert_eq!(short_branch_imm(p - 4, 0, p, 19), Some(0x7_FFFF));
+        assert_eq!(short_branch_imm(p - (1 << 20), 0, p, 19), Some(1 << 18));
+        assert_eq!(short_branch_imm(p + (1 << 20), 0, p, 19), None);
+        assert_eq!(short_branch_imm(p + (1 << 15) - 4, 0, p, 14), Some(0x1FFF));
+        let mut data = [0; 4];
+        assert_eq!(
+            relocate(&mut data, 0, R_AARCH64_TSTBR14, p + (1 << 15), 0, p, None),
+            Err(RelocationError::OutOfRange {
+                r_type: R_AARCH64_TSTBR14,
+                offset: 0
+            })
+        );
+
+        // REL addends are read back out of the immediates
+        assert_eq!(
+            implicit_addend(R_AARCH64_CONDBR19, &word(0).to_le_bytes()),
+            Some(8)
+        );
+        let backward: u32 = 0x3618_0000 | (0x3FFF << 5);
+        assert_eq!(
+            implicit_addend(R_AARCH64_TSTBR14, &backward.to_le_bytes()),
+            Some(-4)
+        );
+    }
+
+    #[test]
+    fn test_write_executable_io_error() {
+        let objects = [start_object()];
+        let mut linker = LinkerContext::default();
+        link(&mut linker, &objects);
+        linker.finalize().unwrap();
+
+        let dir = std::env::temp_dir().join(format!("elkr-missing-{}", std::process::id()));
+        let path = dir.join("out");
+        let path = path.to_str().unwrap();
+        let err = linker.write_executable(path).unwrap_err();
+        let LinkerError::Io { path: err_path, .. } = &err else {
+            panic!("expected an Io error, got {err:?}");
+        };
+        assert_eq!(err_path, path);
+        assert_eq!(err.to_string(), format!("can't write {path}"));
+        let source = std::error::Error::source(&err)
+            .and_then(|source| source.downcast_ref::<io::Error>())
+            .unwrap();
+        assert_eq!(source.kind(), io::ErrorKind::NotFound);
+        // So do the other writers
+        for err in [
+            linker.write_binary(path),
+            linker.write_ihex(path),
+            linker.write_srec(path),
+            linker.write_map_json(path),
+        ] {
+            let err = err.unwrap_err();
+            assert!(matches!(&err, LinkerError::Io { path: err_path, .. } if err_path == path));
+        }
+
+        // Errors of the link itself pass through as they are, with nothing underneath
+        let err = LinkerContext::default()
+            .add_file("garbage.o".to_string(), b"not an object")
+            .unwrap_err();
+        assert!(matches!(err, LinkerError::Malformed { .. }));
+        assert_eq!(
+            err.to_string(),
+            "garbage.o: malformed ELF file: invalid ELF header"
+        );
+        assert!(std::error::Error::source(&err).is_none());
+    }
+
+    #[test]
+    fn test_move_symbol_repatches_callers() {
+        // _start calls target directly and through a pointer; helper calls it too
+        let mut first = ObjectBuilder::new();
+        let text = first.text(&[0x9400_0000, 0x1400_0000]); // bl target; b .
+        first.global_func("_start", text, 0, 8);
+        let target = first.undefined("target");
+        first.rela(text, 0, target, R_AARCH64_CALL26, 0);
+        let data = first.data(".data", &[0; 8]);
+        first.rela(data, 0, target, R_AARCH64_ABS64, 4);
+        let mut second = ObjectBuilder::new();
+        let text = second.text(&[0xd503201f, 0x1400_0000]); // nop; b target
+        second.global_func("helper", text, 0, 8);
+        let target = second.undefined("target");
+        second.rela(text, 4, target, R_AARCH64_JUMP26, 0);
+        let mut third = ObjectBuilder::new();
+        let text = third.text(&[0xd65f03c0]); // ret
+        third.global_func("target", text, 0, 4);
+        let objects = [first.build(), second.build(), third.build()];
+
+        let mut linker = LinkerContext::default();
+        linker.keep_symbols(true);
+        link(&mut linker, &objects);
+        linker.finalize().unwrap();
+        let moved = linker.global_symbols["target"].final_addr + 0x10_0000;
+        linker.move_symbol("target", moved).unwrap();
+        assert_eq!(linker.global_symbols["target"].final_addr, moved);
+
+        let image = linker.link_to_bytes().unwrap();
+        let word = |addr: u64| {
+            let offset = linker.vaddr_to_offset(addr).unwrap() as usize;
+            u32::from_le_bytes(image[offset..offset + 4].try_into().unwrap())
+        };
+        let start = linker.global_symbols["_start"].final_addr;
+        let helper = linker.global_symbols["helper"].final_addr + 4;
+        assert_eq!(
+            word(start),
+            0x9400_0000 | branch26_imm(moved, 0, start).unwrap()
+        );
+        assert_eq!(
+            word(helper),
+            0x1400_0000 | branch26_imm(moved, 0, helper).unwrap()
+        );
+        let pointer = &linker.output_sections[".data"].data[..8];
+        assert_eq!(u64::from_le_bytes(pointer.try_into().unwrap()), moved + 4);
+        let symbols = output_symbols(&image);
+        let (_, target) = symbols.iter().find(|(name, _)| name == "target").unwrap();
+        assert_eq!(target.value, moved);
+        assert_eq!(target.shndx, SHN_ABS);
+
+        // Out of reach of the branches: the pointer could be patched, but isn't
+        let err = linker.move_symbol("target", start + (1 << 28)).unwrap_err();
+        assert!(matches!(err, LinkerError::RelocationOutOfRange { .. }));
+        assert_eq!(linker.global_symbols["target"].final_addr, moved);
+        assert_eq!(linker.global_symbols["target"].section, None);
+        assert_eq!(linker.link_to_bytes().unwrap(), image);
+        let err = linker.move_symbol("missing", 0).unwrap_err();
+        assert_eq!(err.to_string(), "`missing` is not a defined global symbol");
+    }
+
+    #[test]
+    fn test_gnu_stack_permissions() {
+        let stack_flags = |objects: &[Vec<u8>]| {
+            let mut linker = LinkerContext::default();
+            link(&mut linker, objects);
+            linker.finalize().unwrap();
+            let image = linker.link_to_bytes().unwrap();
+            let stack = program_headers(&image)
+                .into_iter()
+                .find(|h| h.p_type == PT_GNU_STACK)
+                .unwrap();
+            (stack.flags, linker.warnings().to_vec())
+        };
+
+        // Every input has a non-executable note
+        let (flags, warnings) = stack_flags(&[start_object()]);
+        assert_eq!(flags, PF_R | PF_W);
+        assert!(warnings.is_empty());
+
+        let mut old = ObjectBuilder::new();
+        old.data(".data", &[1; 4]);
+        old.gnu_stack = None;
+        let (flags, warnings) = stack_flags(&[start_object(), old.build()]);
+        assert_eq!(flags, PF_R | PF_W | PF_X);
+        assert_eq!(
+            warnings,
+            ["1.o: has no .note.GNU-stack section, which makes the stack executable"]
+        );
+
+        let mut exec = ObjectBuilder::new();
+        exec.data(".data", &[1; 4]);
+        exec.gnu_stack = Some(SHF_EXECINSTR);
+        let (flags, warnings) = stack_flags(&[start_object(), exec.build()]);
+        assert_eq!(flags, PF_R | PF_W | PF_X);
+        assert!(warnings[0].contains("marks .note.GNU-stack executable"));
+    }
+
+    #[test]
+    fn test_orphan_sections_placed_by_kind() {
+        let mut obj = ObjectBuilder::new();
+        let text = obj.text(&[0x14000000]); // b .
+        obj.global_func("_start", text, 0, 4);
+        obj.section(".rodata", SHT_PROGBITS, SHF_ALLOC, 8, &[1; 8]);
+        obj.data(".data", &[2; 8]);
+        obj.nobits(".bss", 8, 8);
+        // Orphans, added after everything they belong next to
+        obj.section(
+            ".text.custom",
+            SHT_PROGBITS,
+            SHF_ALLOC | SHF_EXECINSTR,
+            4,
+            &0xd65f03c0u32.to_le_bytes(), // ret
+        );
+        obj.section(".rodata.custom", SHT_PROGBITS, SHF_ALLOC, 8, &[3; 8]);
+        obj.data(".data.custom", &[4; 8]);
+        obj.nobits(".bss.custom", 8, 8);
+        let objects = [obj.build()];
+
+        let mut linker = LinkerContext::default();
+        link(&mut linker, &objects);
+        linker.finalize().unwrap();
+        let image = linker.link_to_bytes().unwrap();
+        let mut by_addr: Vec<_> = linker.output_sections.values().collect();
+        by_addr.sort_by_key(|s| s.header.addr);
+        let order: Vec<&str> = by_addr.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(
+            order,
+            [
+                ".text",
+                ".text.custom",
+                ".rodata",
+                ".rodata.custom",
+                ".data",
+                ".data.custom",
+                ".bss",
+                ".bss.custom"
+            ]
+        );
+        // The orphan code is loaded with the rest of the code
+        let custom = linker.output_sections[".text.custom"].header.addr;
+        assert!(program_headers(&image).iter().any(|h| h.p_type == PT_LOAD
+            && h.flags & PF_X != 0
+            && (h.vaddr..h.vaddr + h.memsz).contains(&custom)));
+    }
 }
@@ -1,39 +1,256 @@
-use std::{env, fs};
+use std::{
+    env,
+    error::Error,
+    fs, io,
+    path::{Path, PathBuf},
+    process,
+};
 
-use elkr::linker::LinkerContext;
+use elkr::{
+    archive::{is_archive, read_archive_members},
+    diagnostics::WarningPolicy,
+    error::LinkerError,
+    linker::LinkerContext,
+};
+
+fn usage(program: &str) -> String {
+    format!(
+        "Usage: {program} [-h|--help] [--no-warnings|--fatal-warnings] [--trace-symbol <name>]... [--require-defined <name>]... [-u|--undefined <name>]... [-L <dir>]... (-o <output_file> | <output_file>) <file1.o|lib.a|-l<name>> [file2.o ...]\n\
+         -l<name> links the members of lib<name>.a the link needs, searched for in the -L directories in order.\n\
+         --no-warnings drops warnings; --fatal-warnings makes any warning fail the link.\n\
+         Any argument of the form @file is replaced by the arguments listed in file."
+    )
+}
+
+/// Reports a command line mistake the way most tools do: usage on stderr, exit status 2.
+fn usage_error(program: &str, message: &str) -> ! {
+    eprintln!("error: {message}");
+    eprintln!("{}", usage(program));
+    process::exit(2);
+}
+
+/// Reports a failed link step, with the I/O error underneath it if there is one, and exits.
+fn link_error(err: &LinkerError) -> ! {
+    match err.source() {
+        Some(source) => eprintln!("error: {err}: {source}"),
+        None => eprintln!("error: {err}"),
+    }
+    process::exit(1);
+}
+
+/// Replaces every `@file` argument with the whitespace-separated arguments listed in
+/// `file`, recursively, for command lines too long for the OS.
+fn expand_response_files(
+    args: impl IntoIterator<Item = String>,
+    expanded: &mut Vec<String>,
+    open_files: &mut Vec<String>,
+) -> io::Result<()> {
+    for arg in args {
+        let Some(path) = arg.strip_prefix('@') else {
+            expanded.push(arg);
+            continue;
+        };
+        if open_files.iter().any(|open| open == path) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{path}: response file includes itself"),
+            ));
+        }
+        let contents = fs::read_to_string(path)
+            .map_err(|err| io::Error::new(err.kind(), format!("{path}: {err}")))?;
+        open_files.push(path.to_string());
+        expand_response_files(
+            contents.split_whitespace().map(str::to_string),
+            expanded,
+            open_files,
+        )?;
+        open_files.pop();
+    }
+    Ok(())
+}
+
+/// Removes every `<option> <name>` pair from `args`; such options may be given anywhere,
+/// any number of times.
+fn take_symbol_option(program: &str, args: &mut Vec<String>, option: &str) -> Vec<String> {
+    let mut names = Vec::new();
+    while let Some(i) = args.iter().position(|arg| arg == option) {
+        if i + 1 >= args.len() {
+            usage_error(program, &format!("{option} requires a symbol name"));
+        }
+        names.push(args.remove(i + 1));
+        args.remove(i);
+    }
+    names
+}
+
+/// An input named on the command line.
+enum Input {
+    File(String),
+    /// `-l<name>`, resolved against the `-L` directories once they are all known
+    Library(String),
+}
+
+/// The value of `option` if `arg` is that option, either joined (`-Ldir`) or as the
+/// next argument (`-L dir`).
+fn option_value<'a>(
+    program: &str,
+    arg: &'a str,
+    option: &str,
+    rest: &mut impl Iterator<Item = &'a String>,
+) -> Option<&'a str> {
+    if arg == option {
+        match rest.next() {
+            Some(value) => Some(value),
+            None => usage_error(program, &format!("{option} requires an argument")),
+        }
+    } else {
+        arg.strip_prefix(option).filter(|value| !value.is_empty())
+    }
+}
+
+/// Finds `lib<name>.a` in the first search directory that has it.
+fn find_library(name: &str, search_dirs: &[&str]) -> Option<PathBuf> {
+    search_dirs
+        .iter()
+        .map(|dir| Path::new(dir).join(format!("lib{name}.a")))
+        .find(|path| path.is_file())
+}
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
-    if args.len() < 4 {
-        eprintln!("Usage: {} <output_file> <file1.o> <file2.o> ...", args[0]);
-        panic!("Not enough arguments provided");
+    let mut raw_args = env::args();
+    let program = raw_args.next().unwrap_or_else(|| "elkr".to_string());
+    let mut args = vec![program.clone()];
+    if let Err(err) = expand_response_files(raw_args, &mut args, &mut Vec::new()) {
+        eprintln!("error: {err}");
+        process::exit(1);
     }
-    let output_path = &args[1];
-    let contents: Vec<_> = args[2..]
+    if args
         .iter()
-        .map(|path| fs::read(path).unwrap())
+        .skip(1)
+        .any(|arg| arg == "-h" || arg == "--help")
+    {
+        println!("{}", usage(&program));
+        return;
+    }
+    let traced_symbols = take_symbol_option(&program, &mut args, "--trace-symbol");
+    let required_symbols = take_symbol_option(&program, &mut args, "--require-defined");
+    let mut undefined_symbols = take_symbol_option(&program, &mut args, "--undefined");
+    undefined_symbols.extend(take_symbol_option(&program, &mut args, "-u"));
+
+    let mut output_path = None;
+    let mut warning_policy = WarningPolicy::Warn;
+    let mut search_dirs = Vec::new();
+    let mut input_args = Vec::new();
+    let mut rest = args[1..].iter();
+    while let Some(arg) = rest.next() {
+        if arg == "--no-warnings" {
+            warning_policy = WarningPolicy::Silent;
+        } else if arg == "--fatal-warnings" {
+            warning_policy = WarningPolicy::Fatal;
+        } else if let Some(path) = option_value(&program, arg, "-o", &mut rest) {
+            output_path = Some(path);
+        } else if let Some(dir) = option_value(&program, arg, "-L", &mut rest) {
+            search_dirs.push(dir);
+        } else if let Some(name) = option_value(&program, arg, "-l", &mut rest) {
+            input_args.push(Input::Library(name.to_string()));
+        } else {
+            input_args.push(Input::File(arg.clone()));
+        }
+    }
+    // Without -o, the first file named is the output
+    let output_path = match (output_path, input_args.first()) {
+        (Some(path), _) => path.to_string(),
+        (None, Some(Input::File(path))) => {
+            let path = path.clone();
+            input_args.remove(0);
+            path
+        }
+        _ => usage_error(&program, "expected an output file"),
+    };
+    if input_args.is_empty() {
+        usage_error(&program, "expected at least one input file");
+    }
+    let paths: Vec<String> = input_args
+        .into_iter()
+        .map(|input| match input {
+            Input::File(path) => path,
+            Input::Library(name) => find_library(&name, &search_dirs)
+                .unwrap_or_else(|| {
+                    eprintln!("error: cannot find -l{name}");
+                    process::exit(1);
+                })
+                .display()
+                .to_string(),
+        })
         .collect();
+    let output_path = &output_path;
+
+    // Archives, regular or thin, only contribute the members the link needs
+    let mut inputs: Vec<(String, Vec<u8>)> = Vec::new();
+    let mut archives: Vec<Vec<(String, Vec<u8>)>> = Vec::new();
+    for path in &paths {
+        let content = fs::read(path).unwrap_or_else(|err| {
+            eprintln!("error: {path}: {err}");
+            process::exit(1);
+        });
+        if is_archive(&content) {
+            match read_archive_members(Path::new(path), &content) {
+                Ok(members) => archives.push(members),
+                Err(err) => link_error(&err),
+            }
+        } else {
+            inputs.push((path.clone(), content));
+        }
+    }
 
     let mut linker = LinkerContext::default();
+    linker.warning_policy(warning_policy);
+    for name in &traced_symbols {
+        linker.trace_symbol(name);
+    }
+    for name in &required_symbols {
+        linker.require_defined(name);
+    }
+    for name in &undefined_symbols {
+        linker.keep_undefined(name);
+    }
 
     println!("--- 0. Loading input files ---");
-    for (i, path) in args[2..].iter().enumerate() {
-        linker.add_file(path.clone(), &contents[i]);
+    for (name, content) in &inputs {
+        if let Err(err) = linker.add_file(name.clone(), content) {
+            link_error(&err);
+        }
+    }
+    for members in &archives {
+        if let Err(err) = linker.add_archive(members) {
+            link_error(&err);
+        }
     }
 
     println!("--- 1. Laying out and merging sections ---");
-    linker.layout_and_merge_sections();
+    if let Err(err) = linker.layout_and_merge_sections() {
+        link_error(&err);
+    }
 
     println!("--- 2. Resolving symbols ---");
-    linker.resolve_symbols();
+    if let Err(err) = linker.resolve_symbols() {
+        link_error(&err);
+    }
 
     println!("--- 3. Applying relocations ---");
-    linker.apply_relocations();
+    if let Err(err) = linker.apply_relocations() {
+        link_error(&err);
+    }
+
+    println!("--- 4. Finalizing the layout ---");
+    if let Err(err) = linker.finalize() {
+        link_error(&err);
+    }
 
-    println!("--- 4. Writing executable file to '{}' ---", output_path);
-    linker
-        .write_executable(output_path)
-        .expect("Failed to write executable");
+    println!("--- 5. Writing executable file to '{}' ---", output_path);
+    if let Err(err) = linker.write_executable(output_path) {
+        link_error(&err);
+    }
 
     println!("--- Linking finished successfully! ---");
 }
@@ -0,0 +1,130 @@
+//! A machine-readable link map, written as JSON for other tools to consume.
+//!
+//! The schema is stable: an object with `sections`, `symbols` and `segments` arrays.
+//! Addresses, offsets, sizes and flags are plain JSON numbers.
+
+use std::io::{self, Write};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MapSection {
+    pub name: String,
+    pub vaddr: u64,
+    pub offset: u64,
+    pub size: u64,
+    /// `sh_flags` of the output section
+    pub flags: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MapSymbol {
+    pub name: String,
+    pub addr: u64,
+    /// Output section the symbol is defined in; `None` for absolute symbols
+    pub section: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MapSegment {
+    /// `p_type` as readelf names it, e.g. `LOAD`
+    pub segment_type: String,
+    /// `p_flags` of the segment
+    pub flags: u32,
+    pub vaddr: u64,
+    pub offset: u64,
+    pub filesz: u64,
+    pub memsz: u64,
+}
+
+/// Where everything ended up in a link: see [`crate::linker::LinkerContext::link_map`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LinkMap {
+    /// Output sections, by address
+    pub sections: Vec<MapSection>,
+    /// Resolved global symbols, by name
+    pub symbols: Vec<MapSymbol>,
+    /// Program headers, in the order they are written
+    pub segments: Vec<MapSegment>,
+}
+
+impl LinkMap {
+    /// Writes the map as a JSON document, one array entry per line.
+    pub fn write_json(&self, mut writer: impl Write) -> io::Result<()> {
+        writeln!(writer, "{{")?;
+        write_array(&mut writer, "sections", &self.sections, |s| {
+            format!(
+                "{{\"name\": {}, \"vaddr\": {}, \"offset\": {}, \"size\": {}, \"flags\": {}}}",
+                json_string(&s.name),
+                s.vaddr,
+                s.offset,
+                s.size,
+                s.flags
+            )
+        })?;
+        writeln!(writer, ",")?;
+        write_array(&mut writer, "symbols", &self.symbols, |s| {
+            let section = s.section.as_deref().map_or("null".to_string(), json_string);
+            format!(
+                "{{\"name\": {}, \"addr\": {}, \"section\": {section}}}",
+                json_string(&s.name),
+                s.addr
+            )
+        })?;
+        writeln!(writer, ",")?;
+        write_array(&mut writer, "segments", &self.segments, |s| {
+            format!(
+                "{{\"type\": {}, \"flags\": {}, \"vaddr\": {}, \"offset\": {}, \"filesz\": {}, \"memsz\": {}}}",
+                json_string(&s.segment_type),
+                s.flags,
+                s.vaddr,
+                s.offset,
+                s.filesz,
+                s.memsz
+            )
+        })?;
+        writeln!(writer, "\n}}")
+    }
+}
+
+fn write_array<T>(
+    writer: &mut impl Write,
+    key: &str,
+    items: &[T],
+    entry: impl Fn(&T) -> String,
+) -> io::Result<()> {
+    write!(writer, "  \"{key}\": [")?;
+    for (i, item) in items.iter().enumerate() {
+        let separator = if i == 0 { "" } else { "," };
+        write!(writer, "{separator}\n    {}", entry(item))?;
+    }
+    if !items.is_empty() {
+        write!(writer, "\n  ")?;
+    }
+    write!(writer, "]")
+}
+
+/// `s` as a quoted JSON string literal.
+fn json_string(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_json_string_escapes() {
+        assert_eq!(json_string("a\"b\\c\n\u{1}"), r#""a\"b\\c\n\u0001""#);
+    }
+}
@@ -0,0 +1,475 @@
+//! Helpers for building small relocatable ELF objects in unit tests.
+
+#![allow(dead_code)]
+
+use crate::{
+    elf::{
+        header::{EI_CLASS_64, EI_DATA_2LSB, EM_AARCH64, ET_REL, parse_elf_header},
+        section::{
+            SHF_ALLOC, SHF_EXECINSTR, SHF_INFO_LINK, SHF_WRITE, SHT_NOBITS, SHT_PROGBITS, SHT_REL,
+            SHT_RELA, SHT_STRTAB, SHT_SYMTAB, SectionHeader, get_section_name,
+            parse_section_header_table, section_name_table_index,
+        },
+        symbol::{
+            STB_GLOBAL, STB_LOCAL, STT_FUNC, STT_NOTYPE, STT_OBJECT, STT_SECTION, Symbol,
+            get_symbol_name, parse_symbol_table,
+        },
+    },
+    linker::{LinkerContext, ProgramHeader},
+};
+
+pub struct BuilderSection {
+    pub name: String,
+    pub sh_type: u32,
+    pub flags: u64,
+    pub addr: u64,
+    pub addralign: u64,
+    pub entsize: u64,
+    pub link: u32,
+    pub info: u32,
+    pub data: Vec<u8>,
+    /// Only used for `SHT_NOBITS`, where there is no data to take the size from.
+    pub size: u64,
+    /// Written as `sh_offset` instead of where the contents are placed, e.g. to point
+    /// the header out of bounds.
+    pub offset: Option<u64>,
+    relocations: Vec<(u64, u32, u32, i64)>,
+}
+
+/// An edit of a generated section, see [`ObjectBuilder::patch_section`].
+type SectionPatch = Box<dyn Fn(&mut BuilderSection)>;
+
+struct BuilderSymbol {
+    name: String,
+    info: u8,
+    shndx: u16,
+    value: u64,
+    size: u64,
+}
+
+/// Builds an AArch64 `ET_REL` object: user sections first (index 1..), then one
+/// `.rela.*` per section with relocations, then `.symtab`, `.strtab`, `.note.GNU-stack`
+/// and `.shstrtab`. User sections are edited through [`ObjectBuilder::section_mut`], the
+/// generated ones through [`ObjectBuilder::patch_section`].
+pub struct ObjectBuilder {
+    pub e_type: u16,
+    pub e_machine: u16,
+    pub sections: Vec<BuilderSection>,
+    symbols: Vec<BuilderSymbol>,
+    /// Emit `SHT_REL` sections, leaving the addends to the section data, instead of `SHT_RELA`.
+    pub rel: bool,
+    /// Flags of the `.note.GNU-stack` section after `.strtab`, as compilers emit it;
+    /// `None` leaves the note out.
+    pub gnu_stack: Option<u64>,
+    patches: Vec<(String, SectionPatch)>,
+}
+
+impl Default for ObjectBuilder {
+    fn default() -> Self {
+        Self {
+            e_type: ET_REL,
+            e_machine: EM_AARCH64,
+            sections: Vec::new(),
+            symbols: vec![BuilderSymbol {
+                name: String::new(),
+                info: 0,
+                shndx: 0,
+                value: 0,
+                size: 0,
+            }],
+            rel: false,
+            gnu_stack: Some(0),
+            patches: Vec::new(),
+        }
+    }
+}
+
+impl ObjectBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a section and returns its section header index.
+    pub fn section(
+        &mut self,
+        name: &str,
+        sh_type: u32,
+        flags: u64,
+        align: u64,
+        data: &[u8],
+    ) -> u16 {
+        self.sections.push(BuilderSection {
+            name: name.to_string(),
+            sh_type,
+            flags,
+            addr: 0,
+            addralign: align,
+            entsize: 0,
+            link: 0,
+            info: 0,
+            data: data.to_vec(),
+            size: data.len() as u64,
+            offset: None,
+            relocations: Vec::new(),
+        });
+        self.sections.len() as u16
+    }
+
+    pub fn text(&mut self, code: &[u32]) -> u16 {
+        let data: Vec<u8> = code.iter().flat_map(|insn| insn.to_le_bytes()).collect();
+        self.section(".text", SHT_PROGBITS, SHF_ALLOC | SHF_EXECINSTR, 4, &data)
+    }
+
+    pub fn data(&mut self, name: &str, data: &[u8]) -> u16 {
+        self.section(name, SHT_PROGBITS, SHF_ALLOC | SHF_WRITE, 8, data)
+    }
+
+    pub fn nobits(&mut self, name: &str, align: u64, size: u64) -> u16 {
+        let index = self.section(name, SHT_NOBITS, SHF_ALLOC | SHF_WRITE, align, &[]);
+        self.sections[index as usize - 1].size = size;
+        index
+    }
+
+    pub fn section_mut(&mut self, index: u16) -> &mut BuilderSection {
+        &mut self.sections[index as usize - 1]
+    }
+
+    /// Edits every section named `name` once `build` has generated it, e.g. `.symtab` or
+    /// a `.rela.*`, to make a malformed object.
+    pub fn patch_section(&mut self, name: &str, patch: impl Fn(&mut BuilderSection) + 'static) {
+        self.patches.push((name.to_string(), Box::new(patch)));
+    }
+
+    /// Adds a symbol and returns its symbol table index.
+    pub fn symbol(
+        &mut self,
+        name: &str,
+        bind: u8,
+        sym_type: u8,
+        shndx: u16,
+        value: u64,
+        size: u64,
+    ) -> u32 {
+        self.symbols.push(BuilderSymbol {
+            name: name.to_string(),
+            info: (bind << 4) | sym_type,
+            shndx,
+            value,
+            size,
+        });
+        (self.symbols.len() - 1) as u32
+    }
+
+    pub fn global_func(&mut self, name: &str, shndx: u16, value: u64, size: u64) -> u32 {
+        self.symbol(name, STB_GLOBAL, STT_FUNC, shndx, value, size)
+    }
+
+    pub fn global_object(&mut self, name: &str, shndx: u16, value: u64, size: u64) -> u32 {
+        self.symbol(name, STB_GLOBAL, STT_OBJECT, shndx, value, size)
+    }
+
+    pub fn undefined(&mut self, name: &str) -> u32 {
+        self.symbol(name, STB_GLOBAL, STT_NOTYPE, 0, 0, 0)
+    }
+
+    pub fn section_symbol(&mut self, shndx: u16) -> u32 {
+        self.symbol("", STB_LOCAL, STT_SECTION, shndx, 0, 0)
+    }
+
+    pub fn rela(&mut self, section: u16, offset: u64, symbol: u32, r_type: u32, addend: i64) {
+        self.sections[section as usize - 1]
+            .relocations
+            .push((offset, symbol, r_type, addend));
+    }
+
+    pub fn build(&self) -> Vec<u8> {
+        let mut sections: Vec<BuilderSection> = self
+            .sections
+            .iter()
+            .map(|s| BuilderSection {
+                relocations: Vec::new(),
+                name: s.name.clone(),
+                data: s.data.clone(),
+                ..*s
+            })
+            .collect();
+        let rela_count = self
+            .sections
+            .iter()
+            .filter(|s| !s.relocations.is_empty())
+            .count();
+        let symtab_index = (self.sections.len() + rela_count + 1) as u32;
+
+        for (i, section) in self.sections.iter().enumerate() {
+            if section.relocations.is_empty() {
+                continue;
+            }
+            let mut data = Vec::new();
+            for &(offset, symbol, r_type, addend) in &section.relocations {
+                data.extend_from_slice(&offset.to_le_bytes());
+                data.extend_from_slice(&(((symbol as u64) << 32) | r_type as u64).to_le_bytes());
+                if !self.rel {
+                    data.extend_from_slice(&addend.to_le_bytes());
+                }
+            }
+            let (prefix, sh_type, entsize) = if self.rel {
+                (".rel", SHT_REL, 16)
+            } else {
+                (".rela", SHT_RELA, 24)
+            };
+            sections.push(BuilderSection {
+                name: format!("{prefix}{}", section.name),
+                sh_type,
+                flags: SHF_INFO_LINK,
+                addr: 0,
+                addralign: 8,
+                entsize,
+                link: symtab_index,
+                info: i as u32 + 1,
+                size: data.len() as u64,
+                data,
+                offset: None,
+                relocations: Vec::new(),
+            });
+        }
+
+        let mut strtab = vec![0u8];
+        let mut symtab = Vec::new();
+        for symbol in &self.symbols {
+            let name_offset = if symbol.name.is_empty() {
+                0
+            } else {
+                let offset = strtab.len() as u32;
+                strtab.extend_from_slice(symbol.name.as_bytes());
+                strtab.push(0);
+                offset
+            };
+            symtab.extend_from_slice(&name_offset.to_le_bytes());
+            symtab.push(symbol.info);
+            symtab.push(0);
+            symtab.extend_from_slice(&symbol.shndx.to_le_bytes());
+            symtab.extend_from_slice(&symbol.value.to_le_bytes());
+            symtab.extend_from_slice(&symbol.size.to_le_bytes());
+        }
+        let first_global = self
+            .symbols
+            .iter()
+            .position(|s| s.info >> 4 != STB_LOCAL)
+            .unwrap_or(self.symbols.len());
+        let table = |name: &str, sh_type, link, info, entsize, data: Vec<u8>| BuilderSection {
+            name: name.to_string(),
+            sh_type,
+            flags: 0,
+            addr: 0,
+            addralign: if entsize > 0 { 8 } else { 1 },
+            entsize,
+            link,
+            info,
+            size: data.len() as u64,
+            data,
+            offset: None,
+            relocations: Vec::new(),
+        };
+        sections.push(table(
+            ".symtab",
+            SHT_SYMTAB,
+            symtab_index + 1,
+            first_global as u32,
+            24,
+            symtab,
+        ));
+        sections.push(table(".strtab", SHT_STRTAB, 0, 0, 0, strtab));
+        if let Some(flags) = self.gnu_stack {
+            sections.push(BuilderSection {
+                flags,
+                ..table(".note.GNU-stack", SHT_PROGBITS, 0, 0, 0, Vec::new())
+            });
+        }
+
+        let mut shstrtab = vec![0u8];
+        let mut name_offsets = Vec::new();
+        for section in sections
+            .iter()
+            .map(|s| s.name.as_str())
+            .chain([".shstrtab"])
+        {
+            name_offsets.push(shstrtab.len() as u32);
+            shstrtab.extend_from_slice(section.as_bytes());
+            shstrtab.push(0);
+        }
+        sections.push(table(".shstrtab", SHT_STRTAB, 0, 0, 0, shstrtab));
+        for (name, patch) in &self.patches {
+            sections
+                .iter_mut()
+                .filter(|s| &s.name == name)
+                .for_each(patch);
+        }
+
+        // Lay out the section contents right after the ELF header.
+        let mut out = vec![0u8; 64];
+        let mut offsets = Vec::new();
+        for section in &sections {
+            // Capped, so that objects can declare alignments far beyond their size
+            let align = section.addralign.clamp(1, 0x1000) as usize;
+            out.resize(out.len().div_ceil(align) * align, 0);
+            offsets.push(section.offset.unwrap_or(out.len() as u64));
+            if section.sh_type != SHT_NOBITS {
+                out.extend_from_slice(&section.data);
+            }
+        }
+        out.resize(out.len().div_ceil(8) * 8, 0);
+        let shoff = out.len() as u64;
+
+        out.extend_from_slice(&[0u8; 64]);
+        for (i, section) in sections.iter().enumerate() {
+            out.extend_from_slice(&name_offsets[i].to_le_bytes());
+            out.extend_from_slice(&section.sh_type.to_le_bytes());
+            out.extend_from_slice(&section.flags.to_le_bytes());
+            out.extend_from_slice(&section.addr.to_le_bytes());
+            out.extend_from_slice(&offsets[i].to_le_bytes());
+            out.extend_from_slice(&section.size.to_le_bytes());
+            out.extend_from_slice(&section.link.to_le_bytes());
+            out.extend_from_slice(&section.info.to_le_bytes());
+            out.extend_from_slice(&section.addralign.to_le_bytes());
+            out.extend_from_slice(&section.entsize.to_le_bytes());
+        }
+
+        let shnum = sections.len() as u16 + 1;
+        let mut header = vec![0x7f, b'E', b'L', b'F', EI_CLASS_64, EI_DATA_2LSB, 1, 0];
+        header.resize(16, 0);
+        header.extend_from_slice(&self.e_type.to_le_bytes());
+        header.extend_from_slice(&self.e_machine.to_le_bytes());
+        header.extend_from_slice(&1u32.to_le_bytes()); // e_version
+        header.extend_from_slice(&0u64.to_le_bytes()); // e_entry
+        header.extend_from_slice(&0u64.to_le_bytes()); // e_phoff
+        header.extend_from_slice(&shoff.to_le_bytes());
+        header.extend_from_slice(&0u32.to_le_bytes()); // e_flags
+        header.extend_from_slice(&64u16.to_le_bytes()); // e_ehsize
+        header.extend_from_slice(&0u16.to_le_bytes()); // e_phentsize
+        header.extend_from_slice(&0u16.to_le_bytes()); // e_phnum
+        header.extend_from_slice(&64u16.to_le_bytes()); // e_shentsize
+        header.extend_from_slice(&shnum.to_le_bytes());
+        header.extend_from_slice(&(shnum - 1).to_le_bytes()); // e_shstrndx
+        out[..64].copy_from_slice(&header);
+        out
+    }
+}
+
+/// An object with a `_start` that branches to itself.
+pub fn start_object() -> Vec<u8> {
+    let mut obj = ObjectBuilder::new();
+    let text = obj.text(&[0x14000000]); // b .
+    obj.global_func("_start", text, 0, 4);
+    obj.build()
+}
+
+/// Runs every link phase over `objects`, named `0.o`, `1.o`, ...
+pub fn link<'a>(linker: &mut LinkerContext<'a>, objects: &'a [Vec<u8>]) {
+    for (i, obj) in objects.iter().enumerate() {
+        linker.add_file(format!("{i}.o"), obj).unwrap();
+    }
+    linker.layout_and_merge_sections().unwrap();
+    linker.resolve_symbols().unwrap();
+    linker.apply_relocations().unwrap();
+}
+
+fn read_u16(image: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes(image[at..at + 2].try_into().unwrap())
+}
+
+fn read_u32(image: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(image[at..at + 4].try_into().unwrap())
+}
+
+fn read_u64(image: &[u8], at: usize) -> u64 {
+    u64::from_le_bytes(image[at..at + 8].try_into().unwrap())
+}
+
+/// Reads the program header table back out of a linked executable.
+pub fn program_headers(image: &[u8]) -> Vec<ProgramHeader> {
+    let phoff = read_u64(image, 0x20) as usize;
+    let phentsize = read_u16(image, 0x36) as usize;
+    let phnum = read_u16(image, 0x38) as usize;
+    (0..phnum)
+        .map(|i| {
+            let at = phoff + i * phentsize;
+            ProgramHeader {
+                p_type: read_u32(image, at),
+                flags: read_u32(image, at + 4),
+                offset: read_u64(image, at + 8),
+                vaddr: read_u64(image, at + 16),
+                paddr: read_u64(image, at + 24),
+                filesz: read_u64(image, at + 32),
+                memsz: read_u64(image, at + 40),
+                align: read_u64(image, at + 48),
+            }
+        })
+        .collect()
+}
+
+/// Reads the section header table back out of a linked executable, with each section's
+/// name.
+pub fn section_headers(image: &[u8]) -> Vec<(String, SectionHeader)> {
+    let (_, header) = parse_elf_header(image).unwrap();
+    let (_, sections) = parse_section_header_table(image, &header).unwrap();
+    let shstrtab = &sections[section_name_table_index(&header, &sections)];
+    let names = &image[shstrtab.offset as usize..(shstrtab.offset + shstrtab.size) as usize];
+    sections
+        .into_iter()
+        .map(|s| (get_section_name(names, &s).unwrap().to_string(), s))
+        .collect()
+}
+
+/// Reads `.symtab` back out of a linked executable, with each symbol's name.
+pub fn output_symbols(image: &[u8]) -> Vec<(String, Symbol)> {
+    let sections = section_headers(image);
+    let (_, symtab) = sections
+        .iter()
+        .find(|(_, s)| s.sh_type == SHT_SYMTAB)
+        .expect("no .symtab");
+    let (_, strtab) = &sections[symtab.link as usize];
+    let names = &image[strtab.offset as usize..(strtab.offset + strtab.size) as usize];
+    let (_, symbols) = parse_symbol_table(image, symtab).unwrap();
+    symbols
+        .into_iter()
+        .map(|s| (get_symbol_name(names, &s).unwrap().to_string(), s))
+        .collect()
+}
+
+/// Parses Intel HEX back into (first address, contiguous data, start address), checking
+/// every record's checksum. Gaps between records are filled with zeroes.
+pub fn decode_ihex(hex: &str) -> (u64, Vec<u8>, Option<u64>) {
+    let mut upper = 0u64;
+    let mut start = None;
+    let mut data = Vec::new();
+    let mut entry = None;
+    for line in hex.lines() {
+        let line = line.strip_prefix(':').expect("record starts with ':'");
+        let bytes: Vec<u8> = (0..line.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&line[i..i + 2], 16).unwrap())
+            .collect();
+        let sum = bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+        assert_eq!(sum, 0, "bad checksum in :{line}");
+        let len = bytes[0] as usize;
+        assert_eq!(bytes.len(), len + 5);
+        let offset = u16::from_be_bytes([bytes[1], bytes[2]]) as u64;
+        let payload = &bytes[4..4 + len];
+        match bytes[3] {
+            0x00 => {
+                let addr = upper + offset;
+                let base = *start.get_or_insert(addr);
+                let at = (addr - base) as usize;
+                if data.len() < at + len {
+                    data.resize(at + len, 0);
+                }
+                data[at..at + len].copy_from_slice(payload);
+            }
+            0x01 => break,
+            0x04 => upper = (u16::from_be_bytes([payload[0], payload[1]]) as u64) << 16,
+            0x05 => entry = Some(u32::from_be_bytes(payload.try_into().unwrap()) as u64),
+            other => panic!("unexpected record type {other}"),
+        }
+    }
+    (start.unwrap_or(0), data, entry)
+}
@@ -0,0 +1,242 @@
+use std::process::Command;
+
+fn elkr() -> Command {
+    Command::new(env!("CARGO_BIN_EXE_elkr"))
+}
+
+#[test]
+fn test_no_arguments_prints_usage() {
+    let output = elkr().output().unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Usage:"), "stderr: {stderr}");
+    assert!(!stderr.contains("panicked"), "stderr: {stderr}");
+}
+
+#[test]
+fn test_help_exits_successfully() {
+    for flag in ["-h", "--help"] {
+        let output = elkr().arg(flag).output().unwrap();
+        assert_eq!(output.status.code(), Some(0));
+        assert!(String::from_utf8_lossy(&output.stdout).contains("Usage:"));
+    }
+}
+
+#[test]
+fn test_missing_input_file_is_reported() {
+    let output = elkr()
+        .args(["/dev/null", "does-not-exist.o"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("does-not-exist.o"));
+}
+
+#[test]
+fn test_response_file_matches_direct_arguments() {
+    let dir = std::env::temp_dir().join(format!("elkr-rsp-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let object = concat!(env!("CARGO_MANIFEST_DIR"), "/materials/hello.o");
+    // Response files may list arguments on one line or several, and nest
+    let inner = dir.join("inner.rsp");
+    std::fs::write(&inner, format!("{object}\n")).unwrap();
+    let outer = dir.join("outer.rsp");
+    std::fs::write(&outer, format!("{object}   @{}\n", inner.display())).unwrap();
+
+    let direct = dir.join("direct");
+    let via_file = dir.join("via-file");
+    let status = elkr()
+        .arg(&direct)
+        .args([object, object])
+        .output()
+        .unwrap()
+        .status;
+    assert!(status.success());
+    let status = elkr()
+        .arg(&via_file)
+        .arg(format!("@{}", outer.display()))
+        .output()
+        .unwrap()
+        .status;
+    assert!(status.success());
+
+    let same = std::fs::read(&direct).unwrap() == std::fs::read(&via_file).unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert!(same);
+}
+
+#[test]
+fn test_recursive_response_file_is_an_error() {
+    let path = std::env::temp_dir().join(format!("elkr-loop-{}.rsp", std::process::id()));
+    std::fs::write(&path, format!("@{}", path.display())).unwrap();
+    let output = elkr().arg(format!("@{}", path.display())).output().unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("includes itself"));
+}
+
+#[test]
+fn test_thin_archive_member_is_linked() {
+    let dir = std::env::temp_dir().join(format!("elkr-thin-cli-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let object = concat!(env!("CARGO_MANIFEST_DIR"), "/materials/hello.o");
+    std::fs::copy(object, dir.join("hello.o")).unwrap();
+    // A thin archive holding just a long name table pointing at ./hello.o
+    let size = std::fs::metadata(object).unwrap().len();
+    let header = |name: &str, size: u64| {
+        format!("{name:<16}{:<12}{:<6}{:<6}{:<8}{size:<10}`\n", 0, 0, 0, 644)
+    };
+    let mut archive = String::from("!<thin>\n");
+    archive += &header("//", 10);
+    archive += "hello.o/\n\n";
+    archive += &header("/0", size);
+    std::fs::write(dir.join("libhello.a"), archive).unwrap();
+
+    let direct = dir.join("direct");
+    let via_archive = dir.join("via-archive");
+    let status = elkr().arg(&direct).arg(object).output().unwrap().status;
+    assert!(status.success());
+    let output = elkr()
+        .arg(&via_archive)
+        .arg(dir.join("libhello.a"))
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    assert!(String::from_utf8_lossy(&output.stdout).contains("libhello.a(hello.o)"));
+    assert_eq!(
+        std::fs::read(&direct).unwrap(),
+        std::fs::read(&via_archive).unwrap()
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_warning_policy_flags_are_accepted() {
+    let materials = concat!(env!("CARGO_MANIFEST_DIR"), "/materials");
+    let output_path = std::env::temp_dir().join(format!("elkr-warn-{}", std::process::id()));
+    for flag in ["--no-warnings", "--fatal-warnings"] {
+        let output = elkr()
+            .arg(flag)
+            .arg("-o")
+            .arg(&output_path)
+            .args([format!("{materials}/main.o"), format!("{materials}/sum.o")])
+            .output()
+            .unwrap();
+        assert_eq!(output.status.code(), Some(0), "{flag}: {output:?}");
+        assert!(!String::from_utf8_lossy(&output.stderr).contains("warning"));
+    }
+    std::fs::remove_file(&output_path).unwrap();
+}
+
+#[test]
+fn test_require_defined_missing_symbol_fails() {
+    let object = concat!(env!("CARGO_MANIFEST_DIR"), "/materials/hello.o");
+    let output = elkr()
+        .args(["/dev/null", object, "--require-defined", "no_such_symbol"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("`no_such_symbol`"));
+}
+
+#[test]
+fn test_library_is_found_in_search_path() {
+    let dir = std::env::temp_dir().join(format!("elkr-lib-cli-{}", std::process::id()));
+    let lib_dir = dir.join("lib");
+    std::fs::create_dir_all(&lib_dir).unwrap();
+    let materials = concat!(env!("CARGO_MANIFEST_DIR"), "/materials");
+    let sum = std::fs::read(format!("{materials}/sum.o")).unwrap();
+    // A copy of sum.o defining `sub` instead, which nothing refers to
+    let sub_name = sum.windows(5).position(|w| w == b"\0sum\0").unwrap() + 1;
+    let mut sub = sum.clone();
+    sub[sub_name..sub_name + 3].copy_from_slice(b"sub");
+    let header = |name: &str, size: usize| {
+        format!("{name:<16}{:<12}{:<6}{:<6}{:<8}{size:<10}`\n", 0, 0, 0, 644)
+    };
+    let mut archive = b"!<arch>\n".to_vec();
+    for (name, member) in [("sub.o/", &sub), ("sum.o/", &sum)] {
+        archive.extend(header(name, member.len()).into_bytes());
+        archive.extend(member);
+        if member.len() % 2 == 1 {
+            archive.push(b'\n');
+        }
+    }
+    std::fs::write(lib_dir.join("libfoo.a"), archive).unwrap();
+
+    let direct = dir.join("direct");
+    let via_library = dir.join("via-library");
+    let objects = [
+        format!("{materials}/start.o"),
+        format!("{materials}/main.o"),
+    ];
+    let status = elkr()
+        .arg(&direct)
+        .args(&objects)
+        .arg(format!("{materials}/sum.o"))
+        .output()
+        .unwrap()
+        .status;
+    assert!(status.success());
+    let output = elkr()
+        .arg("-o")
+        .arg(&via_library)
+        .args(&objects)
+        .arg("-L")
+        .arg(dir.join("missing"))
+        .arg(format!("-L{}", lib_dir.display()))
+        .arg("-lfoo")
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    // Only the member defining the `sum` main.o calls is linked in
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("libfoo.a(sum.o)"), "stdout: {stdout}");
+    assert!(!stdout.contains("libfoo.a(sub.o)"), "stdout: {stdout}");
+    assert!(!stdout.contains("  sub -> "), "stdout: {stdout}");
+    assert_eq!(
+        std::fs::read(&direct).unwrap(),
+        std::fs::read(&via_library).unwrap()
+    );
+
+    // -u pulls in a member nothing refers to
+    let output = elkr()
+        .arg("-o")
+        .arg(&via_library)
+        .args(&objects)
+        .args(["-u", "sub"])
+        .arg(format!("-L{}", lib_dir.display()))
+        .arg("-lfoo")
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("libfoo.a(sub.o)"), "stdout: {stdout}");
+    assert!(stdout.contains("  sub -> "), "stdout: {stdout}");
+
+    let output = elkr()
+        .arg(format!("-o{}", via_library.display()))
+        .args(&objects)
+        .arg("-lnosuch")
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("cannot find -lnosuch"));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_unwritable_output_reports_the_cause() {
+    let object = concat!(env!("CARGO_MANIFEST_DIR"), "/materials/hello.o");
+    let output_path = std::env::temp_dir()
+        .join(format!("elkr-missing-{}", std::process::id()))
+        .join("out");
+    let output = elkr().arg(&output_path).arg(object).output().unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let context = format!("error: can't write {}: ", output_path.display());
+    assert!(stderr.contains(&context), "stderr: {stderr}");
+    assert!(
+        stderr.contains("No such file or directory"),
+        "stderr: {stderr}"
+    );
+}